@@ -0,0 +1,75 @@
+/// An Azure OpenAI deployment to send requests to instead of OpenAI, see
+/// [`crate::ConfigBuilder::azure`]. The request and response bodies are the same as
+/// OpenAI's, but the deployment is part of the URL and the API key is sent in the `api-key`
+/// header rather than as a bearer token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AzureConfig {
+    /// The resource endpoint, e.g. `https://my-resource.openai.azure.com`, without a
+    /// trailing slash.
+    endpoint: String,
+    deployment: String,
+    api_version: String,
+}
+
+impl AzureConfig {
+    /// Send requests to `deployment` of the resource at `endpoint`, using the REST API
+    /// version `api_version`, e.g. `2024-10-21`.
+    pub fn new(
+        endpoint: impl Into<String>,
+        deployment: impl Into<String>,
+        api_version: impl Into<String>,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.into().trim().trim_end_matches('/').to_string(),
+            deployment: deployment.into(),
+            api_version: api_version.into(),
+        }
+    }
+
+    pub(crate) fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub(crate) fn deployment(&self) -> &str {
+        &self.deployment
+    }
+
+    /// The URL of the chat completions endpoint of the deployment.
+    pub(crate) fn chat_completions_url(&self) -> String {
+        format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            self.endpoint, self.deployment, self.api_version
+        )
+    }
+
+    /// The URL of the Responses API of the resource. The deployment is sent as the model
+    /// instead of being part of the URL.
+    pub(crate) fn responses_url(&self) -> String {
+        format!(
+            "{}/openai/responses?api-version={}",
+            self.endpoint, self.api_version
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_completions_url() {
+        let azure = AzureConfig::new(
+            "https://my-resource.openai.azure.com/",
+            "gpt-4o",
+            "2024-10-21",
+        );
+        assert_eq!(
+            azure.chat_completions_url(),
+            "https://my-resource.openai.azure.com/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(
+            azure.responses_url(),
+            "https://my-resource.openai.azure.com/openai/responses?api-version=2024-10-21"
+        );
+    }
+}
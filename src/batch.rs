@@ -0,0 +1,69 @@
+use futures::StreamExt;
+
+use crate::{LlmClient, LlmError, Message};
+
+/// Query OpenAI for `T` once for each conversation in `inputs`, with at most `concurrency`
+/// requests in flight at once to stay within the rate limit. The results are in the same
+/// order as the inputs, and a failed query does not stop the others. A `concurrency` of 0
+/// is treated as 1.
+///
+/// Rate limited requests are still retried according to the [`crate::RetryPolicy`], so a
+/// low concurrency and a patient policy go well together for large batches.
+pub async fn query_openai_batch<T>(
+    inputs: Vec<Vec<Message>>,
+    concurrency: usize,
+) -> Vec<Result<T, LlmError>>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let client = LlmClient::default();
+    run_batch(inputs, concurrency, |messages| client.query(messages)).await
+}
+
+/// Run `query` on every input with at most `concurrency` at once, returning the results in
+/// input order.
+async fn run_batch<I, T, F, Fut>(inputs: Vec<I>, concurrency: usize, query: F) -> Vec<T>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let query = &query;
+    let mut results: Vec<(usize, T)> = futures::stream::iter(inputs.into_iter().enumerate())
+        .map(|(index, input)| async move { (index, query(input).await) })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_batch() {
+        let in_flight = std::sync::atomic::AtomicUsize::new(0);
+        let max_in_flight = std::sync::atomic::AtomicUsize::new(0);
+        let inputs: Vec<u64> = vec![30, 5, 20, 1, 10, 15];
+
+        let results = run_batch(inputs.clone(), 2, |delay| {
+            let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+            async move {
+                let now = in_flight.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(now, std::sync::atomic::Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                in_flight.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                delay * 2
+            }
+        })
+        .await;
+
+        // Later inputs finish first, but the results keep the input order.
+        assert_eq!(results, [60, 10, 40, 2, 20, 30]);
+        assert_eq!(max_in_flight.into_inner(), 2);
+
+        let sequential = run_batch(vec![1, 2, 3], 0, |n| async move { n }).await;
+        assert_eq!(sequential, [1, 2, 3]);
+    }
+}
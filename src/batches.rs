@@ -0,0 +1,414 @@
+use crate::{
+    ApiError, LlmClient, LlmError, Message, OpenAIChatCompletionQuery,
+    OpenAIChatCompletionResponse, parse_structured, prepare_query, retry, try_get_schema,
+    with_headers,
+};
+
+/// The endpoint every request of a batch is sent to.
+const BATCH_ENDPOINT: &str = "/v1/chat/completions";
+
+/// A job of OpenAI's Batch API, which answers a file of requests within 24 hours at half
+/// the price of sending them one by one. See [`query_openai_batch_job`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct Batch {
+    pub id: String,
+    /// One of `validating`, `in_progress`, `finalizing`, `completed`, `failed`, `expired`,
+    /// `cancelling` or `cancelled`.
+    pub status: String,
+    /// The file with the responses, once some are done.
+    #[serde(default)]
+    pub output_file_id: Option<String>,
+    /// The file with the requests that failed.
+    #[serde(default)]
+    pub error_file_id: Option<String>,
+    #[serde(default)]
+    pub request_counts: Option<BatchRequestCounts>,
+}
+
+/// How many requests of a [`Batch`] are done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub struct BatchRequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+impl Batch {
+    /// Whether the batch has stopped, successfully or not, so polling it again does not
+    /// change it.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status.as_str(),
+            "completed" | "failed" | "expired" | "cancelled"
+        )
+    }
+}
+
+/// Answer every conversation in `requests`, each with its own ID, for `T` through the Batch
+/// API: upload them, wait for the batch to finish, checking every `poll_interval`, and
+/// parse the results. For large offline jobs where waiting up to 24 hours is fine in return
+/// for half the price.
+///
+/// The results are paired with the IDs of their requests and in no particular order. A
+/// failed request does not fail the others. The requests are sent to the base URL of the
+/// configuration, Azure deployments are not supported.
+pub async fn query_openai_batch_job<T>(
+    requests: Vec<(String, Vec<Message>)>,
+    poll_interval: std::time::Duration,
+) -> Result<Vec<(String, Result<T, LlmError>)>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let client = LlmClient::default();
+    let batch = client.create_batch::<T>(requests).await?;
+    let batch = client.wait_for_batch(&batch.id, poll_interval).await?;
+    client.batch_results(&batch).await
+}
+
+impl LlmClient {
+    /// The batch input file for `requests`: one line of JSON with the chat completion request
+    /// of each conversation for `T`, as [`LlmClient::query`] would send it.
+    pub fn batch_file<T: schemars::JsonSchema + 'static>(
+        &self,
+        requests: Vec<(String, Vec<Message>)>,
+    ) -> Result<String, LlmError> {
+        let schema = try_get_schema::<T>()?;
+        let mut file = String::new();
+        for (custom_id, messages) in requests {
+            let mut query =
+                OpenAIChatCompletionQuery::new(self.config.model.clone(), messages, schema.clone());
+            prepare_query(self, &mut query)?;
+            let line = serde_json::json!({
+                "custom_id": custom_id,
+                "method": "POST",
+                "url": BATCH_ENDPOINT,
+                "body": query,
+            });
+            file.push_str(&line.to_string());
+            file.push('\n');
+        }
+        Ok(file)
+    }
+
+    /// Upload the [`LlmClient::batch_file`] of `requests` and start a batch for it.
+    pub async fn create_batch<T: schemars::JsonSchema + 'static>(
+        &self,
+        requests: Vec<(String, Vec<Message>)>,
+    ) -> Result<Batch, LlmError> {
+        let file = self.batch_file::<T>(requests)?;
+        let File { id } = self.upload_batch_file(&file).await?;
+        let body = serde_json::json!({
+            "input_file_id": id,
+            "endpoint": BATCH_ENDPOINT,
+            "completion_window": "24h",
+        });
+        let response = self
+            .send_with_retries(|| self.http.post(self.url("batches")).json(&body))
+            .await?;
+        parse_body(response).await
+    }
+
+    /// The current state of the batch with `id`.
+    pub async fn batch(&self, id: &str) -> Result<Batch, LlmError> {
+        let response = self
+            .send_with_retries(|| self.http.get(self.url(&format!("batches/{id}"))))
+            .await?;
+        parse_body(response).await
+    }
+
+    /// Check the batch with `id` every `poll_interval` until it is finished, see
+    /// [`Batch::is_finished`].
+    pub async fn wait_for_batch(
+        &self,
+        id: &str,
+        poll_interval: std::time::Duration,
+    ) -> Result<Batch, LlmError> {
+        loop {
+            let batch = self.batch(id).await?;
+            if batch.is_finished() {
+                return Ok(batch);
+            }
+            tracing::debug!(id, status = %batch.status, "Waiting for batch");
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Download the responses and errors of `batch` and parse each response into `T`,
+    /// paired with the ID of its request. Fails with [`LlmError::Batch`] if the batch has
+    /// neither, such as a batch that failed validation.
+    pub async fn batch_results<T>(
+        &self,
+        batch: &Batch,
+    ) -> Result<Vec<(String, Result<T, LlmError>)>, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a>,
+    {
+        let files: Vec<&str> = [&batch.output_file_id, &batch.error_file_id]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if files.is_empty() {
+            return Err(LlmError::Batch(format!(
+                "Batch {} is {} without results",
+                batch.id, batch.status
+            )));
+        }
+
+        let mut results = Vec::new();
+        for file in files {
+            let content = self
+                .send_with_retries(|| self.http.get(self.url(&format!("files/{file}/content"))))
+                .await?
+                .text()
+                .await?;
+            results.extend(parse_results(&content)?);
+        }
+        Ok(results)
+    }
+
+    async fn upload_batch_file(&self, file: &str) -> Result<File, LlmError> {
+        // reqwest's multipart support needs an extra feature, and this single file form is
+        // simple enough to write out.
+        let boundary = format!("batch-{:016x}", rand_boundary());
+        let body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"purpose\"\r\n\r\nbatch\r\n\
+                --{boundary}\r\nContent-Disposition: form-data; name=\"file\"; \
+                filename=\"batch.jsonl\"\r\nContent-Type: application/jsonl\r\n\r\n{file}\r\n\
+                --{boundary}--\r\n"
+        );
+        let response = self
+            .send_with_retries(|| {
+                self.http
+                    .post(self.url("files"))
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        format!("multipart/form-data; boundary={boundary}"),
+                    )
+                    .body(body.clone())
+            })
+            .await?;
+        parse_body(response).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.config.base_url)
+    }
+
+    /// Authenticate and send the request built by `request`, retrying according to the
+    /// retry policy of the client.
+    async fn send_with_retries(
+        &self,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, LlmError> {
+        let policy = self.current_retry_policy();
+        retry::with_retries(&policy, || async {
+            let mut builder = with_headers(request(), &self.config);
+            if let Some(timeout) = self.config.timeout {
+                builder = builder.timeout(timeout);
+            }
+            let response = builder.send().await?;
+
+            let status = response.status();
+            if !status.is_success() {
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| retry::parse_retry_after(value.to_str().ok()?));
+                let body = response.text().await?;
+                return Err(LlmError::ApiStatus {
+                    status: status.as_u16(),
+                    error: ApiError::parse(&body).map(Box::new),
+                    body,
+                    retry_after,
+                });
+            }
+            Ok(response)
+        })
+        .await
+    }
+}
+
+/// A random boundary for a multipart body, which must not occur in the file.
+fn rand_boundary() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
+
+/// An uploaded file.
+#[derive(Debug, serde::Deserialize)]
+struct File {
+    id: String,
+}
+
+async fn parse_body<T>(response: reqwest::Response) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    serde_json::from_str(&response.text().await?).map_err(LlmError::InvalidResponse)
+}
+
+/// A line of a batch output or error file.
+#[derive(Debug, serde::Deserialize)]
+struct ResultLine {
+    custom_id: String,
+    #[serde(default)]
+    response: Option<ResultResponse>,
+    #[serde(default)]
+    error: Option<ApiError>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ResultResponse {
+    status_code: u16,
+    body: serde_json::Value,
+}
+
+/// The structured output of each request of a batch, paired with its ID.
+type Results<T> = Vec<(String, Result<T, LlmError>)>;
+
+/// Parse every line of a batch output or error file into the structured output of its
+/// request, or why there is none.
+fn parse_results<T>(content: &str) -> Result<Results<T>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let line: ResultLine = serde_json::from_str(line).map_err(LlmError::InvalidResponse)?;
+            let result = match (line.response, line.error) {
+                (Some(response), _) if (200..300).contains(&response.status_code) => {
+                    serde_json::from_value::<OpenAIChatCompletionResponse>(response.body)
+                        .map_err(LlmError::InvalidResponse)
+                        .and_then(|response| parse_structured(response.content()?))
+                }
+                (Some(response), _) => {
+                    let body = response.body.to_string();
+                    Err(LlmError::ApiStatus {
+                        status: response.status_code,
+                        error: ApiError::parse(&body).map(Box::new),
+                        body,
+                        retry_after: None,
+                    })
+                }
+                (None, Some(error)) => Err(LlmError::Batch(error.message)),
+                (None, None) => Err(LlmError::Batch("Request has no response".to_string())),
+            };
+            Ok((line.custom_id, result))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Sentiment {
+        positive: bool,
+    }
+
+    #[test]
+    fn test_batch_file() {
+        let config = crate::Config::builder()
+            .model("gpt-4o-mini")
+            .system_prompt("Classify the review.")
+            .build()
+            .unwrap();
+        let client = LlmClient::with_http_client(reqwest::Client::new(), config);
+        let file = client
+            .batch_file::<Sentiment>(vec![
+                ("row-1".to_string(), vec![Message::user("Great!")]),
+                ("row-2".to_string(), vec![Message::user("Awful.")]),
+            ])
+            .unwrap();
+
+        let lines: Vec<serde_json::Value> = file
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["custom_id"], "row-1");
+        assert_eq!(lines[0]["method"], "POST");
+        assert_eq!(lines[0]["url"], "/v1/chat/completions");
+        let body = &lines[1]["body"];
+        assert_eq!(body["model"], "gpt-4o-mini");
+        assert_eq!(body["messages"][0]["content"], "Classify the review.");
+        assert_eq!(body["messages"][1]["content"], "Awful.");
+        assert_eq!(
+            body["response_format"]["json_schema"],
+            crate::canonical_openai_schema::<Sentiment>()
+        );
+    }
+
+    #[test]
+    fn test_parse_results() {
+        let completion = |content: &str| {
+            serde_json::json!({
+                "status_code": 200,
+                "request_id": "req_1",
+                "body": { "choices": [{ "message": { "content": content } }] },
+            })
+        };
+        let lines = [
+            serde_json::json!({ "id": "batch_req_1", "custom_id": "row-1", "response": completion(r#"{"positive": true}"#), "error": null }),
+            serde_json::json!({ "id": "batch_req_2", "custom_id": "row-2", "response": completion(r#"{"positive": "no"}"#), "error": null }),
+            serde_json::json!({
+                "id": "batch_req_3",
+                "custom_id": "row-3",
+                "response": {
+                    "status_code": 400,
+                    "body": { "error": { "message": "Bad request", "type": "invalid_request_error", "param": "messages", "code": null } },
+                },
+                "error": null,
+            }),
+            serde_json::json!({ "id": "batch_req_4", "custom_id": "row-4", "response": null, "error": { "code": "batch_expired", "message": "Expired" } }),
+        ];
+        let content: String = lines.iter().map(|line| format!("{line}\n")).collect();
+
+        let results = parse_results::<Sentiment>(&content).unwrap();
+        let ids: Vec<&str> = results.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["row-1", "row-2", "row-3", "row-4"]);
+        assert_eq!(
+            *results[0].1.as_ref().unwrap(),
+            Sentiment { positive: true }
+        );
+        assert!(matches!(results[1].1, Err(LlmError::ContentParse(_))));
+        assert!(matches!(
+            &results[2].1,
+            Err(LlmError::ApiStatus { status: 400, error: Some(error), .. })
+                if error.param.as_deref() == Some("messages")
+        ));
+        assert!(matches!(&results[3].1, Err(LlmError::Batch(reason)) if reason == "Expired"));
+
+        assert!(matches!(
+            parse_results::<Sentiment>("not json"),
+            Err(LlmError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn test_batch_status() {
+        let batch: Batch = serde_json::from_value(serde_json::json!({
+            "id": "batch_1",
+            "object": "batch",
+            "status": "in_progress",
+            "output_file_id": null,
+            "request_counts": { "total": 2, "completed": 1, "failed": 0 },
+        }))
+        .unwrap();
+        assert!(!batch.is_finished());
+        assert_eq!(batch.request_counts.unwrap().completed, 1);
+        assert!(
+            Batch {
+                status: "expired".to_string(),
+                ..batch
+            }
+            .is_finished()
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use futures::StreamExt;
+
+use crate::{
+    LlmClient, LlmError, Message, OpenAIChatCompletionQuery, Usage, parse_structured,
+    prepare_query, send_query, try_get_schema,
+};
+
+/// Number of benchmark requests in flight at once.
+const BENCHMARK_CONCURRENCY: usize = 4;
+
+/// How a model performed on a schema over a number of runs. Latencies and token counts only
+/// cover runs where the request succeeded, and are `None` if none did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkResult {
+    pub runs: usize,
+    /// Runs where the request itself failed, for example on a rate limit.
+    pub request_failures: usize,
+    /// Runs where the response did not parse into the schema type.
+    pub parse_failures: usize,
+    pub latency_p50: Option<std::time::Duration>,
+    pub latency_p90: Option<std::time::Duration>,
+    pub latency_p99: Option<std::time::Duration>,
+    pub mean_prompt_tokens: Option<f64>,
+    pub mean_completion_tokens: Option<f64>,
+}
+
+impl BenchmarkResult {
+    /// The share of runs that produced a parseable response, between 0 and 1.
+    pub fn parse_success_rate(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        let successes = self.runs - self.request_failures - self.parse_failures;
+        successes as f64 / self.runs as f64
+    }
+}
+
+/// Run the same extraction `runs` times against `model` and report latency percentiles,
+/// token usage and how often the response parsed into `T`, to pick a model for a schema
+/// empirically. Requests bypass the cache and are not retried, so every run measures one
+/// round trip.
+pub async fn benchmark<T>(
+    messages: Vec<Message>,
+    model: impl Into<String>,
+    runs: usize,
+) -> Result<BenchmarkResult, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let mut query = OpenAIChatCompletionQuery::new(model.into(), messages, try_get_schema::<T>()?);
+    let client = LlmClient::default();
+    prepare_query(&client, &mut query)?;
+
+    let samples = futures::stream::iter(0..runs)
+        .map(|_| run_once::<T>(&client, &query))
+        .buffer_unordered(BENCHMARK_CONCURRENCY)
+        .collect()
+        .await;
+    Ok(summarize(samples))
+}
+
+struct Sample {
+    latency: std::time::Duration,
+    usage: Option<Usage>,
+    outcome: Outcome,
+}
+
+enum Outcome {
+    Parsed,
+    RequestFailed,
+    ParseFailed,
+}
+
+async fn run_once<T>(client: &LlmClient, query: &OpenAIChatCompletionQuery) -> Sample
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let start = std::time::Instant::now();
+    let response = send_query(client, query).await;
+    let latency = start.elapsed();
+
+    let Ok(response) = response else {
+        return Sample {
+            latency,
+            usage: None,
+            outcome: Outcome::RequestFailed,
+        };
+    };
+    let parsed = response
+        .content()
+        .and_then(|content| parse_structured::<T>(content));
+    Sample {
+        latency,
+        usage: response.usage,
+        outcome: match parsed {
+            Ok(_) => Outcome::Parsed,
+            Err(_) => Outcome::ParseFailed,
+        },
+    }
+}
+
+fn summarize(samples: Vec<Sample>) -> BenchmarkResult {
+    let count =
+        |outcome: fn(&Outcome) -> bool| samples.iter().filter(|s| outcome(&s.outcome)).count();
+    let request_failures = count(|o| matches!(o, Outcome::RequestFailed));
+    let parse_failures = count(|o| matches!(o, Outcome::ParseFailed));
+
+    let responded: Vec<&Sample> = samples
+        .iter()
+        .filter(|s| !matches!(s.outcome, Outcome::RequestFailed))
+        .collect();
+    let mut latencies: Vec<std::time::Duration> = responded.iter().map(|s| s.latency).collect();
+    latencies.sort();
+    let usages: Vec<Usage> = responded.iter().filter_map(|s| s.usage).collect();
+    let mean = |tokens: fn(&Usage) -> usize| {
+        (!usages.is_empty())
+            .then(|| usages.iter().map(tokens).sum::<usize>() as f64 / usages.len() as f64)
+    };
+
+    BenchmarkResult {
+        runs: samples.len(),
+        request_failures,
+        parse_failures,
+        latency_p50: percentile(&latencies, 50),
+        latency_p90: percentile(&latencies, 90),
+        latency_p99: percentile(&latencies, 99),
+        mean_prompt_tokens: mean(|usage| usage.prompt_tokens),
+        mean_completion_tokens: mean(|usage| usage.completion_tokens),
+    }
+}
+
+/// The nearest-rank percentile of sorted values.
+fn percentile(sorted: &[std::time::Duration], percent: usize) -> Option<std::time::Duration> {
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let sample = |millis, outcome| Sample {
+            latency: std::time::Duration::from_millis(millis),
+            usage: (!matches!(outcome, Outcome::RequestFailed)).then_some(Usage {
+                prompt_tokens: 100,
+                completion_tokens: millis as usize,
+                total_tokens: 100 + millis as usize,
+            }),
+            outcome,
+        };
+        let mut samples: Vec<Sample> = (1..=8).map(|i| sample(i * 10, Outcome::Parsed)).collect();
+        samples.push(sample(90, Outcome::ParseFailed));
+        samples.push(sample(5, Outcome::RequestFailed));
+
+        let result = summarize(samples);
+        assert_eq!(result.runs, 10);
+        assert_eq!(result.request_failures, 1);
+        assert_eq!(result.parse_failures, 1);
+        assert_eq!(result.parse_success_rate(), 0.8);
+        assert_eq!(
+            result.latency_p50,
+            Some(std::time::Duration::from_millis(50))
+        );
+        assert_eq!(
+            result.latency_p90,
+            Some(std::time::Duration::from_millis(90))
+        );
+        assert_eq!(
+            result.latency_p99,
+            Some(std::time::Duration::from_millis(90))
+        );
+        assert_eq!(result.mean_prompt_tokens, Some(100.0));
+        assert_eq!(result.mean_completion_tokens, Some(50.0));
+
+        let empty = summarize(Vec::new());
+        assert_eq!(empty.parse_success_rate(), 0.0);
+        assert_eq!(empty.latency_p50, None);
+        assert_eq!(empty.mean_prompt_tokens, None);
+    }
+}
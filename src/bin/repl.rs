@@ -0,0 +1,310 @@
+//! An interactive prompt for iterating on prompts for structured extraction. Each line is
+//! sent as the next user message of one conversation and the structured reply is printed as
+//! JSON, while commands starting with `:` switch the model or temperature between turns.
+//!
+//! ```text
+//! cargo run --features cli --bin llm-repl -- --schema schema.json --history session.json
+//! ```
+//!
+//! The schema file holds either a JSON schema, named after the file, or the `json_schema`
+//! object written by `Schema::write_json` with its name and strict flag. Without one, replies
+//! have a single `answer` string. The history is read from the history file at startup if it
+//! exists, and written back after every turn, so an interrupted session can be resumed.
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use llm_structured_outputs::{Conversation, Message, QueryParams, Schema, sanitize_schema_name};
+
+const USAGE: &str = "Usage: llm-repl [--schema <file>] [--history <file>]";
+
+const HELP: &str = "\
+Type a prompt to send it, or a command:
+  :model [name]         Send to another model, or the configured one without a name
+  :temperature [value]  Set the sampling temperature, or reset it without a value
+  :history              Show the conversation so far
+  :clear                Start over with an empty conversation
+  :help                 Show this help
+  :quit                 Exit";
+
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    schema: Option<PathBuf>,
+    history: Option<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--schema" => &mut parsed.schema,
+            "--history" => &mut parsed.history,
+            _ => return Err(format!("Unknown argument '{arg}'\n{USAGE}")),
+        };
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {arg}\n{USAGE}"))?;
+        *target = Some(value.into());
+    }
+    Ok(parsed)
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Ask(String),
+    Model(Option<String>),
+    Temperature(Option<f32>),
+    History,
+    Clear,
+    Help,
+    Quit,
+}
+
+/// The command on a line of input, or `None` for a blank line.
+fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let Some(command) = line.strip_prefix(':') else {
+        return Ok(Some(Command::Ask(line.to_string())));
+    };
+
+    let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+    let argument = Some(argument.trim()).filter(|argument| !argument.is_empty());
+    let command = match name {
+        "model" => Command::Model(argument.map(str::to_string)),
+        "temperature" => Command::Temperature(
+            argument
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid temperature '{value}'"))
+                })
+                .transpose()?,
+        ),
+        "history" => Command::History,
+        "clear" => Command::Clear,
+        "help" => Command::Help,
+        "quit" | "exit" => Command::Quit,
+        _ => return Err(format!("Unknown command ':{name}', see :help")),
+    };
+    Ok(Some(command))
+}
+
+/// The schema in the file at `path`, or one with a single `answer` string without a file.
+fn load_schema(path: Option<&Path>) -> anyhow::Result<Schema> {
+    let Some(path) = path else {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "answer": { "type": "string" } },
+            "required": ["answer"],
+            "additionalProperties": false,
+        });
+        return Ok(Schema::new("answer", schema, true)?);
+    };
+
+    let mut value: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    if let Some(schema) = value.get_mut("schema").map(serde_json::Value::take) {
+        let name = value["name"].as_str().unwrap_or("schema");
+        let strict = value["strict"].as_bool().unwrap_or(true);
+        return Ok(Schema::new(name, schema, strict)?);
+    }
+    let name = path
+        .file_stem()
+        .map(|stem| sanitize_schema_name(&stem.to_string_lossy()))
+        .unwrap_or_else(|| "schema".to_string());
+    Ok(Schema::new(name, value, true)?)
+}
+
+fn load_history(path: &Path) -> anyhow::Result<Vec<Message>> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn save_history(path: &Path, messages: &[Message]) -> anyhow::Result<()> {
+    Ok(std::fs::write(
+        path,
+        serde_json::to_string_pretty(messages)?,
+    )?)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = parse_args(std::env::args().skip(1)).map_err(anyhow::Error::msg)?;
+    if let Err(errors) = llm_structured_outputs::init() {
+        for error in errors {
+            eprintln!("{error}");
+        }
+        std::process::exit(1);
+    }
+    let schema = load_schema(args.schema.as_deref())?;
+    let history = match &args.history {
+        Some(path) if path.exists() => load_history(path)?,
+        _ => Vec::new(),
+    };
+
+    println!(
+        "Schema '{}', {} messages of history. {HELP}",
+        schema.name(),
+        history.len()
+    );
+    let mut conversation = Conversation::with_messages(history);
+    let mut params = QueryParams::default();
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("> ");
+        if let Err(error) = std::io::stdout().flush() {
+            eprintln!("Failed to write the prompt: {error}");
+            break;
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(error)) => {
+                eprintln!("Failed to read input: {error}");
+                break;
+            }
+            None => break,
+        };
+        let command = match parse_command(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(error) => {
+                eprintln!("{error}");
+                continue;
+            }
+        };
+
+        match command {
+            Command::Ask(prompt) => match conversation.ask_dynamic(prompt, schema.clone()).await {
+                Ok(reply) => {
+                    println!("{reply:#}");
+                    save(args.history.as_deref(), &conversation);
+                }
+                Err(error) => eprintln!("Error: {error}"),
+            },
+            Command::Model(model) => {
+                params.model = model;
+                conversation = std::mem::take(&mut conversation).params(params.clone());
+            }
+            Command::Temperature(temperature) => {
+                params.temperature = temperature;
+                conversation = std::mem::take(&mut conversation).params(params.clone());
+            }
+            Command::History => {
+                for message in conversation.messages() {
+                    println!("[{:?}] {}", message.role(), message.content());
+                }
+            }
+            Command::Clear => {
+                conversation = Conversation::new().params(params.clone());
+                save(args.history.as_deref(), &conversation);
+            }
+            Command::Help => println!("{HELP}"),
+            Command::Quit => break,
+        }
+    }
+
+    if let Some(path) = &args.history {
+        println!("The history is saved in {}", path.display());
+    }
+    Ok(())
+}
+
+/// Save the history to `path`, if given, reporting rather than returning a failure so the
+/// session can go on.
+fn save(path: Option<&Path>, conversation: &Conversation) {
+    if let Some(path) = path
+        && let Err(error) = save_history(path, conversation.messages())
+    {
+        eprintln!("Failed to save the history to {}: {error}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| parse_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(args(&[]), Ok(Args::default()));
+        assert_eq!(
+            args(&["--history", "session.json", "--schema", "schema.json"]),
+            Ok(Args {
+                schema: Some("schema.json".into()),
+                history: Some("session.json".into()),
+            })
+        );
+        assert!(args(&["--schema"]).is_err());
+        assert!(args(&["--verbose"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("  "), Ok(None));
+        assert_eq!(
+            parse_command("Extract the names"),
+            Ok(Some(Command::Ask("Extract the names".to_string())))
+        );
+        assert_eq!(
+            parse_command(":model gpt-4o-mini"),
+            Ok(Some(Command::Model(Some("gpt-4o-mini".to_string()))))
+        );
+        assert_eq!(parse_command(":model"), Ok(Some(Command::Model(None))));
+        assert_eq!(
+            parse_command(":temperature 0.2"),
+            Ok(Some(Command::Temperature(Some(0.2))))
+        );
+        assert_eq!(
+            parse_command(":temperature"),
+            Ok(Some(Command::Temperature(None)))
+        );
+        assert!(parse_command(":temperature warm").is_err());
+        assert_eq!(parse_command(":quit"), Ok(Some(Command::Quit)));
+        assert!(parse_command(":undo").is_err());
+    }
+
+    #[test]
+    fn test_load_schema() {
+        let answer = load_schema(None).unwrap();
+        assert_eq!(answer.name(), "answer");
+        assert!(answer.strict());
+
+        let dir = std::env::temp_dir().join(format!("llm-repl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let raw = dir.join("people (v1).json");
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": { "names": { "type": "array", "items": { "type": "string" } } },
+            "required": ["names"],
+            "additionalProperties": false,
+        });
+        std::fs::write(&raw, schema.to_string()).unwrap();
+        let loaded = load_schema(Some(&raw)).unwrap();
+        assert_eq!(loaded.name(), "people__v1_");
+        assert_eq!(loaded.json_schema(), &schema);
+
+        // A schema written by `Schema::write_json` keeps its name and strict flag.
+        let dumped = dir.join("dumped.json");
+        Schema::new("people_v2", schema.clone(), false)
+            .unwrap()
+            .write_json(&dumped)
+            .unwrap();
+        let loaded = load_schema(Some(&dumped)).unwrap();
+        assert_eq!(loaded.name(), "people_v2");
+        assert!(!loaded.strict());
+        assert_eq!(loaded.json_schema(), &schema);
+
+        let history = dir.join("history.json");
+        let messages = vec![
+            Message::user("Hi"),
+            Message::assistant(r#"{"answer": "Hello"}"#),
+        ];
+        save_history(&history, &messages).unwrap();
+        let loaded = load_history(&history).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[1].content(), r#"{"answer": "Hello"}"#);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+use base64::Engine;
+
+use crate::{LlmError, Message, Schema, query_openai_inner};
+
+/// Query OpenAI for content that encodes binary data, such as a generated file, and return
+/// the decoded bytes. The content may be
+///
+/// - a base64 `data:` URL, e.g. `data:image/png;base64,iVBORw0KGgo=`,
+/// - a bare base64 string in the standard or URL-safe alphabet, with or without padding,
+/// - either of the above as a JSON string, which is what a schema of type string produces.
+pub async fn query_openai_bytes(
+    messages: Vec<Message>,
+    schema: Schema,
+) -> Result<Vec<u8>, LlmError> {
+    let response = query_openai_inner(messages, schema).await?;
+    decode_content(response.content()?)
+}
+
+fn decode_content(content: &str) -> Result<Vec<u8>, LlmError> {
+    let content = content.trim();
+    let content = match serde_json::from_str::<String>(content) {
+        Ok(unquoted) => std::borrow::Cow::Owned(unquoted),
+        Err(_) => std::borrow::Cow::Borrowed(content),
+    };
+
+    let payload = match content.strip_prefix("data:") {
+        Some(data_url) => {
+            let (media_type, payload) = data_url
+                .split_once(',')
+                .ok_or_else(|| LlmError::InvalidBinary("data URL without a payload".to_string()))?;
+            if !media_type.ends_with(";base64") {
+                return Err(LlmError::InvalidBinary(
+                    "data URL that is not base64 encoded".to_string(),
+                ));
+            }
+            payload
+        }
+        None => &content,
+    };
+
+    let payload = payload.trim_end_matches('=');
+    [
+        base64::engine::general_purpose::STANDARD_NO_PAD,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD,
+    ]
+    .iter()
+    .find_map(|engine| engine.decode(payload).ok())
+    .ok_or_else(|| LlmError::InvalidBinary("neither a data URL nor base64".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_content() {
+        let png = [0x89, b'P', b'N', b'G', 0xfb, 0xff];
+
+        assert_eq!(decode_content("iVBOR/v/").unwrap(), png);
+        assert_eq!(decode_content("iVBOR_v_").unwrap(), png);
+        assert_eq!(decode_content(r#""iVBOR/v/""#).unwrap(), png);
+        assert_eq!(
+            decode_content(r#""data:image/png;base64,iVBOR/v/""#).unwrap(),
+            png
+        );
+        assert_eq!(decode_content("aGk=").unwrap(), b"hi");
+        assert_eq!(decode_content("aGk").unwrap(), b"hi");
+
+        assert!(decode_content("data:text/plain,hello").is_err());
+        assert!(decode_content("not base64!").is_err());
+    }
+}
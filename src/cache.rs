@@ -0,0 +1,171 @@
+use crate::{
+    Config, LlmError, ORGANIZATION_HEADER, OpenAIChatCompletionQuery, OpenAIChatCompletionResponse,
+    PROJECT_HEADER,
+};
+
+/// Storage for responses to previously sent queries, so identical queries return the stored
+/// response without another network call. Only worth enabling for deterministic queries
+/// where the same input is expected to produce the same output.
+///
+/// Keys are SHA-256 digests of the full query, including model, messages and schema, and of
+/// the endpoint, organization and project it is sent to, so clients for different backends
+/// never read each other's responses. They are stable for a given build of this crate but
+/// may change between versions, which at worst causes cache misses for a persistent backend
+/// such as Redis.
+pub trait Cache: Send + Sync {
+    fn get(&self, key: &str) -> Option<String>;
+    fn insert(&self, key: String, response: String);
+    /// Remove every stored response. Entries are never invalidated otherwise.
+    fn clear(&self);
+}
+
+/// A [`Cache`] keeping responses in memory for the lifetime of the process.
+#[derive(Debug, Default)]
+pub struct MemoryCache(std::sync::Mutex<std::collections::HashMap<String, String>>);
+
+impl MemoryCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Cache for MemoryCache {
+    fn get(&self, key: &str) -> Option<String> {
+        self.0
+            .lock()
+            .expect("Cache lock poisoned")
+            .get(key)
+            .cloned()
+    }
+
+    fn insert(&self, key: String, response: String) {
+        self.0
+            .lock()
+            .expect("Cache lock poisoned")
+            .insert(key, response);
+    }
+
+    fn clear(&self) {
+        self.0.lock().expect("Cache lock poisoned").clear();
+    }
+}
+
+fn cache_key(query: &OpenAIChatCompletionQuery, config: &Config) -> String {
+    use sha2::Digest;
+
+    let header = |name| {
+        config
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let scope = serde_json::json!({
+        "url": config.chat_completions_url(),
+        "organization": header(ORGANIZATION_HEADER),
+        "project": header(PROJECT_HEADER),
+        "query": query,
+    });
+    format!("{:x}", sha2::Sha256::digest(scope.to_string()))
+}
+
+/// Return the cached response for `query` to the backend of `config` if there is one, and
+/// otherwise `fetch` it and store the result.
+pub(crate) async fn with_cache<F, Fut>(
+    cache: Option<&dyn Cache>,
+    query: &OpenAIChatCompletionQuery,
+    config: &Config,
+    fetch: F,
+) -> Result<OpenAIChatCompletionResponse, LlmError>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<OpenAIChatCompletionResponse, LlmError>>,
+{
+    let Some(cache) = cache else {
+        return fetch().await;
+    };
+
+    let key = cache_key(query, config);
+    if let Some(cached) = cache.get(&key) {
+        return serde_json::from_str(&cached).map_err(LlmError::InvalidResponse);
+    }
+
+    let response = fetch().await?;
+    cache.insert(
+        key,
+        serde_json::to_string(&response).expect("Failed to serialize response"),
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Message;
+
+    fn config(base_url: &str) -> Config {
+        Config::builder()
+            .api_key("sk-test")
+            .model("gpt-4o-mini")
+            .base_url(base_url)
+            .build()
+            .unwrap()
+    }
+
+    fn query(content: &str) -> OpenAIChatCompletionQuery {
+        OpenAIChatCompletionQuery::new(
+            "gpt-4o-mini".to_string(),
+            vec![Message::user(content)],
+            crate::get_schema::<Vec<String>>(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_identical_query_hits_cache() {
+        let cache = MemoryCache::new();
+        let config = config("https://api.openai.com/v1");
+        let mut fetches = 0;
+
+        for content in ["Hello, world!", "Hello, world!", "Goodbye, world!"] {
+            let response = with_cache(Some(&cache), &query(content), &config, || {
+                fetches += 1;
+                async {
+                    serde_json::from_str(r#"{"choices": [{"message": {"content": "[\"Hi\"]"}}]}"#)
+                        .map_err(LlmError::InvalidResponse)
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(response.content().unwrap(), r#"["Hi"]"#);
+        }
+        assert_eq!(fetches, 2);
+
+        cache.clear();
+        with_cache(Some(&cache), &query("Hello, world!"), &config, || {
+            fetches += 1;
+            async { serde_json::from_str(r#"{"choices": []}"#).map_err(LlmError::InvalidResponse) }
+        })
+        .await
+        .unwrap();
+        assert_eq!(fetches, 3);
+    }
+
+    #[test]
+    fn test_cache_key_scope() {
+        let query = query("Hello, world!");
+        let openai = config("https://api.openai.com/v1");
+        let key = cache_key(&query, &openai);
+        assert_eq!(key.len(), 64);
+        assert_eq!(key, cache_key(&query, &openai));
+
+        let proxy = config("https://proxy.example.com/v1");
+        assert_ne!(key, cache_key(&query, &proxy));
+        let organization = Config::builder()
+            .api_key("sk-test")
+            .model("gpt-4o-mini")
+            .organization("org-123")
+            .build()
+            .unwrap();
+        assert_ne!(key, cache_key(&query, &organization));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    LlmClient, LlmError, Message, OpenAIChatCompletionQuery, OpenAIChatCompletionResponse,
+    parse_structured, run_query, try_get_schema,
+};
+
+/// Query OpenAI for `n` independent answers in one request, for example to sample several
+/// and vote on the result. The prompt is only billed once, the completion tokens of every
+/// answer are.
+///
+/// Each answer is parsed on its own, so one that refuses or does not parse into `T` is
+/// returned as an error without discarding the others. Only a failure of the request itself,
+/// or a response without any choices, is an error of the whole query. The API may return
+/// fewer than `n` answers, for example when stopped by a content filter.
+pub async fn query_openai_n<T>(
+    messages: Vec<Message>,
+    n: u32,
+) -> Result<Vec<Result<T, LlmError>>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let client = LlmClient::default();
+    let query = OpenAIChatCompletionQuery {
+        n: Some(n),
+        ..OpenAIChatCompletionQuery::new(
+            client.config.model.clone(),
+            messages,
+            try_get_schema::<T>()?,
+        )
+    };
+    let (response, _) = run_query(&client, query).await?;
+    parse_choices(&response)
+}
+
+fn parse_choices<T>(
+    response: &OpenAIChatCompletionResponse,
+) -> Result<Vec<Result<T, LlmError>>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    if response.choices.is_empty() {
+        return Err(LlmError::EmptyChoices);
+    }
+    Ok(response
+        .choices
+        .iter()
+        .map(|choice| choice.content().and_then(parse_structured))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    struct Vote {
+        option: u32,
+    }
+
+    #[test]
+    fn test_parse_choices() {
+        let response: OpenAIChatCompletionResponse = serde_json::from_str(
+            r#"{"choices": [
+                {"message": {"content": "{\"option\": 1}"}},
+                {"message": {"content": "{\"option\": \"two\"}"}},
+                {"message": {"content": null, "refusal": "No."}},
+                {"message": {"content": "{\"option\": 3}"}}
+            ]}"#,
+        )
+        .unwrap();
+        let votes = parse_choices::<Vote>(&response).unwrap();
+        assert_eq!(votes.len(), 4);
+        assert_eq!(votes[0].as_ref().unwrap(), &Vote { option: 1 });
+        assert!(matches!(votes[1], Err(LlmError::ContentParse(_))));
+        assert!(matches!(&votes[2], Err(LlmError::Refusal(refusal)) if refusal == "No."));
+        assert_eq!(votes[3].as_ref().unwrap(), &Vote { option: 3 });
+
+        let empty: OpenAIChatCompletionResponse =
+            serde_json::from_str(r#"{"choices": []}"#).unwrap();
+        assert!(matches!(
+            parse_choices::<Vote>(&empty),
+            Err(LlmError::EmptyChoices)
+        ));
+    }
+
+    #[test]
+    fn test_n_serialized() {
+        let query = OpenAIChatCompletionQuery::new(
+            "gpt-4o".to_string(),
+            Vec::new(),
+            crate::get_schema::<Vec<String>>(),
+        );
+        assert!(serde_json::to_value(&query).unwrap().get("n").is_none());
+        let query = OpenAIChatCompletionQuery {
+            n: Some(5),
+            ..query
+        };
+        assert_eq!(serde_json::to_value(&query).unwrap()["n"], 5);
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{
+    ApiError, CompletionRequest, ContentPart, LlmError, Message, Provider, RETRY_POLICY, Role,
+    Schema, retry,
+};
+
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
+
+/// The API version sent in the `anthropic-version` header.
+const DEFAULT_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires a completion token limit, used unless the request sets one.
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Where and how to send requests to Anthropic's Messages API, see [`ClaudeProvider`].
+#[derive(Clone)]
+pub struct ClaudeConfig {
+    api_key: String,
+    model: String,
+    /// Without a trailing slash.
+    base_url: String,
+    version: String,
+    max_tokens: u32,
+}
+
+impl ClaudeConfig {
+    /// Send requests to `model`, e.g. `claude-sonnet-4-5`, authenticated with `api_key`.
+    pub fn new(api_key: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            model: model.into(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            version: DEFAULT_VERSION.to_string(),
+            max_tokens: DEFAULT_MAX_TOKENS,
+        }
+    }
+
+    /// Read the configuration from `ANTHROPIC_API_KEY` and `ANTHROPIC_MODEL`.
+    pub fn from_env() -> Result<Self, LlmError> {
+        let var = |name: &'static str| {
+            std::env::var(name).map_err(|_| LlmError::MissingConfig(name.to_string()))
+        };
+        Ok(Self::new(
+            var("ANTHROPIC_API_KEY")?,
+            var("ANTHROPIC_MODEL")?,
+        ))
+    }
+
+    /// Send requests to another Anthropic-compatible API, such as a proxy.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Send another `anthropic-version` header than `2023-06-01`.
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Generate at most `max_tokens` tokens unless the request sets
+    /// [`crate::QueryParams::max_completion_tokens`]. Defaults to 4096.
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+}
+
+/// A [`Provider`] for Anthropic's Claude models. Claude has no response format for
+/// structured output, so the schema is sent as the input schema of a tool the model is
+/// forced to call, and the input of that call is the structured output. The same types
+/// therefore work with Claude as with OpenAI.
+///
+/// Developer and system messages become the system prompt.
+#[derive(Clone)]
+pub struct ClaudeProvider {
+    http: reqwest::Client,
+    config: ClaudeConfig,
+}
+
+impl ClaudeProvider {
+    /// Send requests for `config` with a new HTTP client.
+    pub fn new(config: ClaudeConfig) -> Self {
+        Self::with_http_client(reqwest::Client::new(), config)
+    }
+
+    /// Send requests for `config` with `http`.
+    pub fn with_http_client(http: reqwest::Client, config: ClaudeConfig) -> Self {
+        Self { http, config }
+    }
+
+    async fn post(&self, body: &serde_json::Value) -> Result<MessagesResponse, LlmError> {
+        let response = self
+            .http
+            .post(format!("{}/messages", self.config.base_url))
+            .header("x-api-key", &self.config.api_key)
+            .header("anthropic-version", &self.config.version)
+            .json(body)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| retry::parse_retry_after(value.to_str().ok()?));
+            let body = response.text().await?;
+            return Err(LlmError::ApiStatus {
+                status: status.as_u16(),
+                error: ApiError::parse(&body).map(Box::new),
+                body,
+                retry_after,
+            });
+        }
+        serde_json::from_str(&response.text().await?).map_err(LlmError::InvalidResponse)
+    }
+}
+
+impl Provider for ClaudeProvider {
+    async fn complete(&self, request: CompletionRequest) -> Result<String, LlmError> {
+        let body = request_body(&self.config, &request);
+        let policy = RETRY_POLICY
+            .read()
+            .expect("Retry policy lock poisoned")
+            .clone();
+        let response = retry::with_retries(&policy, || self.post(&body)).await?;
+        structured_output(response, &request.schema)
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    ToolUse {
+        name: String,
+        input: serde_json::Value,
+    },
+    Text {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// The tool name to use for `schema`. Tool names follow the same rules as schema names.
+fn tool_name(schema: &Schema) -> &str {
+    schema.name()
+}
+
+/// The body of a Messages API request forcing the model to answer through a tool with the
+/// input schema of `request`.
+fn request_body(config: &ClaudeConfig, request: &CompletionRequest) -> serde_json::Value {
+    let system: Vec<&str> = request
+        .messages
+        .iter()
+        .filter(|message| message.role.is_instructions())
+        .map(|message| message.content.as_str())
+        .collect();
+    let messages: Vec<serde_json::Value> = request
+        .messages
+        .iter()
+        .filter(|message| !message.role.is_instructions())
+        .map(message)
+        .collect();
+    let name = tool_name(&request.schema);
+
+    let mut body = serde_json::json!({
+        "model": request.params.model.as_deref().unwrap_or(&config.model),
+        "max_tokens": request.params.max_completion_tokens.unwrap_or(config.max_tokens),
+        "messages": messages,
+        "tools": [{
+            "name": name,
+            "description": "Respond with the structured output.",
+            "input_schema": request.schema.json_schema(),
+        }],
+        "tool_choice": { "type": "tool", "name": name },
+    });
+    if !system.is_empty() {
+        body["system"] = system.join("\n\n").into();
+    }
+    if let Some(temperature) = request.params.temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = request.params.top_p {
+        body["top_p"] = top_p.into();
+    }
+    if let Some(stop) = request.params.stop.as_ref().filter(|stop| !stop.is_empty()) {
+        body["stop_sequences"] = stop.clone().into();
+    }
+    body
+}
+
+/// A user or assistant message in the Messages API format. Images are sent as URL or
+/// base64 sources depending on whether they are `data:` URLs.
+fn message(message: &Message) -> serde_json::Value {
+    let role = match message.role {
+        Role::Assistant => "assistant",
+        _ => "user",
+    };
+    if message.parts.is_empty() {
+        return serde_json::json!({ "role": role, "content": message.content });
+    }
+
+    let text = (!message.content.is_empty()).then(|| ContentPart::text(message.content.clone()));
+    let blocks: Vec<serde_json::Value> = text
+        .iter()
+        .chain(&message.parts)
+        .map(|part| match part {
+            ContentPart::Text { text } => serde_json::json!({ "type": "text", "text": text }),
+            ContentPart::ImageUrl { image_url } => {
+                let source = match image_url
+                    .url
+                    .strip_prefix("data:")
+                    .and_then(|data| data.split_once(";base64,"))
+                {
+                    Some((media_type, data)) => {
+                        serde_json::json!({ "type": "base64", "media_type": media_type, "data": data })
+                    }
+                    None => serde_json::json!({ "type": "url", "url": image_url.url }),
+                };
+                serde_json::json!({ "type": "image", "source": source })
+            }
+        })
+        .collect();
+    serde_json::json!({ "role": role, "content": blocks })
+}
+
+/// The input of the call to the structured output tool, as JSON text.
+fn structured_output(response: MessagesResponse, schema: &Schema) -> Result<String, LlmError> {
+    if response.content.is_empty() {
+        return Err(LlmError::EmptyChoices);
+    }
+    let mut text = String::new();
+    for block in response.content {
+        match block {
+            ContentBlock::ToolUse { name, input } if name == tool_name(schema) => {
+                return Ok(input.to_string());
+            }
+            ContentBlock::Text { text: block } => text.push_str(&block),
+            _ => {}
+        }
+    }
+    // Without a tool call the model usually explains in text why it did not answer.
+    Err(LlmError::Refusal(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryParams;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Capital {
+        city: String,
+    }
+
+    #[test]
+    fn test_request_body() {
+        let config = ClaudeConfig::new("sk-ant-test", "claude-sonnet-4-5");
+        let request = CompletionRequest {
+            messages: vec![
+                Message::developer("Answer briefly."),
+                Message::user("The capital of Norway?"),
+                Message::assistant(r#"{"city": "Oslo"}"#),
+                Message::from_parts(
+                    Role::User,
+                    vec![
+                        ContentPart::text("And this one?"),
+                        ContentPart::image_url("https://example.com/map.png"),
+                        ContentPart::image_bytes(b"png", "image/png").unwrap(),
+                    ],
+                ),
+            ],
+            schema: crate::get_schema::<Capital>(),
+            params: QueryParams {
+                temperature: Some(0.0),
+                ..QueryParams::default()
+            },
+        };
+        let name = request.schema.name().to_string();
+
+        let body = request_body(&config, &request);
+        assert_eq!(body["model"], "claude-sonnet-4-5");
+        assert_eq!(body["max_tokens"], DEFAULT_MAX_TOKENS);
+        assert_eq!(body["system"], "Answer briefly.");
+        assert_eq!(body["temperature"], 0.0);
+        assert!(body.get("top_p").is_none());
+        assert_eq!(body["tools"][0]["name"], name);
+        assert_eq!(
+            body["tools"][0]["input_schema"],
+            *request.schema.json_schema()
+        );
+        assert_eq!(
+            body["tool_choice"],
+            serde_json::json!({ "type": "tool", "name": name })
+        );
+
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[0],
+            serde_json::json!({ "role": "user", "content": "The capital of Norway?" })
+        );
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(
+            messages[2]["content"],
+            serde_json::json!([
+                { "type": "text", "text": "And this one?" },
+                {
+                    "type": "image",
+                    "source": { "type": "url", "url": "https://example.com/map.png" }
+                },
+                {
+                    "type": "image",
+                    "source": { "type": "base64", "media_type": "image/png", "data": "cG5n" }
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_structured_output() {
+        let schema = crate::get_schema::<Capital>();
+        let response = |content: serde_json::Value| {
+            let response = serde_json::json!({ "content": content, "stop_reason": "tool_use" });
+            serde_json::from_value::<MessagesResponse>(response).unwrap()
+        };
+
+        let answered = response(serde_json::json!([
+            { "type": "thinking", "thinking": "Oslo." },
+            { "type": "tool_use", "id": "toolu_1", "name": schema.name(), "input": { "city": "Oslo" } },
+        ]));
+        let output = structured_output(answered, &schema).unwrap();
+        let capital: Capital = crate::parse_structured(&output).unwrap();
+        assert_eq!(
+            capital,
+            Capital {
+                city: "Oslo".to_string()
+            }
+        );
+
+        let refused = response(serde_json::json!([{ "type": "text", "text": "I can't." }]));
+        assert!(matches!(
+            structured_output(refused, &schema),
+            Err(LlmError::Refusal(text)) if text == "I can't."
+        ));
+        assert!(matches!(
+            structured_output(response(serde_json::json!([])), &schema),
+            Err(LlmError::EmptyChoices)
+        ));
+    }
+}
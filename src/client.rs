@@ -0,0 +1,645 @@
+use futures::Stream;
+
+use crate::{
+    CONFIG, CompletionRequest, Config, LlmError, Message, OpenAIChatCompletionQuery, Provider,
+    QueryParams, RETRY_POLICY, Request, RetryPolicy, Schema, repair, run_query, stream,
+    try_get_schema,
+};
+
+/// A configuration and the HTTP client to send its requests with. Use your own
+/// [`reqwest::Client`] to set up proxies, custom root certificates, TLS or connection pooling.
+///
+/// The free query functions such as [`crate::query_openai`] use the default client, which
+/// shares one HTTP client and reads its configuration from the environment.
+#[derive(Clone)]
+pub struct LlmClient {
+    pub(crate) http: reqwest::Client,
+    pub(crate) config: Config,
+    /// Overrides the policy set with [`crate::set_retry_policy`].
+    retry_policy: Option<RetryPolicy>,
+}
+
+impl Default for LlmClient {
+    /// The shared HTTP client with the configuration from the environment. Panics if the
+    /// configuration is missing, see [`crate::init`].
+    fn default() -> Self {
+        Self::with_http_client(crate::client(), CONFIG.clone())
+    }
+}
+
+impl LlmClient {
+    /// Send requests for `config` with `http`.
+    pub fn with_http_client(http: reqwest::Client, config: Config) -> Self {
+        Self {
+            http,
+            config,
+            retry_policy: None,
+        }
+    }
+
+    /// Send requests with `http` instead, keeping the configuration.
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Retry failed requests of this client according to `policy` instead of the one set
+    /// with [`crate::set_retry_policy`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// The retry policy requests of this client follow.
+    pub(crate) fn current_retry_policy(&self) -> RetryPolicy {
+        match &self.retry_policy {
+            Some(policy) => policy.clone(),
+            None => RETRY_POLICY
+                .read()
+                .expect("Retry policy lock poisoned")
+                .clone(),
+        }
+    }
+
+    /// Query for structured output of type `T`, like [`crate::query_openai`].
+    pub async fn query<T>(&self, messages: Vec<Message>) -> Result<T, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    {
+        self.query_with_opts(messages, QueryParams::default()).await
+    }
+
+    /// Query with parameters such as another model or sampling options, like
+    /// [`crate::query_openai_with_opts`].
+    pub async fn query_with_opts<T>(
+        &self,
+        messages: Vec<Message>,
+        params: QueryParams,
+    ) -> Result<T, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    {
+        let request = CompletionRequest {
+            messages,
+            schema: try_get_schema::<T>()?,
+            params,
+        };
+        Ok(repair::complete_and_parse(self, request).await?.0)
+    }
+
+    /// Query for structured output of type `T` along with the JSON text it was parsed from,
+    /// like [`crate::query_openai_raw`].
+    pub async fn query_raw<T>(&self, messages: Vec<Message>) -> Result<(T, String), LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    {
+        let request = CompletionRequest {
+            messages,
+            schema: try_get_schema::<T>()?,
+            params: QueryParams::default(),
+        };
+        repair::complete_and_parse(self, request).await
+    }
+
+    /// Query for structured output following a schema built at runtime, like
+    /// [`crate::query_openai_dynamic`].
+    pub async fn query_dynamic(
+        &self,
+        messages: Vec<Message>,
+        schema: Schema,
+    ) -> Result<serde_json::Value, LlmError> {
+        let request = CompletionRequest {
+            messages,
+            schema,
+            params: QueryParams::default(),
+        };
+        Ok(repair::complete_and_parse(self, request).await?.0)
+    }
+
+    /// Stream the content deltas of the response, like [`crate::query_openai_stream`].
+    pub async fn query_stream<T>(
+        &self,
+        messages: Vec<Message>,
+    ) -> Result<impl Stream<Item = Result<String, LlmError>> + use<T>, LlmError>
+    where
+        T: schemars::JsonSchema + 'static,
+    {
+        stream::query_openai_stream_inner(self, messages, try_get_schema::<T>()?).await
+    }
+
+    /// Send a built request, like [`Request::send`].
+    pub async fn send<T>(&self, request: Request<T>) -> Result<T, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    {
+        request.send_with(self).await
+    }
+}
+
+/// The default provider, sending chat completion requests to OpenAI or an OpenAI-compatible
+/// API.
+impl Provider for LlmClient {
+    async fn complete(&self, request: CompletionRequest) -> Result<String, LlmError> {
+        let query = OpenAIChatCompletionQuery::new(
+            self.config.model.clone(),
+            request.messages,
+            request.schema,
+        )
+        .with_params(request.params);
+        // The structured output is inside a string field of the response, so it is parsed
+        // separately once the rest of the response has been.
+        let (response, _) = run_query(self, query).await?;
+        Ok(response.content()?.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::RetryDecision;
+
+    /// An HTTP response with `status` and a JSON `body`, closing the connection.
+    fn http_response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\
+                connection: close\r\n{headers}\r\n{body}",
+            body.len()
+        )
+    }
+
+    /// A successful chat completion whose content is `content`.
+    fn completion(content: &str) -> String {
+        let body = serde_json::json!({ "choices": [{ "message": { "content": content } }] });
+        http_response("200 OK", "", &body.to_string())
+    }
+
+    /// Answer requests on a local port with `responses` in order, one per connection,
+    /// returning the base URL and the raw requests received.
+    async fn serve(responses: Vec<String>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                line.to_ascii_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(str::to_string)
+                            })
+                            .and_then(|length| length.trim().parse().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                    if read == 0 {
+                        break;
+                    }
+                }
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request).into_owned());
+            }
+            requests
+        });
+        (base_url, server)
+    }
+
+    fn test_config(base_url: String) -> Config {
+        Config::builder()
+            .api_key("sk-test")
+            .model("gpt-4o")
+            .base_url(base_url)
+            .build()
+            .unwrap()
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Answer {
+        answer: u32,
+    }
+
+    #[tokio::test]
+    async fn test_injected_http_client() {
+        let (base_url, server) = serve(vec![completion(r#"{"answer": 42}"#)]).await;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-proxy-token", "secret".parse().unwrap());
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        let client = LlmClient::with_http_client(http, test_config(base_url));
+
+        let answer: Answer = client.query(Vec::new()).await.unwrap();
+        assert_eq!(answer, Answer { answer: 42 });
+
+        let request = server.await.unwrap().remove(0).to_ascii_lowercase();
+        assert!(request.starts_with("post /chat/completions "), "{request}");
+        assert!(request.contains("x-proxy-token: secret"), "{request}");
+        assert!(
+            request.contains("authorization: bearer sk-test"),
+            "{request}"
+        );
+        assert!(request.contains(r#""model":"gpt-4o""#), "{request}");
+    }
+
+    #[tokio::test]
+    async fn test_client_retry_policy() {
+        let rate_limited = http_response("429 Too Many Requests", "retry-after: 0\r\n", "{}");
+        let ms = std::time::Duration::from_millis;
+
+        let (base_url, server) =
+            serve(vec![rate_limited.clone(), completion(r#"{"answer": 1}"#)]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(RetryPolicy::exponential(1, ms(1), ms(1)));
+        let answer: Answer = client.query(Vec::new()).await.unwrap();
+        assert_eq!(answer, Answer { answer: 1 });
+        assert_eq!(server.await.unwrap().len(), 2);
+
+        let (base_url, _server) = serve(vec![rate_limited]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(RetryPolicy::never());
+        assert!(matches!(
+            client.query::<Answer>(Vec::new()).await,
+            Err(LlmError::ApiStatus {
+                status: 429,
+                retry_after: Some(std::time::Duration::ZERO),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_custom_policy() {
+        // Retry responses whose body is not a chat completion, but never a bad request.
+        let policy = RetryPolicy::new(|error, attempt| {
+            if matches!(error, LlmError::InvalidResponse(_)) && attempt < 3 {
+                RetryDecision::Retry(std::time::Duration::ZERO)
+            } else {
+                RetryDecision::Fail
+            }
+        });
+
+        let garbled = http_response("200 OK", "", r#"{"choices": [{"mess"#);
+        let (base_url, server) = serve(vec![garbled, completion(r#"{"answer": 1}"#)]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(policy.clone());
+        let answer: Answer = client.query(Vec::new()).await.unwrap();
+        assert_eq!(answer, Answer { answer: 1 });
+        assert_eq!(server.await.unwrap().len(), 2);
+
+        let bad_request = http_response("400 Bad Request", "", "{}");
+        let (base_url, server) = serve(vec![bad_request]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(policy);
+        assert!(matches!(
+            client.query::<Answer>(Vec::new()).await,
+            Err(LlmError::ApiStatus { status: 400, .. })
+        ));
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_conversation_client() {
+        let (base_url, server) = serve(vec![
+            completion(r#"{"answer": 8}"#),
+            completion(r#"{"answer": 24}"#),
+        ])
+        .await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url));
+        let mut conversation = crate::Conversation::with_developer_message("Answer briefly.")
+            .client(client)
+            .model("gpt-4o-mini");
+
+        let first: Answer = conversation
+            .ask("How many legs does a spider have?")
+            .await
+            .unwrap();
+        assert_eq!(first, Answer { answer: 8 });
+        let second: Answer = conversation.ask("And three spiders?").await.unwrap();
+        assert_eq!(second, Answer { answer: 24 });
+
+        let messages = conversation.messages();
+        assert_eq!(messages.len(), 5);
+        assert!(matches!(messages[2].role, crate::Role::Assistant));
+        assert_eq!(messages[2].content, r#"{"answer": 8}"#);
+
+        let requests = server.await.unwrap();
+        assert!(
+            requests[1].contains(r#""model":"gpt-4o-mini""#),
+            "{}",
+            requests[1]
+        );
+        assert!(
+            requests[1].contains(r#"{\"answer\": 8}"#),
+            "{}",
+            requests[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conversation_failed_ask() {
+        let rate_limited = http_response("429 Too Many Requests", "", "{}");
+        let (base_url, server) = serve(vec![rate_limited, completion(r#"{"answer": 8}"#)]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(RetryPolicy::never());
+        let mut conversation =
+            crate::Conversation::with_developer_message("Answer briefly.").client(client);
+
+        let question = "How many legs does a spider have?";
+        assert!(matches!(
+            conversation.ask::<Answer>(question).await,
+            Err(LlmError::ApiStatus { status: 429, .. })
+        ));
+        assert_eq!(conversation.messages().len(), 1);
+
+        // Asking again sends the question once.
+        let answer: Answer = conversation.ask(question).await.unwrap();
+        assert_eq!(answer, Answer { answer: 8 });
+        assert_eq!(conversation.messages().len(), 3);
+        let requests = server.await.unwrap();
+        assert_eq!(requests[1].matches(r#""role":"user""#).count(), 1);
+
+        // Nor are messages trimmed to fit the budget lost.
+        let rate_limited = http_response("429 Too Many Requests", "", "{}");
+        let (base_url, _server) = serve(vec![rate_limited]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(RetryPolicy::never());
+        let history: Vec<Message> = (0..10)
+            .map(|i| Message::user(format!("Question {i} {}", "word ".repeat(50))))
+            .collect();
+        let mut conversation = crate::Conversation::with_messages(history)
+            .client(client)
+            .token_budget(crate::TokenBudget::new(128_000 - 200));
+        assert!(conversation.ask::<Answer>(question).await.is_err());
+        assert_eq!(conversation.messages().len(), 10);
+        assert!(
+            conversation.messages()[0]
+                .content()
+                .starts_with("Question 0")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_conversation_summarize_oldest() {
+        let summary = |text: &str| completion(&serde_json::json!({ "summary": text }).to_string());
+        let (base_url, server) = serve(vec![
+            completion(r#"{"answer": 8}"#),
+            summary("Spiders have 8 legs."),
+            completion(r#"{"answer": 6}"#),
+            summary("Spiders have 8 legs and insects 6."),
+            completion(r#"{"answer": 0}"#),
+        ])
+        .await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url));
+        let budget =
+            crate::TokenBudget::new(128_000 - 150).strategy(crate::TrimStrategy::SummarizeOldest);
+        let mut conversation = crate::Conversation::with_developer_message("Answer briefly.")
+            .client(client)
+            .token_budget(budget);
+
+        let padding = "word ".repeat(30);
+        for question in ["spider", "insect", "snake"] {
+            let _: Answer = conversation
+                .ask(format!("How many legs does a {question} have? {padding}"))
+                .await
+                .unwrap();
+        }
+
+        // The first summary is folded into the second rather than kept as a system prompt.
+        let requests = server.await.unwrap();
+        assert!(requests[3].contains("[user] Summary of the earlier conversation: Spiders"));
+        let messages = conversation.messages();
+        assert_eq!(messages.len(), 5);
+        assert!(matches!(messages[0].role(), crate::Role::Developer));
+        assert!(matches!(messages[1].role(), crate::Role::User));
+        assert_eq!(
+            messages[1].content(),
+            "Summary of the earlier conversation: Spiders have 8 legs and insects 6."
+        );
+        let summaries = messages
+            .iter()
+            .filter(|message| message.content().starts_with("Summary of"))
+            .count();
+        assert_eq!(summaries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tool_round_trip() {
+        #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+        struct GetWeather {
+            city: String,
+        }
+
+        let wire_call = serde_json::json!({
+            "id": "call_1",
+            "type": "function",
+            "function": { "name": "get_weather", "arguments": r#"{"city": "Oslo"}"# },
+        });
+        let reply = serde_json::json!({
+            "choices": [{ "message": { "content": null, "tool_calls": [wire_call] } }],
+        });
+        let (base_url, server) = serve(vec![
+            http_response("200 OK", "", &reply.to_string()),
+            completion("It is sunny in Oslo."),
+        ])
+        .await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url));
+        let tools = vec![crate::ToolDef::named::<GetWeather>("get_weather").unwrap()];
+
+        let mut messages = vec![Message::user("What is the weather in Oslo?")];
+        let called = client
+            .query_tools(
+                messages.clone(),
+                tools.clone(),
+                crate::ToolChoice::Auto,
+                None,
+            )
+            .await
+            .unwrap();
+        let [call] = called.tool_calls.as_slice() else {
+            panic!("Expected one tool call: {called:?}");
+        };
+        let arguments: GetWeather = call.arguments().unwrap();
+        assert_eq!(arguments.city, "Oslo");
+
+        messages.push(called.message());
+        messages.push(Message::tool_result(&call.id, r#"{"weather": "sunny"}"#));
+        let response = client
+            .query_tools(messages, tools, crate::ToolChoice::Auto, None)
+            .await
+            .unwrap();
+        assert_eq!(response.content.as_deref(), Some("It is sunny in Oslo."));
+        assert!(response.tool_calls.is_empty());
+
+        let requests = server.await.unwrap();
+        let (_, body) = requests[1].split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        let sent = &body["messages"];
+        assert_eq!(
+            sent[1],
+            serde_json::json!({ "role": "assistant", "content": null, "tool_calls": [wire_call] })
+        );
+        assert_eq!(
+            sent[2],
+            serde_json::json!({
+                "role": "tool",
+                "content": r#"{"weather": "sunny"}"#,
+                "tool_call_id": "call_1",
+            })
+        );
+
+        // The history reads back as it was sent.
+        let parsed: Vec<Message> = serde_json::from_value(sent.clone()).unwrap();
+        assert_eq!(parsed[1].tool_calls(), called.tool_calls);
+        assert!(matches!(parsed[2].role(), crate::Role::Tool));
+        assert_eq!(parsed[2].tool_call_id(), Some("call_1"));
+    }
+
+    #[tokio::test]
+    async fn test_create_batch() {
+        let (base_url, server) = serve(vec![
+            http_response("200 OK", "", r#"{"id": "file-1", "purpose": "batch"}"#),
+            http_response("200 OK", "", r#"{"id": "batch_1", "status": "validating"}"#),
+        ])
+        .await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url));
+        let batch = client
+            .create_batch::<Answer>(vec![("row-1".to_string(), vec![Message::user("1 + 1?")])])
+            .await
+            .unwrap();
+        assert_eq!(batch.id, "batch_1");
+        assert!(!batch.is_finished());
+
+        let requests = server.await.unwrap();
+        let upload = &requests[0];
+        assert!(upload.starts_with("POST /files "), "{upload}");
+        assert!(
+            upload.contains("content-type: multipart/form-data; boundary="),
+            "{upload}"
+        );
+        assert!(
+            upload.contains("name=\"purpose\"\r\n\r\nbatch\r\n"),
+            "{upload}"
+        );
+        assert!(upload.contains(r#"{"custom_id":"row-1""#), "{upload}");
+        let create = &requests[1];
+        assert!(create.starts_with("POST /batches "), "{create}");
+        assert!(create.contains(r#""input_file_id":"file-1""#), "{create}");
+    }
+
+    #[tokio::test]
+    async fn test_api_error() {
+        let rejected = http_response(
+            "400 Bad Request",
+            "",
+            r#"{"error": {"message": "Invalid schema for response_format 'Answer': 'format' is not permitted.", "type": "invalid_request_error", "param": "response_format", "code": null}}"#,
+        );
+        let (base_url, _server) = serve(vec![rejected]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(RetryPolicy::never());
+        let Err(LlmError::ApiStatus {
+            status: 400,
+            error: Some(error),
+            ..
+        }) = client.query::<Answer>(Vec::new()).await
+        else {
+            panic!("Expected a rejected request");
+        };
+        assert_eq!(error.kind.as_deref(), Some("invalid_request_error"));
+        assert_eq!(error.param.as_deref(), Some("response_format"));
+        assert_eq!(error.code, None);
+        assert!(error.message.contains("'format' is not permitted"));
+
+        // Bodies without the envelope are kept as is.
+        let (base_url, _server) =
+            serve(vec![http_response("502 Bad Gateway", "", "upstream error")]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url))
+            .retry_policy(RetryPolicy::never());
+        assert!(matches!(
+            client.query::<Answer>(Vec::new()).await,
+            Err(LlmError::ApiStatus { status: 502, error: None, body, .. }) if body == "upstream error"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_empty_choices() {
+        // Some backends answer a content-filtered request with no choices.
+        let empty = http_response(
+            "200 OK",
+            "",
+            r#"{"id": "chatcmpl-1", "choices": [], "prompt_filter_results": []}"#,
+        );
+        let (base_url, server) = serve(vec![empty]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url));
+        assert!(matches!(
+            client.query::<Answer>(Vec::new()).await,
+            Err(LlmError::EmptyChoices)
+        ));
+        // The error is not retried.
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // Accept the connection but never answer.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let _server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await
+        });
+
+        let config = Config::builder()
+            .model("gpt-4o")
+            .base_url(base_url)
+            .timeout(std::time::Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let client = LlmClient::with_http_client(reqwest::Client::new(), config)
+            .retry_policy(RetryPolicy::never());
+        assert!(matches!(
+            client.query::<Answer>(Vec::new()).await,
+            Err(LlmError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_query_raw() {
+        let content = r#"{"answer": 7, "unit": "days"}"#;
+        let (base_url, _server) = serve(vec![completion(content)]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url));
+        let (answer, raw) = client.query_raw::<Answer>(Vec::new()).await.unwrap();
+        assert_eq!(answer, Answer { answer: 7 });
+        // Fields the type does not model are kept in the raw JSON.
+        assert_eq!(raw, content);
+    }
+
+    #[tokio::test]
+    async fn test_query_dynamic() {
+        let (base_url, server) = serve(vec![completion(r#"{"answer": 7}"#)]).await;
+        let client = LlmClient::with_http_client(reqwest::Client::new(), test_config(base_url));
+        let schema = crate::get_schema::<Answer>();
+        let answer = client
+            .query_dynamic(Vec::new(), schema.clone())
+            .await
+            .unwrap();
+        assert_eq!(answer, serde_json::json!({ "answer": 7 }));
+
+        let request = server.await.unwrap().remove(0);
+        let name = format!(r#""name":"{}""#, schema.name());
+        assert!(request.contains(&name), "{request}");
+    }
+}
@@ -0,0 +1,264 @@
+use base64::Engine;
+
+use crate::{LlmError, Message, ROLE_NAMES, RoleNames, ToolCall};
+
+/// Image MIME types accepted by OpenAI's vision models.
+const SUPPORTED_IMAGE_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// A part of a message's content in OpenAI's content-parts format.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+/// The location of an image, either a URL or a `data:` URL holding the image itself.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    /// The resolution to process the image at: `low`, `high` or `auto`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ContentPart {
+    /// A piece of text.
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    /// An image at a URL.
+    pub fn image_url(url: impl Into<String>) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: url.into(),
+                detail: None,
+            },
+        }
+    }
+
+    /// An image sent inline as a base64 `data:` URL, so it does not need to be hosted
+    /// anywhere. Errors if `mime` is not an image type OpenAI supports.
+    pub fn image_bytes(bytes: &[u8], mime: &str) -> Result<Self, LlmError> {
+        let mime = mime.trim().to_ascii_lowercase();
+        if !SUPPORTED_IMAGE_TYPES.contains(&mime.as_str()) {
+            return Err(LlmError::InvalidParameter {
+                name: "mime",
+                reason: format!(
+                    "Unsupported image type '{mime}', expected one of {}",
+                    SUPPORTED_IMAGE_TYPES.join(", ")
+                ),
+            });
+        }
+
+        let data = base64::engine::general_purpose::STANDARD.encode(bytes);
+        Ok(Self::image_url(format!("data:{mime};base64,{data}")))
+    }
+
+    /// Process an image at the resolution `detail`: `low`, `high` or `auto`. Has no effect
+    /// on text.
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        if let ContentPart::ImageUrl { image_url } = &mut self {
+            image_url.detail = Some(detail.into());
+        }
+        self
+    }
+}
+
+/// A message as sent to and received from the API.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct WireMessage {
+    role: String,
+    /// `None` for an assistant message with only tool calls.
+    #[serde(default)]
+    content: Option<WireContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum WireContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+impl From<Message> for WireMessage {
+    fn from(message: Message) -> Self {
+        WireMessage::new(
+            message,
+            &ROLE_NAMES.read().expect("Role names lock poisoned"),
+        )
+    }
+}
+
+impl TryFrom<WireMessage> for Message {
+    type Error = anyhow::Error;
+
+    fn try_from(message: WireMessage) -> anyhow::Result<Self> {
+        message.into_message(&ROLE_NAMES.read().expect("Role names lock poisoned"))
+    }
+}
+
+impl WireMessage {
+    fn new(message: Message, role_names: &RoleNames) -> Self {
+        let content = if !message.parts.is_empty() {
+            let text = (!message.content.is_empty()).then_some(ContentPart::Text {
+                text: message.content,
+            });
+            Some(WireContent::Parts(
+                text.into_iter().chain(message.parts).collect(),
+            ))
+        } else if message.content.is_empty() && !message.tool_calls.is_empty() {
+            None
+        } else {
+            Some(WireContent::Text(message.content))
+        };
+        WireMessage {
+            role: role_names.name(&message.role).to_string(),
+            content,
+            tool_call_id: message.tool_call_id,
+            tool_calls: message.tool_calls,
+        }
+    }
+
+    fn into_message(self, role_names: &RoleNames) -> anyhow::Result<Message> {
+        let (content, parts) = match self.content {
+            None => (String::new(), Vec::new()),
+            Some(WireContent::Text(text)) => (text, Vec::new()),
+            Some(WireContent::Parts(parts)) => {
+                let mut content = String::new();
+                let mut other = Vec::new();
+                for part in parts {
+                    match part {
+                        ContentPart::Text { text } => content.push_str(&text),
+                        part => other.push(part),
+                    }
+                }
+                (content, other)
+            }
+        };
+        Ok(Message {
+            role: role_names.role(&self.role)?,
+            content,
+            parts,
+            tool_call_id: self.tool_call_id,
+            tool_calls: self.tool_calls,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+
+    #[test]
+    fn test_image_bytes() {
+        let png = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        let part = ContentPart::image_bytes(&png, "image/PNG").unwrap();
+        assert_eq!(
+            part,
+            ContentPart::image_url("data:image/png;base64,iVBORw0KGgo=")
+        );
+        assert!(ContentPart::image_bytes(&png, "application/pdf").is_err());
+
+        let message = Message::user("What is in this image?");
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({ "role": "user", "content": "What is in this image?" })
+        );
+
+        let message = message.with_part(part);
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "What is in this image?" },
+                    {
+                        "type": "image_url",
+                        "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" }
+                    },
+                ],
+            })
+        );
+
+        let parsed: Message = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.content, message.content);
+        assert_eq!(parsed.parts, message.parts);
+    }
+
+    #[test]
+    fn test_message_from_parts() {
+        let message = Message::from_parts(
+            Role::User,
+            vec![
+                ContentPart::text("Compare "),
+                ContentPart::image_url("https://example.com/a.png").detail("low"),
+                ContentPart::text(" with "),
+                ContentPart::image_url("https://example.com/b.png"),
+            ],
+        );
+        assert_eq!(message.parts().len(), 4);
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    { "type": "text", "text": "Compare " },
+                    {
+                        "type": "image_url",
+                        "image_url": { "url": "https://example.com/a.png", "detail": "low" }
+                    },
+                    { "type": "text", "text": " with " },
+                    { "type": "image_url", "image_url": { "url": "https://example.com/b.png" } },
+                ],
+            })
+        );
+        assert_eq!(
+            ContentPart::text("Hi").detail("low"),
+            ContentPart::text("Hi")
+        );
+    }
+
+    #[test]
+    fn test_role_names() {
+        let names = RoleNames::new().assistant("model").developer("system");
+        let messages = [
+            (Role::Developer, "system"),
+            (Role::User, "user"),
+            (Role::Assistant, "model"),
+        ];
+
+        for (role, name) in messages {
+            let message = Message::new(role, "Hello");
+            let wire = serde_json::to_value(WireMessage::new(message, &names)).unwrap();
+            assert_eq!(
+                wire,
+                serde_json::json!({ "role": name, "content": "Hello" })
+            );
+
+            let wire: WireMessage = serde_json::from_value(wire).unwrap();
+            let parsed = wire.into_message(&names).unwrap();
+            assert_eq!(names.name(&parsed.role), name);
+        }
+
+        // The default names are still understood.
+        let wire: WireMessage =
+            serde_json::from_value(serde_json::json!({ "role": "assistant", "content": "Hi" }))
+                .unwrap();
+        assert!(matches!(
+            wire.into_message(&names).unwrap().role,
+            Role::Assistant
+        ));
+        let wire: WireMessage =
+            serde_json::from_value(serde_json::json!({ "role": "critic", "content": "Hi" }))
+                .unwrap();
+        assert!(wire.into_message(&names).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{
+    CompletionRequest, LlmClient, LlmError, Message, QueryParams, Schema, context_window,
+    estimate_prompt_tokens, repair, try_get_schema,
+};
+
+/// How to shrink the history of a [`Conversation`] that no longer fits its token budget.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// Drop the oldest messages.
+    #[default]
+    DropOldest,
+    /// Replace the oldest messages with a summary written by the model. This costs an extra
+    /// request every time the history is trimmed.
+    SummarizeOldest,
+}
+
+/// The share of the model's context window a [`Conversation`] may use for its prompt. The
+/// remaining `reserved_completion_tokens` are left for the reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenBudget {
+    reserved_completion_tokens: usize,
+    strategy: TrimStrategy,
+}
+
+impl TokenBudget {
+    /// A budget keeping `reserved_completion_tokens` free for the reply, trimming with
+    /// [`TrimStrategy::DropOldest`].
+    pub const fn new(reserved_completion_tokens: usize) -> Self {
+        Self {
+            reserved_completion_tokens,
+            strategy: TrimStrategy::DropOldest,
+        }
+    }
+
+    /// Trim the history with `strategy` instead.
+    pub fn strategy(mut self, strategy: TrimStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+}
+
+/// A multi-turn dialogue. Developer and system messages at the start of the history are the system
+/// prompt and are never trimmed.
+#[derive(Clone, Default)]
+pub struct Conversation {
+    messages: Vec<Message>,
+    budget: Option<TokenBudget>,
+    /// `None` for the default client.
+    client: Option<LlmClient>,
+    params: QueryParams,
+}
+
+impl std::fmt::Debug for Conversation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The client is left out, as its configuration holds the API key.
+        f.debug_struct("Conversation")
+            .field("messages", &self.messages)
+            .field("budget", &self.budget)
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A summary of the oldest part of a conversation, replacing it in the history.
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Summary {
+    summary: String,
+}
+
+impl Conversation {
+    /// An empty conversation without a token budget.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A conversation continuing from `messages`, such as a history saved from
+    /// [`Conversation::messages`].
+    pub fn with_messages(messages: Vec<Message>) -> Self {
+        Self {
+            messages,
+            ..Self::default()
+        }
+    }
+
+    /// A conversation starting with a developer message.
+    pub fn with_developer_message(content: impl Into<String>) -> Self {
+        Self {
+            messages: vec![Message::developer(content)],
+            ..Self::default()
+        }
+    }
+
+    /// Send the requests with `client` and its configuration instead of the default client.
+    pub fn client(mut self, client: LlmClient) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Send every request with `params`, such as a temperature of 0.
+    pub fn params(mut self, params: QueryParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Send the requests to `model` instead of the one configured for the client.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.params.model = Some(model.into());
+        self
+    }
+
+    /// Trim the oldest messages before each request to keep the prompt within the model's
+    /// context window minus the reserved completion tokens, rather than have OpenAI reject
+    /// it with `context_length_exceeded`.
+    pub fn token_budget(mut self, budget: TokenBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// The history of the conversation.
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Send `content` as the next user message and return the parsed reply, which is kept in
+    /// the history for the following turns. If the request fails, the history is left as it
+    /// was, including messages trimmed to fit the budget, so asking again does not send the
+    /// message twice.
+    pub async fn ask<T>(&mut self, content: impl Into<String>) -> Result<T, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    {
+        let schema = try_get_schema::<T>()?;
+        self.ask_with_schema(content, schema).await
+    }
+
+    /// Ask like [`Conversation::ask`] for a reply following a schema built at runtime, like
+    /// [`crate::query_openai_dynamic`].
+    pub async fn ask_dynamic(
+        &mut self,
+        content: impl Into<String>,
+        schema: Schema,
+    ) -> Result<serde_json::Value, LlmError> {
+        self.ask_with_schema(content, schema).await
+    }
+
+    async fn ask_with_schema<T>(
+        &mut self,
+        content: impl Into<String>,
+        schema: Schema,
+    ) -> Result<T, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a>,
+    {
+        // The history is only replaced once the request succeeds.
+        let mut messages = self.messages.clone();
+        messages.push(Message::user(content));
+        let client = self.client.clone().unwrap_or_default();
+        self.trim(&client, &mut messages, &schema).await?;
+
+        let request = CompletionRequest {
+            messages: messages.clone(),
+            schema,
+            params: self.params.clone(),
+        };
+        let (parsed, content) = repair::complete_and_parse(&client, request).await?;
+        messages.push(Message::assistant(content));
+        self.messages = messages;
+        Ok(parsed)
+    }
+
+    /// Trim `messages` to fit the token budget, if there is one.
+    async fn trim(
+        &self,
+        client: &LlmClient,
+        messages: &mut Vec<Message>,
+        schema: &Schema,
+    ) -> Result<(), LlmError> {
+        let Some(budget) = self.budget else {
+            return Ok(());
+        };
+        let model = self.params.model.as_deref().unwrap_or(&client.config.model);
+        let window = context_window(model)
+            .ok_or_else(|| LlmError::UnknownContextWindow(model.to_string()))?;
+        let limit = window.saturating_sub(budget.reserved_completion_tokens);
+
+        let trimmed = messages_to_trim(messages, schema, limit);
+        if trimmed.is_empty() {
+            return Ok(());
+        }
+
+        match budget.strategy {
+            TrimStrategy::DropOldest => {
+                messages.drain(trimmed);
+            }
+            TrimStrategy::SummarizeOldest => {
+                let summary = self.summarize(client, &messages[trimmed.clone()]).await?;
+                messages.splice(trimmed, [summary]);
+                // The summary itself may still not fit, in which case fall back to dropping.
+                let trimmed = messages_to_trim(messages, schema, limit);
+                messages.drain(trimmed);
+            }
+        }
+        Ok(())
+    }
+
+    async fn summarize(
+        &self,
+        client: &LlmClient,
+        messages: &[Message],
+    ) -> Result<Message, LlmError> {
+        let transcript: String = messages
+            .iter()
+            .map(|message| {
+                let role = serde_json::to_value(message.role).ok();
+                let role = role
+                    .as_ref()
+                    .and_then(|role| role.as_str())
+                    .unwrap_or_default();
+                format!("[{role}] {}\n", message.content)
+            })
+            .collect();
+        let request = vec![
+            Message::developer(
+                "Summarize the following part of a conversation as briefly as possible, \
+                    keeping every fact needed to continue it."
+                    .to_string(),
+            ),
+            Message::user(transcript),
+        ];
+
+        let Summary { summary } = client.query_with_opts(request, self.params.clone()).await?;
+        // Sent as a user message rather than a developer message, so it is not mistaken for
+        // the system prompt and gets folded into the next summary when trimming again.
+        Ok(Message::user(format!(
+            "Summary of the earlier conversation: {summary}"
+        )))
+    }
+}
+
+/// The range of the oldest messages to remove so the prompt fits in `limit` tokens. The
+/// leading developer and system messages and the latest message are always kept, so the
+/// result may still not fit.
+fn messages_to_trim(messages: &[Message], schema: &Schema, limit: usize) -> std::ops::Range<usize> {
+    let start = messages
+        .iter()
+        .position(|message| !message.role.is_instructions())
+        .unwrap_or(messages.len());
+    let last = messages.len().saturating_sub(1).max(start);
+
+    let mut end = start;
+    while end < last {
+        let kept: Vec<Message> = messages[..start]
+            .iter()
+            .chain(&messages[end..])
+            .cloned()
+            .collect();
+        if estimate_prompt_tokens(&kept, Some(schema)) <= limit {
+            break;
+        }
+        end += 1;
+    }
+    start..end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+
+    fn message(role: Role, content: &str) -> Message {
+        Message::new(role, content)
+    }
+
+    #[test]
+    fn test_messages_to_trim() {
+        let schema = crate::get_schema::<Vec<String>>();
+        let mut messages = vec![message(Role::Developer, "You are a helpful assistant")];
+        for i in 0..10 {
+            messages.push(message(
+                Role::User,
+                &format!("Question {i} {}", "word ".repeat(50)),
+            ));
+            messages.push(message(Role::Assistant, &format!("Answer {i}")));
+        }
+        messages.push(message(Role::User, "The latest question"));
+
+        let total = estimate_prompt_tokens(&messages, Some(&schema));
+        assert_eq!(messages_to_trim(&messages, &schema, total), 1..1);
+
+        // Each question takes about 70 tokens and each answer about 7, so freeing 100 tokens
+        // takes two questions.
+        let trimmed = messages_to_trim(&messages, &schema, total - 100);
+        assert_eq!(trimmed, 1..4);
+        let mut kept = messages.clone();
+        kept.drain(trimmed);
+        assert!(matches!(kept[0].role, Role::Developer));
+        assert!(kept[1].content == "Answer 1");
+        assert!(estimate_prompt_tokens(&kept, Some(&schema)) <= total - 100);
+
+        // The system prompt and the latest message are kept even when they do not fit.
+        let trimmed = messages_to_trim(&messages, &schema, 0);
+        assert_eq!(trimmed, 1..messages.len() - 1);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    Config, LlmClient, LlmError, Message, OpenAIChatCompletionQuery, QueryParams, ResponseFormat,
+    prepare_query, try_get_schema,
+};
+
+/// Number of characters of each message to include in a description.
+const MESSAGE_PREVIEW_LENGTH: usize = 200;
+
+/// Describe the request that would be sent for `T`, `messages` and `params` in a
+/// human-readable form, without sending it. Handy for pasting into a bug report or looking
+/// over before a large batch. The request is adapted to the configuration as when it is sent,
+/// such as with the system prompt added. Long messages are truncated and the API key is
+/// redacted.
+pub fn describe_request<T: schemars::JsonSchema + 'static>(
+    messages: Vec<Message>,
+    params: QueryParams,
+) -> Result<String, LlmError> {
+    describe_with::<T>(&LlmClient::default(), messages, params)
+}
+
+fn describe_with<T: schemars::JsonSchema + 'static>(
+    client: &LlmClient,
+    messages: Vec<Message>,
+    params: QueryParams,
+) -> Result<String, LlmError> {
+    let mut query = OpenAIChatCompletionQuery::new(
+        client.config.model.clone(),
+        messages,
+        try_get_schema::<T>()?,
+    )
+    .with_params(params);
+    prepare_query(client, &mut query)?;
+    Ok(describe_query(&query, &client.config))
+}
+
+fn describe_query(query: &OpenAIChatCompletionQuery, config: &Config) -> String {
+    let mut description = format!("POST {}\n", config.chat_completions_url());
+    let api_key_header = match config.api_key {
+        Some(_) if config.azure.is_some() => {
+            description.push_str("api-key: [REDACTED]\n");
+            Some(crate::AZURE_API_KEY_HEADER)
+        }
+        Some(_) => {
+            description.push_str("Authorization: Bearer [REDACTED]\n");
+            Some(reqwest::header::AUTHORIZATION.as_str())
+        }
+        None => None,
+    };
+    for (name, value) in &config.headers {
+        if Some(name.as_str()) == api_key_header {
+            continue;
+        }
+        // Custom headers often authenticate with a proxy, so only the account identifiers
+        // are shown.
+        let value = match name.as_str() {
+            "openai-organization" | "openai-project" => value.to_str().unwrap_or("[INVALID]"),
+            _ => "[REDACTED]",
+        };
+        description.push_str(&format!("{name}: {value}\n"));
+    }
+    description.push_str(&format!("Model: {}\n", query.model));
+
+    // The parameters as sent, which may differ from those given, such as `max_tokens` for
+    // legacy endpoints.
+    let mut params = serde_json::to_value(query).expect("Queries serialize to JSON objects");
+    let params = params
+        .as_object_mut()
+        .expect("Queries serialize to JSON objects");
+    for field in ["model", "messages", "response_format"] {
+        params.remove(field);
+    }
+    if params.is_empty() {
+        description.push_str("Params: none\n");
+    } else {
+        description.push_str("Params:\n");
+        for (name, value) in params {
+            description.push_str(&format!("  {name}: {value}\n"));
+        }
+    }
+
+    description.push_str("Messages:\n");
+    for message in &query.messages {
+        let role = serde_json::to_value(message.role).expect("Roles serialize to strings");
+        let role = role.as_str().unwrap_or_default();
+        let mut content: String = message
+            .content
+            .chars()
+            .take(MESSAGE_PREVIEW_LENGTH)
+            .collect();
+        if content.len() < message.content.len() {
+            content.push_str("...");
+        }
+        description.push_str(&format!("  [{role}] {content}\n"));
+    }
+
+    match &query.response_format {
+        ResponseFormat::JsonSchema { json_schema } => description.push_str(&format!(
+            "Schema: {} (strict: {})\n{}\n",
+            json_schema.name,
+            json_schema.strict,
+            serde_json::to_string_pretty(&json_schema.schema)
+                .expect("Failed to convert schema to JSON")
+        )),
+        ResponseFormat::JsonObject => description.push_str("Schema: none (JSON object mode)\n"),
+        ResponseFormat::Text => description.push_str("Schema: none (text)\n"),
+    }
+
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_query() {
+        let query = OpenAIChatCompletionQuery::new(
+            "gpt-4o-mini".to_string(),
+            vec![
+                Message::developer("Summarize the text"),
+                Message::user("word ".repeat(100)),
+            ],
+            crate::get_schema::<Vec<String>>(),
+        );
+
+        let mut config = Config::builder()
+            .api_key("sk-secret")
+            .model("gpt-4o-mini")
+            .organization("org-123")
+            .header("X-Proxy-Auth", "proxy-secret")
+            .build()
+            .unwrap();
+        let description = describe_query(&query, &config);
+        assert!(description.starts_with("POST https://api.openai.com/v1/chat/completions\n"));
+        assert!(!description.contains("sk-secret"));
+        assert!(description.contains("Model: gpt-4o-mini"));
+        assert!(description.contains("Bearer [REDACTED]"));
+        assert!(description.contains("openai-organization: org-123\n"));
+        assert!(description.contains("x-proxy-auth: [REDACTED]\n"));
+        assert!(!description.contains("proxy-secret"));
+        assert!(description.contains("[developer] Summarize the text\n"));
+        assert!(description.contains(&format!("[user] {}...\n", "word ".repeat(40))));
+        assert!(description.contains(r#""type": "array""#));
+
+        config.api_key = None;
+        let description = describe_query(&query, &config);
+        assert!(!description.contains("Authorization"));
+        assert!(description.contains("Params: none\n"));
+    }
+
+    #[test]
+    fn test_describe_prepared() {
+        let config = Config::builder()
+            .api_key("sk-secret")
+            .model("gpt-4o-mini")
+            .system_prompt("Answer briefly.")
+            .strict(false)
+            .legacy_max_tokens(true)
+            .build()
+            .unwrap();
+        let client = LlmClient::with_http_client(reqwest::Client::new(), config);
+        let params = QueryParams {
+            model: Some("gpt-4o".to_string()),
+            temperature: Some(0.5),
+            max_completion_tokens: Some(100),
+            ..QueryParams::default()
+        };
+
+        let description =
+            describe_with::<Vec<String>>(&client, vec![Message::user("Hello")], params).unwrap();
+        assert!(description.contains("Model: gpt-4o\n"));
+        assert!(description.contains("  temperature: 0.5\n"));
+        assert!(description.contains("  max_tokens: 100\n"));
+        assert!(!description.contains("max_completion_tokens"));
+        assert!(description.contains("] Answer briefly.\n  [user] Hello\n"));
+        assert!(description.contains("(strict: false)"));
+    }
+}
@@ -0,0 +1,256 @@
+/// Everything that can go wrong when querying the API, so callers can tell a rate limit
+/// apart from a rejected schema or an unparseable response and decide whether to retry.
+#[derive(Debug)]
+pub enum LlmError {
+    /// The request could not be sent or the response could not be received.
+    Http(reqwest::Error),
+    /// No response arrived within the timeout of the [`crate::Config`].
+    Timeout(reqwest::Error),
+    /// The API responded with a non-success status code. `error` holds the fields of the
+    /// error in the body, if it has the usual `{"error": {...}}` envelope. `retry_after` is
+    /// how long the `Retry-After` header asked to wait before trying again, if it was sent.
+    ApiStatus {
+        status: u16,
+        body: String,
+        error: Option<Box<ApiError>>,
+        retry_after: Option<std::time::Duration>,
+    },
+    /// The response did not contain any choices.
+    EmptyChoices,
+    /// The model refused to answer, for example for safety reasons. Holds its explanation.
+    Refusal(String),
+    /// The response body was not a valid chat completion.
+    InvalidResponse(serde_json::Error),
+    /// The model stopped at the completion token limit, so the structured output is cut off.
+    /// Raise [`crate::QueryParams::max_completion_tokens`] or ask for a shorter answer.
+    Truncated,
+    /// The structured output in the response did not parse into the requested type.
+    ContentParse(serde_json::Error),
+    /// The structured output contained the same key twice within an object. Only reported
+    /// when enabled with [`crate::set_reject_duplicate_keys`].
+    DuplicateKey(String),
+    /// An array required to be non-empty by the [`crate::ArrayPolicy`] was empty. Holds the
+    /// JSON pointer of the field.
+    EmptyArray(String),
+    /// The response was not JSON, typically an HTML error page from a proxy or load
+    /// balancer in front of the API.
+    UnexpectedContentType {
+        content_type: String,
+        body_snippet: String,
+    },
+    /// A required configuration value is missing. Holds the name of the environment
+    /// variable or builder field.
+    MissingConfig(String),
+    /// The configuration is present but invalid.
+    InvalidConfig(ConfigError),
+    /// A custom schema name does not match `^[a-zA-Z0-9_-]+$` or is longer than 64
+    /// characters.
+    InvalidSchemaName(String),
+    /// The schema name of a type collides with that of another type under
+    /// [`crate::CollisionPolicy::Error`].
+    SchemaNameCollision {
+        name: String,
+        type_name: String,
+        existing_type_name: String,
+    },
+    /// A request parameter is outside the limits of the API, such as too many stop
+    /// sequences.
+    InvalidParameter { name: &'static str, reason: String },
+    /// A strict schema was about to be sent to a model that does not support strict mode,
+    /// under [`crate::StrictMismatch::Error`].
+    StrictUnsupported {
+        model: String,
+        url: String,
+        schema: String,
+    },
+    /// A [`crate::PromptTemplate`] is malformed, such as an unclosed placeholder. Holds the
+    /// reason.
+    InvalidTemplate(String),
+    /// A placeholder of a [`crate::PromptTemplate`] was given no value. Holds its name.
+    MissingVariable(String),
+    /// A job of the Batch API has no results, or one of its requests has no response.
+    /// Holds the reason.
+    Batch(String),
+    /// The context window of a model is not known, so a [`crate::TokenBudget`] cannot be
+    /// kept. Holds the model.
+    UnknownContextWindow(String),
+    /// A field expected in the structured output is missing, such as the sub-object of one
+    /// component of a [`crate::MultiOutput`]. Holds its name.
+    MissingField(String),
+    /// The structured output was rejected by the validation of
+    /// [`crate::query_openai_validated`] on every attempt. Holds the last reason.
+    ValidationFailed(String),
+    /// The content of the response could not be decoded into bytes by
+    /// [`crate::query_openai_bytes`]. Holds the reason.
+    InvalidBinary(String),
+}
+
+impl std::fmt::Display for LlmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LlmError::Http(e) => write!(f, "Error querying api: {e}"),
+            LlmError::Timeout(e) => write!(f, "Request to the api timed out: {e}"),
+            LlmError::ApiStatus { status, body, .. } => {
+                write!(
+                    f,
+                    "Error querying api: HTTP status {status}\nRaw output:\n{body}"
+                )
+            }
+            LlmError::EmptyChoices => write!(f, "No choices in response from OpenAI"),
+            LlmError::Refusal(refusal) => write!(f, "Model refused to answer: {refusal}"),
+            LlmError::InvalidResponse(e) => write!(f, "Invalid response from OpenAI: {e}"),
+            LlmError::Truncated => write!(
+                f,
+                "Response was cut off at the completion token limit, raise max_completion_tokens"
+            ),
+            LlmError::ContentParse(e) => {
+                write!(f, "Response does not match the structured output type: {e}")
+            }
+            LlmError::DuplicateKey(key) => write!(f, "Duplicate key '{key}' in response"),
+            LlmError::EmptyArray(field) => write!(f, "Array '{field}' in response is empty"),
+            LlmError::UnexpectedContentType {
+                content_type,
+                body_snippet,
+            } => write!(
+                f,
+                "Expected a JSON response but got '{content_type}'. Start of body:\n{body_snippet}"
+            ),
+            LlmError::MissingConfig(name) => write!(f, "{name} not set"),
+            LlmError::InvalidConfig(e) => write!(f, "Invalid configuration: {e}"),
+            LlmError::InvalidSchemaName(name) => write!(
+                f,
+                "Schema name '{name}' must match ^[a-zA-Z0-9_-]+$ and be at most 64 characters"
+            ),
+            LlmError::SchemaNameCollision {
+                name,
+                type_name,
+                existing_type_name,
+            } => write!(
+                f,
+                "Schema name '{name}' of {type_name} collides with the name of {existing_type_name}"
+            ),
+            LlmError::InvalidParameter { name, reason } => {
+                write!(f, "Invalid parameter '{name}': {reason}")
+            }
+            LlmError::StrictUnsupported { model, url, schema } => write!(
+                f,
+                "Model '{model}' at {url} does not support strict schema '{schema}'"
+            ),
+            LlmError::InvalidTemplate(reason) => write!(f, "Invalid prompt template: {reason}"),
+            LlmError::MissingVariable(name) => {
+                write!(f, "No value for variable '{name}' of the prompt template")
+            }
+            LlmError::Batch(reason) => write!(f, "Batch failed: {reason}"),
+            LlmError::UnknownContextWindow(model) => {
+                write!(f, "Unknown context window for model '{model}'")
+            }
+            LlmError::MissingField(field) => write!(f, "Missing '{field}' in response from OpenAI"),
+            LlmError::ValidationFailed(reason) => write!(f, "Response failed validation: {reason}"),
+            LlmError::InvalidBinary(reason) => {
+                write!(f, "Response content is not binary data: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LlmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LlmError::Http(e) | LlmError::Timeout(e) => Some(e),
+            LlmError::InvalidResponse(e) | LlmError::ContentParse(e) => Some(e),
+            LlmError::InvalidConfig(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            LlmError::Timeout(e)
+        } else {
+            LlmError::Http(e)
+        }
+    }
+}
+
+impl From<ConfigError> for LlmError {
+    fn from(e: ConfigError) -> Self {
+        match e {
+            ConfigError::Missing(var) => LlmError::MissingConfig(var.to_string()),
+            e => LlmError::InvalidConfig(e),
+        }
+    }
+}
+
+/// The error the API describes in the body of a failed request, to tell failures with the
+/// same status apart. For example, a schema OpenAI rejects fails with the type
+/// `invalid_request_error` and the `param` it rejected.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct ApiError {
+    pub message: String,
+    /// Such as `invalid_request_error` or `rate_limit_error`.
+    #[serde(rename = "type", default)]
+    pub kind: Option<String>,
+    /// The request parameter the error is about, such as `response_format`.
+    #[serde(default)]
+    pub param: Option<String>,
+    /// A more specific reason, such as `context_length_exceeded`.
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+impl ApiError {
+    /// The error in `body`, or `None` if it is not in the `{"error": {...}}` envelope used by
+    /// OpenAI and Anthropic.
+    pub(crate) fn parse(body: &str) -> Option<Self> {
+        #[derive(serde::Deserialize)]
+        struct Envelope {
+            error: ApiError,
+        }
+        serde_json::from_str::<Envelope>(body)
+            .ok()
+            .map(|envelope| envelope.error)
+    }
+}
+
+/// A problem with the configuration, see [`crate::Config::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// A required environment variable is not set.
+    Missing(&'static str),
+    /// The `.env` file could not be loaded.
+    DotEnv(String),
+    /// `OPENAI_BASE_URL` is not an http or https URL.
+    InvalidBaseUrl(String),
+    /// `OPENAI_TIMEOUT_SECS` is not a non-negative number of seconds.
+    InvalidTimeout(String),
+    /// A header name or value is not valid in HTTP. Holds the name of the header.
+    InvalidHeader(String),
+    /// `OPENAI_RPM_LIMIT` or `OPENAI_TPM_LIMIT` is not a whole number per minute.
+    InvalidRateLimit { var: &'static str, value: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Missing(var) => write!(f, "{var} not set"),
+            ConfigError::DotEnv(e) => write!(f, "Failed to load .env file: {e}"),
+            ConfigError::InvalidBaseUrl(url) => {
+                write!(f, "OPENAI_BASE_URL '{url}' is not an http or https URL")
+            }
+            ConfigError::InvalidTimeout(timeout) => {
+                write!(
+                    f,
+                    "OPENAI_TIMEOUT_SECS '{timeout}' is not a number of seconds"
+                )
+            }
+            ConfigError::InvalidHeader(name) => write!(f, "Invalid value for header '{name}'"),
+            ConfigError::InvalidRateLimit { var, value } => {
+                write!(f, "{var} '{value}' is not a whole number per minute")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,62 @@
+/// A value together with the model's reasoning for it. Use it in place of a field's type,
+/// e.g. `word_count: Explained<i64>`, to have the model justify each extraction, which tends
+/// to improve accuracy and makes the result easier to audit.
+///
+/// The reasoning comes first in the schema so the model writes it before settling on the
+/// value.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+// Keep the doc comment above out of the schema sent to the model.
+#[schemars(description = "A value together with the reasoning for it")]
+pub struct Explained<T> {
+    #[schemars(description = "Short reasoning for why the value was chosen")]
+    pub reasoning: String,
+    pub value: T,
+}
+
+impl<T> std::ops::Deref for Explained<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct ExplainedResponse {
+        word_count: Explained<i64>,
+    }
+
+    #[test]
+    fn test_explained_schema() {
+        let schema = crate::get_schema::<ExplainedResponse>().schema;
+        let explained = &schema["$defs"]["Explained_for_int64"];
+        assert_eq!(explained["additionalProperties"], false);
+        assert_eq!(
+            explained["required"],
+            serde_json::json!(["reasoning", "value"])
+        );
+        assert_eq!(explained["properties"]["value"]["type"], "integer");
+        let properties: Vec<&String> = explained["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect();
+        assert_eq!(properties, ["reasoning", "value"]);
+
+        let response: ExplainedResponse = serde_json::from_value(serde_json::json!({
+            "word_count": { "reasoning": "Two words separated by a space", "value": 2 },
+        }))
+        .unwrap();
+        assert_eq!(*response.word_count, 2);
+        assert_eq!(
+            response.word_count.reasoning,
+            "Two words separated by a space"
+        );
+    }
+}
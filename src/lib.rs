@@ -1,351 +1,3572 @@
-static CLIENT: std::sync::LazyLock<reqwest::Client> =
-    std::sync::LazyLock::new(reqwest::Client::new);
+static CLIENT: std::sync::RwLock<Option<reqwest::Client>> = std::sync::RwLock::new(None);
 static CONFIG: std::sync::LazyLock<Config> = std::sync::LazyLock::new(Config::new);
+static SCHEMA_PREFIX: std::sync::RwLock<String> = std::sync::RwLock::new(String::new());
+/// Generated schemas by type, see [`try_get_schema`].
+static SCHEMA_CACHE: std::sync::LazyLock<
+    std::sync::RwLock<std::collections::HashMap<std::any::TypeId, serde_json::Value>>,
+> = std::sync::LazyLock::new(Default::default);
+/// Schema names set with [`set_schema_name`], by type.
+static SCHEMA_NAMES: std::sync::LazyLock<
+    std::sync::RwLock<std::collections::HashMap<std::any::TypeId, String>>,
+> = std::sync::LazyLock::new(Default::default);
+static SCHEMA_REGISTRY: std::sync::LazyLock<std::sync::Mutex<registry::SchemaRegistry>> =
+    std::sync::LazyLock::new(Default::default);
+static COLLISION_POLICY: std::sync::RwLock<CollisionPolicy> =
+    std::sync::RwLock::new(CollisionPolicy::Allow);
+static CACHE: std::sync::RwLock<Option<std::sync::Arc<dyn Cache>>> = std::sync::RwLock::new(None);
+static REJECT_DUPLICATE_KEYS: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+static STRICT_SUPPORT: std::sync::RwLock<(StrictSupport, StrictMismatch)> =
+    std::sync::RwLock::new((StrictSupport::new(), StrictMismatch::Warn));
+static ARRAY_POLICY: std::sync::RwLock<ArrayPolicy> = std::sync::RwLock::new(ArrayPolicy::new());
+static ROLE_NAMES: std::sync::RwLock<RoleNames> = std::sync::RwLock::new(RoleNames::new());
+static FLOAT_PRECISION: std::sync::RwLock<FloatPrecision> =
+    std::sync::RwLock::new(FloatPrecision::new());
+static REPAIR_ATTEMPTS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+static RETRY_POLICY: std::sync::LazyLock<std::sync::RwLock<RetryPolicy>> =
+    std::sync::LazyLock::new(Default::default);
+
+mod azure;
+mod batch;
+mod batches;
+mod benchmark;
+mod binary;
+mod cache;
+mod choices;
+mod claude;
+mod client;
+mod content;
+mod conversation;
+mod describe;
+mod error;
+mod explained;
+mod logprobs;
+mod metadata;
+mod mock;
+mod multi;
+mod parse;
+mod pricing;
+mod provider;
+mod rate_limit;
+mod registry;
+mod repair;
+mod request;
+mod responses;
+mod retry;
+mod roles;
+mod schema_check;
+mod stream;
+mod strict;
+mod template;
+mod tokens;
+mod tools;
+mod two_phase;
+mod validate;
+
+pub mod tasks;
+
+pub use azure::AzureConfig;
+pub use batch::query_openai_batch;
+pub use batches::{Batch, BatchRequestCounts, query_openai_batch_job};
+pub use benchmark::{BenchmarkResult, benchmark};
+pub use binary::query_openai_bytes;
+pub use cache::{Cache, MemoryCache};
+pub use choices::query_openai_n;
+pub use claude::{ClaudeConfig, ClaudeProvider};
+pub use client::LlmClient;
+pub use content::{ContentPart, ImageUrl};
+pub use conversation::{Conversation, TokenBudget, TrimStrategy};
+pub use describe::describe_request;
+pub use error::{ApiError, ConfigError, LlmError};
+pub use explained::Explained;
+pub use logprobs::{
+    Logprobs, TokenLogprob, TopLogprob, WithLogprobs, char_logprobs, query_openai_with_logprobs,
+};
+pub use metadata::{WithMetadata, query_openai_with_metadata};
+pub use mock::MockProvider;
+pub use multi::{MultiOutput, query_openai_multi};
+pub use parse::{ArrayPolicy, FloatPrecision};
+pub use pricing::{ModelPrice, Pricing};
+pub use provider::{CompletionRequest, Provider, query_with_provider};
+pub use rate_limit::RateLimit;
+pub use registry::CollisionPolicy;
+pub use request::Request;
+pub use responses::ResponsesProvider;
+pub use retry::{RetryDecision, RetryPolicy, RetryStats};
+pub use roles::{InstructionRole, RoleNames};
+pub use schema_check::{SchemaIssue, check_field_names, validate_schema};
+pub use stream::{
+    collect_structured, query_openai_stream, query_openai_stream_partial,
+    query_openai_stream_records,
+};
+pub use strict::{ResponseMode, StrictMismatch, StrictSupport};
+pub use template::PromptTemplate;
+pub use tokens::{
+    context_window, estimate_prompt_tokens, estimate_tokens, fits_context, remaining_context,
+};
+pub use tools::{FunctionCall, ToolCall, ToolChoice, ToolDef, ToolResponse, query_openai_tools};
+pub use two_phase::{
+    TwoPhase, TwoPhasePrompts, query_openai_two_phase, query_openai_two_phase_with,
+};
+pub use validate::{query_openai_validated, query_validated_with_provider};
 
 /// Create an OpenAI compatible schema from a Rust type. Utilizes a diagnostic version of the
 /// desired response schema's type name for the schema name sent to OpenAI.
-pub fn get_schema<T: schemars::JsonSchema>() -> Schema {
-    let schema = schemars::generate::SchemaSettings::default()
-        // The schema generator automatically adds "format" to the items specifying
-        // for example int64 or double.
-        // OpenAI does not support this.
-        .with_transform(schemars::transform::RecursiveTransform(
-            |schema: &mut schemars::Schema| {
-                schema.remove("format");
-            },
-        ))
-        .into_generator()
-        .into_root_schema_for::<T>();
-    let schema = serde_json::to_value(schema).expect("Failed to convert schema to JSON value");
+///
+/// Panics if the schema name collides with that of another type under
+/// [`CollisionPolicy::Error`]. Use [`try_get_schema`] to handle this instead, or
+/// [`get_schema_with_opts`] to choose the name or turn off strict mode.
+///
+/// Property names follow the `#[serde(rename)]` and `#[serde(rename_all)]` attributes of the
+/// type, so they match what serde deserializes. Avoid `#[schemars]` renames that disagree
+/// with them, which [`check_field_names`] detects.
+pub fn get_schema<T: schemars::JsonSchema + 'static>() -> Schema {
+    try_get_schema::<T>().expect("Schema name collision")
+}
 
-    // We need a name for the schema. Get the type name and ensure it
-    // is compatible with OpenAI as per the regex "^[a-zA-Z0-9_-]+$"
-    let name = std::any::type_name::<T>()
-        .to_string()
-        .replace("::", "_")
-        .replace("<", "_")
-        .replace(">", "_");
+/// The `json_schema` object (name, schema and strict flag) that would be sent for `T`, after
+/// all transforms. Paste it into the OpenAI playground or another language's SDK to reuse
+/// the schema outside this crate.
+///
+/// Panics on a schema name collision, like [`get_schema`].
+pub fn canonical_openai_schema<T: schemars::JsonSchema + 'static>() -> serde_json::Value {
+    serde_json::to_value(get_schema::<T>()).expect("Failed to convert schema to JSON value")
+}
 
-    Schema {
-        name,
-        schema,
-        strict: true,
-    }
+/// Create an OpenAI compatible schema from a Rust type, like [`get_schema`], returning an
+/// error if its name collides with that of another type under [`CollisionPolicy::Error`].
+///
+/// The schema of each type is only generated once and reused by later calls.
+pub fn try_get_schema<T: schemars::JsonSchema + 'static>() -> Result<Schema, LlmError> {
+    get_schema_with_opts::<T>(&SchemaOptions::default())
 }
 
-/// Query OpenAI with a message and a schema defined by the generic type T. The schema
-/// is used to enforce structured output from the OpenAI API and parse the response into
-/// said Rust type.
-pub async fn query_openai<T>(messages: Vec<Message>) -> T
-where
-    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema,
-{
-    let schema = get_schema::<T>();
-    let response = query_openai_inner(messages, schema)
-        .await
-        .expect("Response from OpenAI");
-
-    // The response is inside a string field, so we first need to parse the
-    // entire response and then pick out the content field to parse separately
-    // into our structured output type.
-    serde_json::from_str(
-        &response
-            .choices
-            .get(0)
-            .expect("Response from OpenAI")
-            .message
-            .content,
-    )
-    .expect("Correctly structured parseable response")
+/// Create an OpenAI compatible schema from a Rust type like [`try_get_schema`], named `name`
+/// rather than after the path of the type. Fails if `name` does not match
+/// `^[a-zA-Z0-9_-]+$` with at most 64 characters.
+pub fn get_schema_named<T: schemars::JsonSchema + 'static>(name: &str) -> Result<Schema, LlmError> {
+    get_schema_with_opts::<T>(&SchemaOptions {
+        name: Some(name.to_string()),
+        ..SchemaOptions::default()
+    })
 }
 
-/// Query the OpenAI API with a message and a schema.
-async fn query_openai_inner(
-    messages: Vec<Message>,
-    schema: Schema,
-) -> anyhow::Result<OpenAIChatCompletionResponse> {
-    let query = OpenAIChatCompletionQuery {
-        model: CONFIG.model.clone(), // E.g. "o3-mini-2025-01-31"
-        messages,
-        response_format: ResponseFormat {
-            // Always set to json_schema when using structured outputs
-            response_type: "json_schema".to_string(),
-            json_schema: schema,
-        },
-    };
+/// Name the schema of `T` `name` wherever it is created without a custom name, including by
+/// the query functions, instead of deriving a name like `my_crate_module_MyType` from its
+/// path. Like names set with [`get_schema_named`], it is used as is, without the prefix
+/// set with [`set_schema_prefix`]. Fails if `name` is not a valid schema name.
+///
+/// A `#[schemars(title)]` attribute is not used for the name, as schemars gives every
+/// schema a title.
+pub fn set_schema_name<T: ?Sized + 'static>(name: impl Into<String>) -> Result<(), LlmError> {
+    let name = name.into();
+    validate_schema_name(&name)?;
+    SCHEMA_NAMES
+        .write()
+        .expect("Schema names lock poisoned")
+        .insert(std::any::TypeId::of::<T>(), name);
+    Ok(())
+}
 
-    let response = CLIENT
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(CONFIG.api_key.clone())
-        .json(&query)
-        .send()
-        .await?;
+/// How [`get_schema_with_opts`] creates a schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaOptions {
+    /// Whether OpenAI enforces the schema exactly. Some OpenAI-compatible backends, and
+    /// schemas using features strict mode does not allow, need this off. Defaults to `true`.
+    ///
+    /// Strict mode does not support constraints such as `minimum`, `maxLength` or
+    /// `pattern`, so they are removed from strict schemas. Check ranges and lengths after
+    /// parsing instead, for example with [`query_openai_validated`].
+    ///
+    /// Turning strict mode off with `OPENAI_STRICT` or [`ConfigBuilder::strict`] overrides
+    /// this when the request is sent, not when the schema is created.
+    pub strict: bool,
+    /// The schema name to send instead of one derived from the type name. Used as is,
+    /// without the prefix set with [`set_schema_prefix`], and must match `^[a-zA-Z0-9_-]+$`
+    /// with at most 64 characters.
+    pub name: Option<String>,
+    /// Replace every `$ref` to a definition with the definition itself and drop `$defs`,
+    /// for backends that cannot resolve references. Definitions of recursive types cannot
+    /// be inlined, so they are kept in `$defs` and referenced as before. Defaults to
+    /// `false`.
+    pub inline_definitions: bool,
+}
 
-    if let Err(e) = response.error_for_status_ref() {
-        anyhow::bail!(
-            "Error querying api: {e}\nRaw output:\n{}",
-            response.text().await.unwrap()
-        );
+impl Default for SchemaOptions {
+    fn default() -> Self {
+        Self {
+            strict: true,
+            name: None,
+            inline_definitions: false,
+        }
     }
-
-    Ok(response.json().await?)
 }
 
-#[derive(Debug, serde::Serialize)]
-struct OpenAIChatCompletionQuery {
-    model: String,
-    messages: Vec<Message>,
-    response_format: ResponseFormat,
-}
+/// Create an OpenAI compatible schema from a Rust type like [`try_get_schema`], with a
+/// custom name or without strict mode. Also fails if the custom name is invalid.
+pub fn get_schema_with_opts<T: schemars::JsonSchema + 'static>(
+    options: &SchemaOptions,
+) -> Result<Schema, LlmError> {
+    // Keyed by `TypeId`, as type names are not guaranteed to be unique.
+    let type_id = std::any::TypeId::of::<T>();
+    let type_name = std::any::type_name::<T>();
+    let cached = SCHEMA_CACHE
+        .read()
+        .expect("Schema cache lock poisoned")
+        .get(&type_id)
+        .cloned();
+    let generated = cached.is_none();
+    let mut schema = match cached {
+        Some(schema) => schema,
+        None => {
+            let schema = generate_schema::<T>();
+            SCHEMA_CACHE
+                .write()
+                .expect("Schema cache lock poisoned")
+                .insert(type_id, schema.clone());
+            schema
+        }
+    };
+    if options.inline_definitions {
+        inline_definitions(&mut schema);
+    }
+    if options.strict {
+        schema_check::strip_constraints(&mut schema);
+    }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct ResponseFormat {
-    #[serde(rename = "type")]
-    response_type: String,
-    json_schema: Schema,
-}
+    let custom_name = SCHEMA_NAMES
+        .read()
+        .expect("Schema names lock poisoned")
+        .get(&type_id)
+        .cloned();
+    let name = match (&options.name, custom_name) {
+        (Some(name), _) => {
+            validate_schema_name(name)?;
+            name.clone()
+        }
+        (None, Some(name)) => name,
+        (None, None) => schema_name(
+            type_name,
+            &SCHEMA_PREFIX.read().expect("Schema prefix lock poisoned"),
+        ),
+    };
+    let name = SCHEMA_REGISTRY
+        .lock()
+        .expect("Schema registry lock poisoned")
+        .register(
+            name,
+            type_id,
+            type_name,
+            *COLLISION_POLICY
+                .read()
+                .expect("Collision policy lock poisoned"),
+        )?;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Schema {
-    name: String,
-    schema: serde_json::Value,
-    strict: bool,
+    let schema = Schema {
+        name,
+        schema,
+        strict: options.strict,
+    };
+    // Catch schemas OpenAI would reject while developing, without the cost in release
+    // builds.
+    if cfg!(debug_assertions)
+        && generated
+        && schema.strict
+        && let Err(issues) = validate_schema(&schema)
+    {
+        for issue in issues {
+            tracing::warn!(
+                schema = %schema.name,
+                %issue,
+                "Strict schema will be rejected by OpenAI"
+            );
+        }
+    }
+    Ok(schema)
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Message {
-    role: Role,
-    content: String,
+/// Check that `name` is accepted by OpenAI as a schema name.
+fn validate_schema_name(name: &str) -> Result<(), LlmError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_SCHEMA_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(LlmError::InvalidSchemaName(name.to_string()))
+    }
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum Role {
-    Developer,
-    User,
-    Assistant,
+/// Generate the JSON schema of `T` with all transforms applied.
+fn generate_schema<T: schemars::JsonSchema>() -> serde_json::Value {
+    let schema = schemars::generate::SchemaSettings::default()
+        .with_transform(schemars::transform::RecursiveTransform(openai_transform))
+        .into_generator()
+        .into_root_schema_for::<T>();
+    serde_json::to_value(schema).expect("Failed to convert schema to JSON value")
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-struct OpenAIChatCompletionResponse {
-    choices: Vec<Choice>,
-    // There are a bunch of extra fields in the response
-    // that we don't care about. See the OpenAI API docs.
+/// Set what happens when two different types get the same schema name. Types are only
+/// tracked while the policy is not [`CollisionPolicy::Allow`], the default.
+pub fn set_collision_policy(policy: CollisionPolicy) {
+    *COLLISION_POLICY
+        .write()
+        .expect("Collision policy lock poisoned") = policy;
 }
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-struct Choice {
-    message: ResponseMessage,
-}
+/// Adapt a (sub)schema generated by schemars to the subset of JSON schema OpenAI supports.
+/// Applied recursively, including to the entries of `$defs` that recursive and reused types
+/// are referenced from.
+fn openai_transform(schema: &mut schemars::Schema) {
+    // The schema generator automatically adds "format" to the items specifying
+    // for example int64 or double.
+    // OpenAI does not support this.
+    schema.remove("format");
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-struct ResponseMessage {
-    content: String,
+    if let Some(schema) = schema.as_object_mut() {
+        one_of_to_any_of(schema);
+        normalize_fixed_length_array(schema);
+        forbid_additional_properties(schema);
+        require_all_properties(schema);
+    }
 }
 
-struct Config {
-    api_key: String,
-    model: String,
+/// Strict mode does not support `oneOf`, which schemars uses for enums with data. Its
+/// branches are objects with different required keys or strings and each is normalized on
+/// its own, so at most one of them matches and `anyOf` means the same.
+fn one_of_to_any_of(schema: &mut serde_json::Map<String, serde_json::Value>) {
+    if schema.contains_key("anyOf") {
+        return;
+    }
+    if let Some(variants) = schema.remove("oneOf") {
+        schema.insert("anyOf".to_string(), variants);
+    }
 }
 
-impl Config {
-    fn new() -> Self {
-        dotenvy::dotenv().expect("Failed to load .env file");
-        Self {
-            api_key: std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY not set"),
-            model: std::env::var("OPENAI_MODEL").expect("OPENAI_MODEL not set"),
+/// Strict mode requires every property of an object to be required. Optional fields can
+/// still be expressed as `Option<T>`, which schemars turns into a type that also allows
+/// `null`, so that the model answers `null` rather than leaving the field out.
+///
+/// Optional fields whose schema does not allow `null` yet, for example because of
+/// `#[schemars(with = ...)]`, are made nullable, unless they have a default other than
+/// `null`. Those are simply required, as `null` would not deserialize into them.
+fn require_all_properties(schema: &mut serde_json::Map<String, serde_json::Value>) {
+    let previously_required = match schema.get("required") {
+        Some(serde_json::Value::Array(required)) => required.clone(),
+        _ => Vec::new(),
+    };
+    let Some(serde_json::Value::Object(properties)) = schema.get_mut("properties") else {
+        return;
+    };
+
+    for (key, property) in properties.iter_mut() {
+        let optional = !previously_required.contains(&serde_json::Value::String(key.clone()));
+        let default_null = property
+            .get("default")
+            .is_none_or(serde_json::Value::is_null);
+        if optional && default_null {
+            make_nullable(property);
         }
     }
+    let required = properties
+        .keys()
+        .cloned()
+        .map(serde_json::Value::String)
+        .collect();
+    schema.insert("required".to_string(), serde_json::Value::Array(required));
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
-    #[serde(deny_unknown_fields)]
-    struct SimpleResponseSchema {
-        #[schemars(description = "Summary of the text in two extremely short paragraphs")]
-        summary: Vec<String>,
+/// Allow `null` in addition to the values `schema` accepts, in the `["string", "null"]`
+/// form where possible and with `anyOf` otherwise.
+fn make_nullable(schema: &mut serde_json::Value) {
+    let null_schema = serde_json::json!({ "type": "null" });
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+    let accepts_null = match object.get("type") {
+        Some(serde_json::Value::String(t)) => t == "null",
+        Some(serde_json::Value::Array(types)) => types.contains(&"null".into()),
+        _ => ["anyOf", "oneOf"].iter().any(|key| {
+            object
+                .get(*key)
+                .and_then(serde_json::Value::as_array)
+                .is_some_and(|variants| variants.contains(&null_schema))
+        }),
+    };
+    if accepts_null {
+        return;
+    }
 
-        #[schemars(description = "Tone of the text")]
-        tone: String,
+    match object.get_mut("type") {
+        Some(serde_json::Value::String(t)) => {
+            let t = std::mem::take(t);
+            object.insert("type".to_string(), serde_json::json!([t, "null"]));
+            if let Some(serde_json::Value::Array(values)) = object.get_mut("enum") {
+                values.push(serde_json::Value::Null);
+            }
+        }
+        Some(serde_json::Value::Array(types)) => types.push("null".into()),
+        _ => {
+            // Annotations describe the field as a whole, so they stay outside of the union.
+            let annotations: Vec<_> = ["description", "default"]
+                .into_iter()
+                .filter_map(|key| object.remove_entry(key))
+                .collect();
+            let inner = std::mem::take(object);
+            object.insert(
+                "anyOf".to_string(),
+                serde_json::json!([serde_json::Value::Object(inner), null_schema]),
+            );
+            object.extend(annotations);
+        }
+    }
+}
 
-        #[schemars(description = "Number of words in the text")]
-        word_count: i64,
+/// Strict mode requires every object to forbid properties beyond the listed ones, which
+/// schemars only does for types with `#[serde(deny_unknown_fields)]`. Objects that allow
+/// additional properties of some schema, such as a flattened map, are left as they are.
+fn forbid_additional_properties(schema: &mut serde_json::Map<String, serde_json::Value>) {
+    if schema.contains_key("properties") {
+        schema
+            .entry("additionalProperties")
+            .or_insert(serde_json::Value::Bool(false));
+    }
+}
 
-        #[schemars(description = "Flair from 0 to 1")]
-        flair: f64,
+/// Tuples and fixed-size arrays produce `prefixItems` and equal `minItems`/`maxItems`, which
+/// OpenAI rejects. Replace them with a plain `items` schema and describe the length instead,
+/// appended to any description of the field.
+///
+/// The types of the positions of a heterogeneous tuple such as `(String, i64)` are lost:
+/// `items` becomes an `anyOf` of them, so `[1, "a"]` is valid for the schema and only fails
+/// when deserializing. The description names the type of each position to steer the model,
+/// but prefer a struct with named fields where the order matters. The length and the item
+/// types are still checked when deserializing.
+fn normalize_fixed_length_array(schema: &mut serde_json::Map<String, serde_json::Value>) {
+    let mut positions = None;
+    if let Some(serde_json::Value::Array(prefix_items)) = schema.remove("prefixItems") {
+        let mut distinct: Vec<serde_json::Value> = Vec::new();
+        for item in &prefix_items {
+            if !distinct.contains(item) {
+                distinct.push(item.clone());
+            }
+        }
+        let items = match distinct.len() {
+            1 => distinct.remove(0),
+            _ => {
+                positions = Some(
+                    prefix_items
+                        .iter()
+                        .map(position_type)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                );
+                serde_json::json!({ "anyOf": distinct })
+            }
+        };
+        schema.insert("items".to_string(), items);
     }
 
-    #[tokio::test]
-    async fn test_simple_schema() {
-        let response = query_openai::<SimpleResponseSchema>(vec![Message {
-            role: Role::User,
-            content: "Hello, world!".to_string(),
-        }])
-        .await;
+    let (Some(min), Some(max)) = (schema.get("minItems"), schema.get("maxItems")) else {
+        return;
+    };
+    if min != max {
+        return;
+    }
+    let mut length = format!("Exactly {min} items");
+    if let Some(positions) = positions {
+        length.push_str(&format!(", in this order: {positions}"));
+    }
+    schema.remove("minItems");
+    schema.remove("maxItems");
+    let description = match schema.get("description").and_then(|value| value.as_str()) {
+        Some(description) if !description.is_empty() => {
+            format!("{}. {length}", description.trim_end_matches('.'))
+        }
+        _ => length,
+    };
+    schema.insert("description".to_string(), description.into());
+}
 
-        assert!(response.summary.len() > 0);
-        assert!(response.tone.len() > 0);
-        assert!(response.word_count > 0);
-        assert!(response.flair >= 0.0 && response.flair <= 1.0);
+/// A short name for the type of a tuple position, such as `string` or the name of a
+/// referenced definition, for describing the order of the positions.
+fn position_type(item: &serde_json::Value) -> String {
+    if let Some(kind) = item.get("type").and_then(|kind| kind.as_str()) {
+        return kind.to_string();
+    }
+    if let Some(reference) = item.get("$ref").and_then(|reference| reference.as_str()) {
+        return reference
+            .rsplit('/')
+            .next()
+            .unwrap_or(reference)
+            .to_string();
     }
+    "any".to_string()
+}
 
-    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
-    enum Sentiment {
-        Positive,
-        Neutral,
-        Negative,
+/// Prepare a root schema for embedding inside another schema by removing its `$schema`
+/// marker and returning its `$defs`, which must be hoisted to the new root for the
+/// `#/$defs/...` references to keep resolving.
+fn take_definitions(schema: &mut serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+    let Some(object) = schema.as_object_mut() else {
+        return serde_json::Map::new();
+    };
+    object.remove("$schema");
+    match object.remove("$defs") {
+        Some(serde_json::Value::Object(defs)) => defs,
+        _ => serde_json::Map::new(),
     }
+}
 
-    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
-    #[serde(deny_unknown_fields)]
-    struct EnumResponseSchema {
-        #[schemars(description = "Summary of the text")]
-        summary: String,
+/// The prefix of references to definitions in the root `$defs`.
+const DEFINITION_REF_PREFIX: &str = "#/$defs/";
 
-        #[schemars(description = "Sentiment of the text")]
-        sentiment: Sentiment,
+/// Inline the definitions `schema` references, keeping only those of recursive types in
+/// `$defs`, see [`SchemaOptions::inline_definitions`].
+fn inline_definitions(schema: &mut serde_json::Value) {
+    let Some(serde_json::Value::Object(mut defs)) = schema
+        .as_object_mut()
+        .and_then(|object| object.remove("$defs"))
+    else {
+        return;
+    };
 
-        #[schemars(description = "Number of words in the text")]
-        word_count: i64,
-    }
+    let references: std::collections::HashMap<String, Vec<String>> = defs
+        .iter()
+        .map(|(name, def)| {
+            let mut refs = Vec::new();
+            collect_references(def, &mut refs);
+            (name.clone(), refs)
+        })
+        .collect();
+    let recursive: std::collections::HashSet<String> = defs
+        .keys()
+        .filter(|name| reaches(name, name, &references, &mut Default::default()))
+        .cloned()
+        .collect();
 
-    #[tokio::test]
-    async fn test_enum_schema() {
-        let response = query_openai::<EnumResponseSchema>(vec![Message {
-            role: Role::User,
-            content: "I'm having a wonderful day today!".to_string(),
-        }])
-        .await;
+    // Inline into the recursive definitions first, so they only reference each other.
+    let mut kept = serde_json::Map::new();
+    for name in defs.keys().filter(|name| recursive.contains(*name)) {
+        let mut def = defs[name].clone();
+        inline_references(&mut def, &defs, &recursive);
+        kept.insert(name.clone(), def);
+    }
+    defs.retain(|name, _| !recursive.contains(name));
 
-        assert!(response.summary.len() > 0);
-        assert!(response.word_count > 0);
-        // No need to validate the enum sentiment since the query will fail if it is not
-        // one of the values.
+    inline_references(schema, &defs, &recursive);
+    if !kept.is_empty() {
+        schema["$defs"] = serde_json::Value::Object(kept);
     }
+}
 
-    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
-    #[serde(deny_unknown_fields)]
-    struct NestedResponseSchema {
-        #[schemars(description = "A list of responses to the message")]
-        responses: Vec<SimpleResponseSchema>,
+/// Collect the names of the definitions `schema` references directly.
+fn collect_references(schema: &serde_json::Value, refs: &mut Vec<String>) {
+    match schema {
+        serde_json::Value::Object(object) => {
+            if let Some(name) = object
+                .get("$ref")
+                .and_then(serde_json::Value::as_str)
+                .and_then(|reference| reference.strip_prefix(DEFINITION_REF_PREFIX))
+            {
+                refs.push(name.to_string());
+            }
+            object
+                .values()
+                .for_each(|value| collect_references(value, refs));
+        }
+        serde_json::Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_references(value, refs)),
+        _ => {}
     }
+}
 
-    #[tokio::test]
-    async fn test_nested_schema() {
-        let responses = query_openai::<NestedResponseSchema>(vec![Message {
-            role: Role::User,
-            content: "Hello, world! Reply with at least 3 different responses".to_string(),
-        }])
-        .await;
-        assert!(responses.responses.len() >= 3);
+/// Whether the definition `from` references `to`, directly or through other definitions.
+fn reaches(
+    from: &str,
+    to: &str,
+    references: &std::collections::HashMap<String, Vec<String>>,
+    visited: &mut std::collections::HashSet<String>,
+) -> bool {
+    references.get(from).into_iter().flatten().any(|next| {
+        next == to || (visited.insert(next.clone()) && reaches(next, to, references, visited))
+    })
+}
 
-        for response in responses.responses {
-            assert!(response.summary.len() > 0);
-            // assert!(response.tone.len() > 0);
-            // assert!(response.word_count > 0);
-            // assert!(response.flair >= 0.0 && response.flair <= 1.0);
+/// Replace references to the non-recursive `defs` within `schema` with the definitions,
+/// keeping keywords next to the reference such as a description.
+fn inline_references(
+    schema: &mut serde_json::Value,
+    defs: &serde_json::Map<String, serde_json::Value>,
+    recursive: &std::collections::HashSet<String>,
+) {
+    match schema {
+        serde_json::Value::Object(object) => {
+            let name = object
+                .get("$ref")
+                .and_then(serde_json::Value::as_str)
+                .and_then(|reference| reference.strip_prefix(DEFINITION_REF_PREFIX))
+                .filter(|name| !recursive.contains(*name))
+                .map(str::to_string);
+            if let Some(def) = name.and_then(|name| defs.get(&name)) {
+                object.remove("$ref");
+                let siblings = std::mem::take(object);
+                let serde_json::Value::Object(def) = def.clone() else {
+                    return;
+                };
+                object.extend(def);
+                object.extend(siblings);
+            }
+            object
+                .values_mut()
+                .for_each(|value| inline_references(value, defs, recursive));
         }
+        serde_json::Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| inline_references(value, defs, recursive)),
+        _ => {}
     }
+}
 
-    /// Represents a complex schema that tests most features of the schema.
-    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
-    #[serde(deny_unknown_fields)]
-    struct ComplexResponseSchema {
-        #[schemars(description = "Summary of the text")]
-        summary: String,
+/// Maximum length of a schema name accepted by OpenAI.
+const MAX_SCHEMA_NAME_LENGTH: usize = 64;
 
-        #[schemars(description = "Number of words in the text")]
-        word_count: i64,
+/// Set a namespace prefix, e.g. `myapp_`, that is prepended to the name of every schema
+/// created by [`get_schema`]. Useful for telling apart schemas from different services
+/// sharing an OpenAI account. The prefix must only use characters valid in a schema name
+/// and be shorter than 64 characters, otherwise this fails with
+/// [`LlmError::InvalidSchemaName`]. An empty prefix removes it again.
+pub fn set_schema_prefix(prefix: impl Into<String>) -> Result<(), LlmError> {
+    let prefix = prefix.into();
+    // Every name derived with the prefix must be valid, so it is checked like a name
+    // followed by at least one character of the type name.
+    if !prefix.is_empty() && validate_schema_name(&format!("{prefix}_")).is_err() {
+        return Err(LlmError::InvalidSchemaName(prefix));
+    }
 
-        #[schemars(description = "Flair from 0 to 1")]
-        flair: f64,
+    *SCHEMA_PREFIX.write().expect("Schema prefix lock poisoned") = prefix;
+    Ok(())
+}
 
-        #[schemars(description = "A list of responses to the message")]
-        responses: Vec<SimpleResponseSchema>,
+/// Build the schema name from the prefix and the type name.
+fn schema_name(type_name: &str, prefix: &str) -> String {
+    // We need a name for the schema. Get the type name and ensure it
+    // is compatible with OpenAI as per the regex "^[a-zA-Z0-9_-]+$"
+    let name: String = format!("{prefix}{}", type_name.replace("::", "_"))
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    if name.len() <= MAX_SCHEMA_NAME_LENGTH {
+        return name;
+    }
 
-        #[schemars(description = "A list of sentiments to the message")]
-        sentiments: Vec<Sentiment>,
+    // Truncated names of different types often share their start, e.g. for generic types
+    // with long paths, so end them with a hash of the full name to keep them apart.
+    let hash = format!("_{:08x}", fnv1a(name.as_bytes()));
+    name[..MAX_SCHEMA_NAME_LENGTH - hash.len()].to_string() + &hash
+}
 
-        // No description is allowed when the type is an object
-        object_in_object: SimpleResponseSchema,
+/// Turn `name` into a schema name OpenAI accepts, the way names are derived from type names:
+/// characters outside `[a-zA-Z0-9_-]` become underscores and names longer than 64
+/// characters are shortened, ending in a hash of the full name.
+pub fn sanitize_schema_name(name: &str) -> String {
+    schema_name(name, "")
+}
+
+/// The 32-bit FNV-1a hash, which unlike the standard library's hashers is stable across
+/// processes and versions, as schema names must be.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x01000193)
+    })
+}
+
+/// Set the policy deciding which failed requests are retried. See [`RetryPolicy`].
+pub fn set_retry_policy(policy: RetryPolicy) {
+    *RETRY_POLICY.write().expect("Retry policy lock poisoned") = policy;
+}
+
+/// The shared HTTP client, created on first use and again after [`shutdown`].
+fn client() -> reqwest::Client {
+    if let Some(client) = CLIENT.read().expect("Client lock poisoned").as_ref() {
+        return client.clone();
     }
+    CLIENT
+        .write()
+        .expect("Client lock poisoned")
+        .get_or_insert_with(reqwest::Client::new)
+        .clone()
+}
 
-    #[tokio::test]
-    async fn test_schemars_default_schema() {
-        let schema = Schema {
-            name: "ComplexResponseSchema".to_string(),
-            schema: serde_json::to_value(schemars::schema_for!(ComplexResponseSchema))
-                .expect("Failed to convert schema to JSON value"),
-            strict: true,
-        };
+/// Drop the shared HTTP client and with it the pooled connections, once any requests still
+/// in flight have finished. Useful for graceful shutdown and leak detection in tests. Safe to
+/// call more than once, and any later query creates a new client.
+pub fn shutdown() {
+    CLIENT.write().expect("Client lock poisoned").take();
+}
 
-        let messages = vec![Message {
-            role: Role::User,
-            content: "Hello, world!".to_string(),
-        }];
+/// Reject structured output containing the same key twice within an object with
+/// [`LlmError::DuplicateKey`], instead of silently keeping the last value. Disabled by
+/// default.
+pub fn set_reject_duplicate_keys(reject: bool) {
+    REJECT_DUPLICATE_KEYS.store(reject, std::sync::atomic::Ordering::Relaxed);
+}
 
-        // Now lets start getting it to work.
-        let response = query_openai_inner(messages.clone(), schema).await;
-        assert!(response.is_err());
+/// When structured output does not parse into the requested type, such as a float where
+/// an integer is expected, show the model its answer with the error and ask it to fix it,
+/// up to `attempts` times. Costs an extra request per attempt. Disabled by default, and a
+/// single attempt is usually enough.
+pub fn set_repair_attempts(attempts: usize) {
+    REPAIR_ATTEMPTS.store(attempts, std::sync::atomic::Ordering::Relaxed);
+}
 
-        // Error querying api: HTTP status client error (400 Bad Request) for url (https://api.openai.com/v1/chat/completions)
-        // Raw output:
-        // {
-        //   "error": {
-        //     "message": "Invalid schema for response_format 'ComplexResponseSchema': In context=('properties', 'flair'), 'format' is not permitted.",
-        //     "type": "invalid_request_error",
-        //     "param": "response_format",
-        //     "code": null
-        //   }
-        // }
+/// Set how empty and missing array fields of structured output are handled, see
+/// [`ArrayPolicy`].
+pub fn set_array_policy(policy: ArrayPolicy) {
+    *ARRAY_POLICY.write().expect("Array policy lock poisoned") = policy;
+}
 
-        let schema = schemars::generate::SchemaSettings::default()
-            .with_transform(schemars::transform::RecursiveTransform(
-                |schema: &mut schemars::Schema| {
-                    schema.remove("format");
-                },
-            ))
-            .into_generator()
-            .into_root_schema_for::<ComplexResponseSchema>();
+/// Set which float fields of structured output are rounded, see [`FloatPrecision`].
+pub fn set_float_precision(precision: FloatPrecision) {
+    *FLOAT_PRECISION
+        .write()
+        .expect("Float precision lock poisoned") = precision;
+}
 
-        let schema = Schema {
-            name: "ComplexResponseSchema".to_string(),
-            schema: serde_json::to_value(schema).expect("Failed to convert schema to JSON value"),
-            strict: true,
-        };
+/// Set the names roles are sent as, for endpoints that do not use OpenAI's names.
+pub fn set_role_names(names: RoleNames) {
+    *ROLE_NAMES.write().expect("Role names lock poisoned") = names;
+}
 
-        let response = query_openai_inner(messages, schema).await.unwrap();
-        let response: ComplexResponseSchema = serde_json::from_str(
-            &response
-                .choices
-                .get(0)
-                .expect("Response from OpenAI")
-                .message
-                .content,
-        )
-        .unwrap();
+/// Configure which endpoints and models support strict mode and how to handle requests
+/// that would use it where it is not supported.
+pub fn set_strict_support(support: StrictSupport, on_mismatch: StrictMismatch) {
+    *STRICT_SUPPORT
+        .write()
+        .expect("Strict support lock poisoned") = (support, on_mismatch);
+}
 
-        // Assert all fields in ComplexResponseSchema
-        assert!(!response.summary.is_empty(), "Summary should not be empty");
-        assert!(response.word_count > 0, "Word count should be positive");
+/// Set a cache for responses, or `None` to disable caching again. Disabled by default.
+/// See [`Cache`].
+pub fn set_cache(cache: Option<std::sync::Arc<dyn Cache>>) {
+    *CACHE.write().expect("Cache lock poisoned") = cache;
+}
+
+/// Query OpenAI with a message and a schema defined by the generic type T. The schema
+/// is used to enforce structured output from the OpenAI API and parse the response into
+/// said Rust type.
+///
+/// Fails if the request fails, the response has no choices, or its content does not parse
+/// into `T`.
+pub async fn query_openai<T>(messages: Vec<Message>) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    query_with_provider(&LlmClient::default(), messages).await
+}
+
+/// Query OpenAI like [`query_openai`] with parameters such as a temperature of 0 for more
+/// deterministic output, or another model than the configured one.
+pub async fn query_openai_with_opts<T>(
+    messages: Vec<Message>,
+    params: QueryParams,
+) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    LlmClient::default().query_with_opts(messages, params).await
+}
+
+/// Query OpenAI like [`query_openai`], also returning the JSON text of the response exactly
+/// as the model produced it, for example to store fields `T` does not model.
+///
+/// When the response does not parse into `T`, the error does not include the text. Query
+/// for [`serde_json::Value`] instead to inspect what the model returns.
+pub async fn query_openai_raw<T>(messages: Vec<Message>) -> Result<(T, String), LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    LlmClient::default().query_raw(messages).await
+}
+
+/// Query OpenAI for structured output following `schema`, for schemas that are only known at
+/// runtime, such as those loaded from configuration, and have no Rust type to parse into.
+pub async fn query_openai_dynamic(
+    messages: Vec<Message>,
+    schema: Schema,
+) -> Result<serde_json::Value, LlmError> {
+    LlmClient::default().query_dynamic(messages, schema).await
+}
+
+/// Parse structured output from the content of a response according to the configured
+/// parsing options.
+fn parse_structured<T>(content: &str) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    parse::parse_content(
+        content,
+        REJECT_DUPLICATE_KEYS.load(std::sync::atomic::Ordering::Relaxed),
+        &ARRAY_POLICY.read().expect("Array policy lock poisoned"),
+        &FLOAT_PRECISION
+            .read()
+            .expect("Float precision lock poisoned"),
+    )
+}
+
+/// Query the OpenAI API with a message and a schema, retrying failures according to the
+/// configured [`RetryPolicy`] and answering from the configured [`Cache`] when possible.
+async fn query_openai_inner(
+    messages: Vec<Message>,
+    schema: Schema,
+) -> Result<OpenAIChatCompletionResponse, LlmError> {
+    Ok(query_openai_inner_with_stats(messages, schema).await?.0)
+}
+
+/// Query the OpenAI API like [`query_openai_inner`], also returning the retries it took.
+async fn query_openai_inner_with_stats(
+    messages: Vec<Message>,
+    schema: Schema,
+) -> Result<(OpenAIChatCompletionResponse, RetryStats), LlmError> {
+    let client = LlmClient::default();
+    let query = OpenAIChatCompletionQuery::new(
+        client.config.model.clone(), // E.g. "o3-mini-2025-01-31"
+        messages,
+        schema,
+    );
+    run_query(&client, query).await
+}
+
+/// Send a query with `client` and the configured strict mode handling, [`RetryPolicy`] and
+/// [`Cache`].
+///
+/// Runs in a `tracing` span with the model and schema name, and logs the final failure of a
+/// query with the status and the start of the body for errors from the API.
+async fn run_query(
+    client: &LlmClient,
+    mut query: OpenAIChatCompletionQuery,
+) -> Result<(OpenAIChatCompletionResponse, RetryStats), LlmError> {
+    use tracing::Instrument;
+
+    prepare_query(client, &mut query)?;
+    if let Some(dir) = &client.config.dump_schema_dir {
+        dump_schema(dir, &query);
+    }
+
+    let span = tracing::info_span!(
+        "llm_query",
+        model = %query.model,
+        schema = query.schema_name().unwrap_or("none"),
+    );
+    let policy = client.current_retry_policy();
+    let cache = CACHE.read().expect("Cache lock poisoned").clone();
+    let mut stats = RetryStats::default();
+    let response = cache::with_cache(cache.as_deref(), &query, &client.config, || async {
+        let (response, retries) =
+            retry::with_retry_stats(&policy, || send_query(client, &query)).await?;
+        stats = retries;
+        Ok(response)
+    })
+    .instrument(span.clone())
+    .await;
+
+    let _span = span.enter();
+    match response {
+        Ok(response) => {
+            tracing::debug!(attempts = stats.attempts, "Query succeeded");
+            Ok((response, stats))
+        }
+        Err(error) => {
+            match &error {
+                LlmError::ApiStatus { status, body, .. } => {
+                    let body: String = body.chars().take(BODY_SNIPPET_LENGTH).collect();
+                    tracing::warn!(status, %body, "Query rejected by the API");
+                }
+                error => tracing::warn!(%error, "Query failed"),
+            }
+            Err(error)
+        }
+    }
+}
+
+/// Adapt a query to where `client` sends it, before it is sent or looked up in the cache.
+fn prepare_query(
+    client: &LlmClient,
+    query: &mut OpenAIChatCompletionQuery,
+) -> Result<(), LlmError> {
+    query.params.validate()?;
+    query.params.drop_unsupported(&query.model);
+    if let Some(prompt) = &client.config.system_prompt
+        && !query
+            .messages
+            .iter()
+            .any(|message| message.role.is_instructions())
+    {
+        query.messages.insert(0, Message::developer(prompt.clone()));
+    }
+    if query.params.response_mode == ResponseMode::JsonObject {
+        strict::use_json_object(query);
+    }
+    client
+        .config
+        .instruction_role
+        .normalize(&mut query.messages, &query.model);
+    if !client.config.strict {
+        if let ResponseFormat::JsonSchema { json_schema } = &mut query.response_format {
+            json_schema.strict = false;
+        }
+        query.tools.iter_mut().for_each(ToolDef::disable_strict);
+    }
+    if client.config.legacy_max_tokens {
+        query.max_tokens = query.params.max_completion_tokens.take();
+    }
+    strict::enforce_support(query, &client.config.chat_completions_url())
+}
+
+/// Write the schema of `query`, as it is sent, to `<dir>/<name>.json`. A failure to write
+/// is only logged, as the dump is a debugging aid.
+fn dump_schema(dir: &std::path::Path, query: &OpenAIChatCompletionQuery) {
+    let ResponseFormat::JsonSchema { json_schema } = &query.response_format else {
+        return;
+    };
+    let path = dir.join(format!("{}.json", json_schema.name));
+    let written = std::fs::create_dir_all(dir).and_then(|()| json_schema.write_json(&path));
+    if let Err(error) = written {
+        tracing::warn!(%error, path = %path.display(), "Failed to dump schema");
+    }
+}
+
+/// Send a single query to the OpenAI API without retrying.
+async fn send_query(
+    client: &LlmClient,
+    query: &OpenAIChatCompletionQuery,
+) -> Result<OpenAIChatCompletionResponse, LlmError> {
+    let response = post_query(client, query).await?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+    let body = response.text().await?;
+
+    parse_response(content_type.as_deref(), &body)
+}
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Post a query to the OpenAI API, turning a non-success status into an error.
+async fn post_query(
+    client: &LlmClient,
+    query: &OpenAIChatCompletionQuery,
+) -> Result<reqwest::Response, LlmError> {
+    if let Some(limiter) = &client.config.rate_limiter {
+        let schema = match &query.response_format {
+            ResponseFormat::JsonSchema { json_schema } => Some(json_schema),
+            _ => None,
+        };
+        limiter
+            .acquire(estimate_prompt_tokens(&query.messages, schema))
+            .await;
+    }
+    let mut request = client.http.post(client.config.chat_completions_url());
+    if let Some(timeout) = client.config.timeout {
+        request = request.timeout(timeout);
+    }
+    // The API key is only sent in the headers, so the body is safe to log as is.
+    #[cfg(feature = "trace-request-body")]
+    tracing::debug!(
+        body = %serde_json::to_string(query).unwrap_or_default(),
+        "Sending request"
+    );
+    let response = with_headers(request, &client.config)
+        .json(query)
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| retry::parse_retry_after(value.to_str().ok()?));
+        let body = response.text().await?;
+        return Err(LlmError::ApiStatus {
+            status: status.as_u16(),
+            error: ApiError::parse(&body).map(Box::new),
+            body,
+            retry_after,
+        });
+    }
+    Ok(response)
+}
+
+/// Number of characters of an unexpected response body to include in errors.
+const BODY_SNIPPET_LENGTH: usize = 200;
+
+/// Parse the body of a successful response. Misconfigured proxies sometimes answer with an
+/// HTML page and a success status, so the content type is checked before parsing to give a
+/// clearer error than the one from serde.
+fn parse_response(
+    content_type: Option<&str>,
+    body: &str,
+) -> Result<OpenAIChatCompletionResponse, LlmError> {
+    if let Some(content_type) = content_type
+        && !content_type.contains("json")
+    {
+        return Err(LlmError::UnexpectedContentType {
+            content_type: content_type.to_string(),
+            body_snippet: body.chars().take(BODY_SNIPPET_LENGTH).collect(),
+        });
+    }
+
+    serde_json::from_str(body).map_err(LlmError::InvalidResponse)
+}
+
+#[derive(Debug, serde::Serialize)]
+struct OpenAIChatCompletionQuery {
+    model: String,
+    messages: Vec<Message>,
+    response_format: ResponseFormat,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(flatten)]
+    params: QueryParams,
+    /// The completion token limit for endpoints that do not understand
+    /// `max_completion_tokens`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    /// How many choices to generate, one unless set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<u32>,
+    /// Functions the model may call, see [`query_openai_tools`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallel_tool_calls: Option<bool>,
+}
+
+/// Optional parameters of a request. Unset parameters are left out of the request, so the
+/// API defaults apply.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct QueryParams {
+    /// Send the request to this model instead of the configured one, e.g. a cheap model for
+    /// simple extraction and a reasoning model for hard questions.
+    #[serde(skip)]
+    pub model: Option<String>,
+    /// Sampling temperature between 0 and 2. Use 0 for the most deterministic output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling: only consider the tokens making up this probability mass.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    /// The maximum number of tokens to generate, including reasoning tokens. Output cut off
+    /// at the limit is incomplete JSON and fails to parse.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    /// How long reasoning models such as o3-mini think before answering. Left out of
+    /// requests to other models, which reject it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    /// Sample deterministically on a best-effort basis, so that repeated requests with the
+    /// same seed and parameters usually return the same result. Compare the
+    /// [`WithMetadata::system_fingerprint`] of the responses to detect backend changes
+    /// that break this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// Stop generating at any of these sequences, which are left out of the response. At
+    /// most [`MAX_STOP_SEQUENCES`]. The cut off output of a structured response does not
+    /// parse, so this is mostly useful for free-form text.
+    #[serde(skip_serializing_if = "is_none_or_empty")]
+    pub stop: Option<Vec<String>>,
+    /// Between -2 and 2. Positive values make the model more likely to talk about new
+    /// topics, by penalizing tokens that already appeared at all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    /// Between -2 and 2. Positive values make the model less likely to repeat itself, by
+    /// penalizing tokens by how often they already appeared.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// How the response is constrained to the schema. Only set this to
+    /// [`ResponseMode::JsonObject`] for models that do not support `json_schema`, as the
+    /// schema is then no longer enforced.
+    #[serde(skip)]
+    pub response_mode: ResponseMode,
+    /// Return the log probability of each token of the content, see
+    /// [`query_openai_with_logprobs`].
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub logprobs: bool,
+    /// Also return this many of the most likely alternatives for each token, at most
+    /// [`MAX_TOP_LOGPROBS`]. Requires `logprobs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+}
+
+/// The most stop sequences OpenAI accepts in a request.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+/// The most alternatives per token OpenAI returns log probabilities for.
+pub const MAX_TOP_LOGPROBS: u8 = 20;
+
+fn is_none_or_empty(values: &Option<Vec<String>>) -> bool {
+    values.as_ref().is_none_or(Vec::is_empty)
+}
+
+/// How much reasoning models spend on thinking, trading latency and cost for accuracy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl QueryParams {
+    /// Check the parameters against the limits of the API, to fail before sending a request
+    /// it would reject.
+    fn validate(&self) -> Result<(), LlmError> {
+        if let Some(stop) = &self.stop
+            && stop.len() > MAX_STOP_SEQUENCES
+        {
+            return Err(LlmError::InvalidParameter {
+                name: "stop",
+                reason: format!(
+                    "{} sequences given, at most {MAX_STOP_SEQUENCES} are allowed",
+                    stop.len()
+                ),
+            });
+        }
+        for (name, penalty) in [
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ] {
+            if let Some(penalty) = penalty
+                && !(-2.0..=2.0).contains(&penalty)
+            {
+                return Err(LlmError::InvalidParameter {
+                    name,
+                    reason: format!("{penalty} is outside of the range -2 to 2"),
+                });
+            }
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > MAX_TOP_LOGPROBS {
+                return Err(LlmError::InvalidParameter {
+                    name: "top_logprobs",
+                    reason: format!("{top_logprobs} is more than {MAX_TOP_LOGPROBS}"),
+                });
+            }
+            if !self.logprobs {
+                return Err(LlmError::InvalidParameter {
+                    name: "top_logprobs",
+                    reason: "logprobs must be enabled as well".to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the parameters `model` does not accept.
+    fn drop_unsupported(&mut self, model: &str) {
+        if !is_reasoning_model(model) {
+            self.reasoning_effort = None;
+        }
+    }
+}
+
+/// Whether `model` is one of OpenAI's reasoning models, which accept a reasoning effort.
+fn is_reasoning_model(model: &str) -> bool {
+    let model = model.rsplit('/').next().unwrap_or(model);
+    ["o1", "o3", "o4"].iter().any(|family| {
+        model
+            .strip_prefix(family)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+    })
+}
+
+impl OpenAIChatCompletionQuery {
+    /// The body of a request for structured output following `schema`.
+    fn new(model: String, messages: Vec<Message>, schema: Schema) -> Self {
+        Self {
+            model,
+            messages,
+            // Always set to json_schema when using structured outputs
+            response_format: ResponseFormat::JsonSchema {
+                json_schema: schema,
+            },
+            stream: false,
+            params: QueryParams::default(),
+            max_tokens: None,
+            n: None,
+            tools: Vec::new(),
+            tool_choice: None,
+            parallel_tool_calls: None,
+        }
+    }
+
+    /// Use `params` for the request, including its model override if there is one.
+    fn with_params(mut self, mut params: QueryParams) -> Self {
+        if let Some(model) = params.model.take() {
+            self.model = model;
+        }
+        self.params = params;
+        self
+    }
+
+    /// The name of the schema the response must follow, if any.
+    fn schema_name(&self) -> Option<&str> {
+        match &self.response_format {
+            ResponseFormat::JsonSchema { json_schema } => Some(&json_schema.name),
+            ResponseFormat::JsonObject | ResponseFormat::Text => None,
+        }
+    }
+
+    /// The body of a request for a free-form text reply.
+    fn text(model: String, messages: Vec<Message>) -> Self {
+        Self {
+            model,
+            messages,
+            response_format: ResponseFormat::Text,
+            stream: false,
+            params: QueryParams::default(),
+            max_tokens: None,
+            n: None,
+            tools: Vec::new(),
+            tool_choice: None,
+            parallel_tool_calls: None,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseFormat {
+    JsonSchema {
+        json_schema: Schema,
+    },
+    /// Any JSON object, for endpoints that cannot enforce a schema.
+    JsonObject,
+    /// Free-form text rather than structured output.
+    Text,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Schema {
+    name: String,
+    schema: serde_json::Value,
+    strict: bool,
+}
+
+impl Schema {
+    /// A schema from a hand-written JSON schema, for example one loaded from a file, to query
+    /// without a Rust type with [`query_openai_dynamic`]. The schema is sent as is, so in
+    /// strict mode it must meet the constraints [`validate_schema`] checks. Fails if `name`
+    /// is not a valid schema name, see [`SchemaOptions::name`].
+    pub fn new(
+        name: impl Into<String>,
+        schema: serde_json::Value,
+        strict: bool,
+    ) -> Result<Self, LlmError> {
+        let name = name.into();
+        validate_schema_name(&name)?;
+        Ok(Self {
+            name,
+            schema,
+            strict,
+        })
+    }
+
+    /// The name of the schema, see [`SchemaOptions::name`].
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The JSON schema of the structured output.
+    pub fn json_schema(&self) -> &serde_json::Value {
+        &self.schema
+    }
+
+    /// Whether the output must follow the schema exactly, see [`SchemaOptions::strict`].
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// The `json_schema` object sent for this schema, with its name and strict flag, as
+    /// indented JSON to read or diff against OpenAI's requirements.
+    pub fn to_pretty_string(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed to convert schema to JSON")
+    }
+
+    /// Write [`Schema::to_pretty_string`] to the file at `path`, replacing it if it exists.
+    pub fn write_json(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_pretty_string())
+    }
+}
+
+/// A message of the conversation. Messages with [`ContentPart`]s besides their text are sent
+/// in the content-parts format, others as a plain string.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "content::WireMessage", into = "content::WireMessage")]
+pub struct Message {
+    role: Role,
+    /// The text of the message.
+    content: String,
+    /// Parts sent after the text, such as images.
+    parts: Vec<ContentPart>,
+    /// The call a [`Role::Tool`] message holds the result of.
+    tool_call_id: Option<String>,
+    /// The tools an assistant message called.
+    tool_calls: Vec<ToolCall>,
+}
+
+impl Message {
+    /// A message from `role`.
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            parts: Vec::new(),
+            tool_call_id: None,
+            tool_calls: Vec::new(),
+        }
+    }
+
+    /// A developer message, holding instructions the model should follow, also known as
+    /// the system prompt.
+    pub fn developer(content: impl Into<String>) -> Self {
+        Self::new(Role::Developer, content)
+    }
+
+    /// A user message.
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    /// An assistant message, for example an earlier reply of the model.
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    /// An assistant message calling `tool_calls`, to keep a reply with tool calls in the
+    /// history before the results of the calls. See [`ToolResponse::message`].
+    pub fn assistant_tool_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls,
+            ..Self::assistant(content)
+        }
+    }
+
+    /// The result of the tool call with the ID `tool_call_id`, such as the JSON returned by
+    /// the function, for the model to continue with.
+    pub fn tool_result(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+
+    /// A message made of `parts` alone, in order, for example text interleaved with images.
+    pub fn from_parts(role: Role, parts: Vec<ContentPart>) -> Self {
+        Self {
+            parts,
+            ..Self::new(role, String::new())
+        }
+    }
+
+    /// Who sent the message.
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// The text of the message.
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// The parts sent after the text of the message, such as images.
+    pub fn parts(&self) -> &[ContentPart] {
+        &self.parts
+    }
+
+    /// Attach a part, such as an image, to be sent after the text of the message.
+    pub fn with_part(mut self, part: ContentPart) -> Self {
+        self.parts.push(part);
+        self
+    }
+
+    /// The ID of the call a tool message holds the result of.
+    pub fn tool_call_id(&self) -> Option<&str> {
+        self.tool_call_id.as_deref()
+    }
+
+    /// The tools an assistant message called.
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        &self.tool_calls
+    }
+}
+
+/// Who a [`Message`] is from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Developer,
+    /// The role older models and most compatible backends use for instructions instead of
+    /// `developer`. Either is sent as the one the model understands, see
+    /// [`InstructionRole`].
+    System,
+    User,
+    Assistant,
+    /// The result of a tool call, see [`Message::tool_result`].
+    Tool,
+}
+
+impl Role {
+    /// Whether messages of this role hold instructions rather than the conversation.
+    fn is_instructions(self) -> bool {
+        matches!(self, Role::Developer | Role::System)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct OpenAIChatCompletionResponse {
+    choices: Vec<Choice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    system_fingerprint: Option<String>,
+    // There are a bunch of extra fields in the response
+    // that we don't care about. See the OpenAI API docs.
+}
+
+impl OpenAIChatCompletionResponse {
+    /// The content of the first choice, which holds the structured output.
+    fn content(&self) -> Result<&str, LlmError> {
+        self.choices
+            .first()
+            .ok_or(LlmError::EmptyChoices)?
+            .content()
+    }
+}
+
+/// Tokens used by a request, as reported by OpenAI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct Choice {
+    message: ResponseMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    logprobs: Option<Logprobs>,
+    /// Why the model stopped, such as `stop` when it finished or `length` at the token
+    /// limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    finish_reason: Option<String>,
+}
+
+impl Choice {
+    /// The content of the message, or an error if it was cut off or refused.
+    fn content(&self) -> Result<&str, LlmError> {
+        // Content cut off at the token limit is incomplete JSON, which would otherwise be
+        // reported as a confusing parse error.
+        if self.finish_reason.as_deref() == Some("length") {
+            return Err(LlmError::Truncated);
+        }
+        self.message.content()
+    }
+}
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct ResponseMessage {
+    /// `None` when the model refused. Some compatible servers send an array of content parts
+    /// instead of a string, whose text parts are joined.
+    #[serde(default, deserialize_with = "deserialize_content")]
+    content: Option<String>,
+    /// Why the model refused to answer, instead of content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refusal: Option<String>,
+    /// The functions the model called, for requests with tools.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+}
+
+impl ResponseMessage {
+    /// The content of the message, or the refusal as an error.
+    fn content(&self) -> Result<&str, LlmError> {
+        if let Some(refusal) = &self.refusal {
+            return Err(LlmError::Refusal(refusal.clone()));
+        }
+        self.content.as_deref().ok_or_else(|| {
+            LlmError::InvalidResponse(serde::de::Error::custom(
+                "Response message has neither content nor a refusal",
+            ))
+        })
+    }
+}
+
+/// Deserialize message content from a string, or from an array of content parts by
+/// concatenating the text of its `text` parts and ignoring the others. An array without text
+/// is no content.
+fn deserialize_content<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Content {
+        Text(String),
+        Parts(Vec<Part>),
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Part {
+        #[serde(rename = "type")]
+        kind: String,
+        #[serde(default)]
+        text: Option<String>,
+    }
+
+    Ok(
+        match <Option<Content> as serde::Deserialize>::deserialize(deserializer)? {
+            None => None,
+            Some(Content::Text(text)) => Some(text),
+            Some(Content::Parts(parts)) => parts
+                .into_iter()
+                .filter(|part| matches!(part.kind.as_str(), "text" | "output_text"))
+                .filter_map(|part| part.text)
+                .reduce(|mut content, text| {
+                    content.push_str(&text);
+                    content
+                }),
+        },
+    )
+}
+
+/// Add the configured headers to a request and authenticate it. A custom header of the
+/// same name as the one carrying the API key is dropped when there is a key, so the key is
+/// always sent.
+fn with_headers(request: reqwest::RequestBuilder, config: &Config) -> reqwest::RequestBuilder {
+    let mut headers = config.headers.clone();
+    let Some(api_key) = config.api_key.as_deref() else {
+        return request.headers(headers);
+    };
+    if config.azure.is_some() {
+        headers.remove(AZURE_API_KEY_HEADER);
+        return request
+            .headers(headers)
+            .header(AZURE_API_KEY_HEADER, api_key);
+    }
+    headers.remove(reqwest::header::AUTHORIZATION);
+    authorize(request.headers(headers), Some(api_key))
+}
+
+/// The header Azure OpenAI expects the API key in.
+const AZURE_API_KEY_HEADER: &str = "api-key";
+
+/// Authenticate a request with the API key, if there is one.
+fn authorize(request: reqwest::RequestBuilder, api_key: Option<&str>) -> reqwest::RequestBuilder {
+    match api_key {
+        Some(api_key) => request.bearer_auth(api_key),
+        None => request,
+    }
+}
+
+/// Where and how to send requests. The free query functions read it from the environment
+/// and the `.env` file on first use, see [`Config::from_env`]. Build one explicitly with
+/// [`Config::builder`] to use with an [`LlmClient`].
+#[derive(Clone)]
+pub struct Config {
+    /// `None` for endpoints without authentication, such as local inference servers.
+    api_key: Option<String>,
+    model: String,
+    /// The OpenAI-compatible API to send requests to, without a trailing slash.
+    base_url: String,
+    /// Send the completion token limit as the deprecated `max_tokens`.
+    legacy_max_tokens: bool,
+    /// How long to wait for a response before giving up with [`LlmError::Timeout`]. `None`
+    /// waits forever.
+    timeout: Option<std::time::Duration>,
+    /// Extra headers sent with every request, such as the organization and project of
+    /// enterprise accounts or the authentication of a proxy.
+    headers: reqwest::header::HeaderMap,
+    /// Send requests to this Azure OpenAI deployment instead of `base_url`.
+    azure: Option<AzureConfig>,
+    /// Sent as a developer message at the start of every request without one.
+    system_prompt: Option<String>,
+    /// The role developer and system messages are sent with.
+    instruction_role: InstructionRole,
+    /// Whether schemas are sent in strict mode. When `false`, strict mode is turned off for
+    /// every request, whatever the schema says. This happens as the request is sent, so
+    /// schemas created with [`get_schema`] still report `strict: true`, and constraints are
+    /// still stripped from them.
+    strict: bool,
+    /// Write every schema sent to this directory, see [`ConfigBuilder::dump_schema_dir`].
+    dump_schema_dir: Option<std::path::PathBuf>,
+    /// Shared by the clones of the configuration, so every client using it counts towards
+    /// the same limit. `None` without limits.
+    rate_limiter: Option<std::sync::Arc<rate_limit::RateLimiter>>,
+}
+
+/// Environment variables that must be set.
+const REQUIRED_VARS: &[&str] = &["OPENAI_API_KEY", "OPENAI_MODEL"];
+
+/// Set to `1` or `true` to send requests without an API key, even if `OPENAI_API_KEY` is
+/// set.
+const NO_AUTH_VAR: &str = "OPENAI_NO_AUTH";
+
+/// The base URL of an OpenAI-compatible API such as vLLM, Groq or Ollama, defaulting to
+/// OpenAI's.
+const BASE_URL_VAR: &str = "OPENAI_BASE_URL";
+
+/// Set to `1` or `true` for endpoints that only understand `max_tokens`, like many
+/// OpenAI-compatible servers, rather than `max_completion_tokens`.
+const LEGACY_MAX_TOKENS_VAR: &str = "OPENAI_LEGACY_MAX_TOKENS";
+
+/// Set to `0` or `false` for backends that support `json_schema` but fail in strict mode,
+/// see [`ConfigBuilder::strict`].
+const STRICT_VAR: &str = "OPENAI_STRICT";
+
+/// The request timeout in seconds, e.g. `30` or `2.5`. Requests wait forever if unset.
+const TIMEOUT_VAR: &str = "OPENAI_TIMEOUT_SECS";
+
+/// Instructions sent as a developer message with every request, see
+/// [`ConfigBuilder::system_prompt`].
+const SYSTEM_PROMPT_VAR: &str = "OPENAI_SYSTEM_PROMPT";
+
+/// A directory to write every schema sent to, see [`ConfigBuilder::dump_schema_dir`].
+const DUMP_SCHEMA_DIR_VAR: &str = "OPENAI_DUMP_SCHEMA_DIR";
+
+/// The most requests to send per minute, see [`ConfigBuilder::rate_limit`].
+const RPM_LIMIT_VAR: &str = "OPENAI_RPM_LIMIT";
+
+/// The most prompt tokens to send per minute, see [`ConfigBuilder::rate_limit`].
+const TPM_LIMIT_VAR: &str = "OPENAI_TPM_LIMIT";
+
+/// Environment variables sent as headers when set, for accounts in several organizations or
+/// projects.
+const HEADER_VARS: &[(&str, &str)] = &[
+    ("OPENAI_ORG_ID", ORGANIZATION_HEADER),
+    ("OPENAI_PROJECT_ID", PROJECT_HEADER),
+];
+
+const ORGANIZATION_HEADER: &str = "OpenAI-Organization";
+const PROJECT_HEADER: &str = "OpenAI-Project";
+
+impl Config {
+    /// The configuration behind the free query functions, which have no way to report
+    /// missing configuration other than panicking.
+    fn new() -> Self {
+        Self::from_env().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Read the configuration from the environment, after loading the `.env` file if there
+    /// is one and the `dotenv` feature is enabled. Environment variables take precedence
+    /// over the `.env` file.
+    pub fn from_env() -> Result<Self, LlmError> {
+        load_dotenv()?;
+        Ok(Self::from_vars(|var| std::env::var(var).ok())?)
+    }
+
+    /// Start building a configuration from explicit values, without reading the
+    /// environment. Use it with [`LlmClient::with_http_client`] on targets without an
+    /// environment or filesystem, such as WASM.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        if let Some(error) = Self::validate_vars(&var).into_iter().next() {
+            return Err(error);
+        }
+        // A key set in the environment for other tools must not leak to a server that was
+        // explicitly configured without auth.
+        let api_key = (!is_enabled(var(NO_AUTH_VAR)))
+            .then(|| var("OPENAI_API_KEY"))
+            .flatten();
+        Ok(Self {
+            api_key,
+            model: var("OPENAI_MODEL").expect("Validated above"),
+            base_url: Self::base_url(&var),
+            legacy_max_tokens: is_enabled(var(LEGACY_MAX_TOKENS_VAR)),
+            timeout: Self::timeout(&var).expect("Validated above"),
+            headers: Self::headers(&var).expect("Validated above"),
+            azure: None,
+            system_prompt: var(SYSTEM_PROMPT_VAR),
+            instruction_role: InstructionRole::Auto,
+            strict: !is_disabled(var(STRICT_VAR)),
+            dump_schema_dir: var(DUMP_SCHEMA_DIR_VAR).map(Into::into),
+            rate_limiter: Self::rate_limiter(Self::rate_limit(&var).expect("Validated above")),
+        })
+    }
+
+    fn rate_limit(var: impl Fn(&str) -> Option<String>) -> Result<RateLimit, ConfigError> {
+        let per_minute = |name: &'static str| {
+            var(name)
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| ConfigError::InvalidRateLimit { var: name, value })
+                })
+                .transpose()
+        };
+        let mut limit = RateLimit::new();
+        if let Some(requests) = per_minute(RPM_LIMIT_VAR)? {
+            limit = limit.requests_per_minute(requests);
+        }
+        if let Some(tokens) = per_minute(TPM_LIMIT_VAR)? {
+            limit = limit.tokens_per_minute(tokens);
+        }
+        Ok(limit)
+    }
+
+    fn rate_limiter(limit: RateLimit) -> Option<std::sync::Arc<rate_limit::RateLimiter>> {
+        (!limit.is_unlimited()).then(|| std::sync::Arc::new(rate_limit::RateLimiter::new(limit)))
+    }
+
+    fn headers(
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<reqwest::header::HeaderMap, ConfigError> {
+        header_map(
+            HEADER_VARS
+                .iter()
+                .filter_map(|(var_name, header)| Some((header.to_string(), var(var_name)?))),
+        )
+    }
+
+    fn timeout(
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<std::time::Duration>, ConfigError> {
+        let Some(timeout) = var(TIMEOUT_VAR) else {
+            return Ok(None);
+        };
+        timeout
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+            .map(Some)
+            .ok_or(ConfigError::InvalidTimeout(timeout))
+    }
+
+    fn base_url(var: impl Fn(&str) -> Option<String>) -> String {
+        let base_url = var(BASE_URL_VAR).unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        base_url.trim().trim_end_matches('/').to_string()
+    }
+
+    /// The URL of the chat completions endpoint.
+    fn chat_completions_url(&self) -> String {
+        match &self.azure {
+            Some(azure) => azure.chat_completions_url(),
+            None => format!("{}/chat/completions", self.base_url),
+        }
+    }
+
+    /// The URL of the Responses API, see [`ResponsesProvider`].
+    fn responses_url(&self) -> String {
+        match &self.azure {
+            Some(azure) => azure.responses_url(),
+            None => format!("{}/responses", self.base_url),
+        }
+    }
+
+    /// Whether requests are sent without an API key, either explicitly or because the
+    /// endpoint is a local inference server.
+    fn no_auth(var: impl Fn(&str) -> Option<String>) -> bool {
+        is_enabled(var(NO_AUTH_VAR)) || is_local_url(&Self::base_url(var))
+    }
+
+    /// Check all of the configuration at once, reporting every problem found rather than
+    /// stopping at the first one.
+    pub fn validate() -> Result<(), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+        if let Err(e) = load_dotenv() {
+            errors.push(e);
+        }
+        errors.extend(Self::validate_vars(|var| std::env::var(var).ok()));
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_vars(var: impl Fn(&str) -> Option<String>) -> Vec<ConfigError> {
+        let no_auth = Self::no_auth(&var);
+        let mut errors: Vec<ConfigError> = REQUIRED_VARS
+            .iter()
+            .filter(|name| !(no_auth && **name == "OPENAI_API_KEY"))
+            .filter(|name| var(name).is_none())
+            .map(|name| ConfigError::Missing(name))
+            .collect();
+
+        let base_url = Self::base_url(&var);
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            errors.push(ConfigError::InvalidBaseUrl(base_url));
+        }
+        if let Err(e) = Self::timeout(&var) {
+            errors.push(e);
+        }
+        if let Err(e) = Self::headers(&var) {
+            errors.push(e);
+        }
+        if let Err(e) = Self::rate_limit(&var) {
+            errors.push(e);
+        }
+        errors
+    }
+}
+
+/// Builds a [`Config`] from explicit values, see [`Config::builder`].
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    api_key: Option<String>,
+    model: Option<String>,
+    base_url: Option<String>,
+    legacy_max_tokens: bool,
+    timeout: Option<std::time::Duration>,
+    headers: Vec<(String, String)>,
+    azure: Option<AzureConfig>,
+    system_prompt: Option<String>,
+    instruction_role: InstructionRole,
+    strict: Option<bool>,
+    dump_schema_dir: Option<std::path::PathBuf>,
+    rate_limit: RateLimit,
+}
+
+impl ConfigBuilder {
+    /// Authenticate with `api_key`. Without one requests are sent unauthenticated, as for
+    /// local inference servers.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Send requests to `model`, e.g. `gpt-4o`. Required.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Send requests to the OpenAI-compatible API at `base_url` instead of OpenAI's.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Send the completion token limit as `max_tokens`, for endpoints that do not understand
+    /// `max_completion_tokens` yet.
+    pub fn legacy_max_tokens(mut self, legacy: bool) -> Self {
+        self.legacy_max_tokens = legacy;
+        self
+    }
+
+    /// Give up on requests that take longer than `timeout` with [`LlmError::Timeout`]. This
+    /// covers the whole request including reading the response, so for streams it limits
+    /// how long the full stream may take.
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send the `OpenAI-Organization` header, for accounts in several organizations.
+    pub fn organization(self, id: impl Into<String>) -> Self {
+        self.header(ORGANIZATION_HEADER, id)
+    }
+
+    /// Send the `OpenAI-Project` header, for accounts with several projects.
+    pub fn project(self, id: impl Into<String>) -> Self {
+        self.header(PROJECT_HEADER, id)
+    }
+
+    /// Send a custom header with every request, replacing one of the same name set before.
+    /// An `Authorization` header is only sent without an API key, so it cannot replace the
+    /// bearer token.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Send requests to an Azure OpenAI deployment, authenticated with the API key in the
+    /// `api-key` header. The base URL is not used, and the model defaults to the name of the
+    /// deployment.
+    pub fn azure(mut self, azure: AzureConfig) -> Self {
+        self.azure = Some(azure);
+        self
+    }
+
+    /// Start every request with a developer message with `prompt`, for instructions that are
+    /// the same on every call. Requests that already contain a developer message are left
+    /// alone rather than merged, so a conversation can still set its own instructions.
+    pub fn system_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.system_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Send developer and system messages with the role chosen by `role` rather than
+    /// by the model family, for backends with their own conventions.
+    pub fn instruction_role(mut self, role: InstructionRole) -> Self {
+        self.instruction_role = role;
+        self
+    }
+
+    /// Send schemas in strict mode, the default. Turn it off for the whole deployment when
+    /// pointing at a backend that supports `json_schema` but fails in strict mode. This
+    /// overrides [`SchemaOptions::strict`], as such a backend rejects every strict schema.
+    /// The override is applied as each request is sent, so [`Schema::strict`] still reports
+    /// the strict flag the schema was created with; [`crate::describe_request`] shows the
+    /// flag actually sent.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = Some(strict);
+        self
+    }
+
+    /// Write the schema of every chat completion request to `<dir>/<name>.json` exactly as
+    /// it is sent, after all transforms, to see why OpenAI rejects a schema. Each request
+    /// overwrites the file of its schema.
+    pub fn dump_schema_dir(mut self, dir: impl Into<std::path::PathBuf>) -> Self {
+        self.dump_schema_dir = Some(dir.into());
+        self
+    }
+
+    /// Wait before sending requests that would exceed `limit`, rather than sending them and
+    /// retrying after a 429. Every client built from clones of the configuration shares the
+    /// limit, so set it to the limit of the account or project when nothing else uses it.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = limit;
+        self
+    }
+
+    /// Build the configuration, failing if the model is missing, the base URL is not an
+    /// http or https URL or a header is not valid in HTTP.
+    pub fn build(self) -> Result<Config, LlmError> {
+        let model = self
+            .model
+            .or_else(|| Some(self.azure.as_ref()?.deployment().to_string()))
+            .ok_or_else(|| LlmError::MissingConfig("model".to_string()))?;
+        let base_url = Config::base_url(|_| self.base_url.clone());
+        let urls = std::iter::once(base_url.as_str())
+            .chain(self.azure.as_ref().map(AzureConfig::endpoint));
+        for url in urls {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(ConfigError::InvalidBaseUrl(url.to_string()).into());
+            }
+        }
+
+        Ok(Config {
+            api_key: self.api_key,
+            model,
+            base_url,
+            legacy_max_tokens: self.legacy_max_tokens,
+            timeout: self.timeout,
+            headers: header_map(self.headers)?,
+            azure: self.azure,
+            system_prompt: self.system_prompt,
+            instruction_role: self.instruction_role,
+            strict: self.strict.unwrap_or(true),
+            dump_schema_dir: self.dump_schema_dir,
+            rate_limiter: Config::rate_limiter(self.rate_limit),
+        })
+    }
+}
+
+/// Collect headers from names and values, failing on the first that is not valid in HTTP.
+fn header_map(
+    headers: impl IntoIterator<Item = (String, String)>,
+) -> Result<reqwest::header::HeaderMap, ConfigError> {
+    let mut map = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        let header_name = reqwest::header::HeaderName::try_from(name.as_str());
+        let header_value = reqwest::header::HeaderValue::try_from(value.as_str());
+        let (Ok(header_name), Ok(header_value)) = (header_name, header_value) else {
+            return Err(ConfigError::InvalidHeader(name));
+        };
+        map.insert(header_name, header_value);
+    }
+    Ok(map)
+}
+
+/// Load the `.env` file into the environment. A missing file is fine, as deployments
+/// usually set the environment directly, but one that exists and cannot be read is not.
+#[cfg(feature = "dotenv")]
+fn load_dotenv() -> Result<(), ConfigError> {
+    match dotenvy::dotenv() {
+        Ok(_) => Ok(()),
+        Err(e) if e.not_found() => Ok(()),
+        Err(e) => Err(ConfigError::DotEnv(e.to_string())),
+    }
+}
+
+/// Without the `dotenv` feature only the environment itself is read.
+#[cfg(not(feature = "dotenv"))]
+fn load_dotenv() -> Result<(), ConfigError> {
+    Ok(())
+}
+
+/// Whether a flag read from the environment is set to `1` or `true`.
+fn is_enabled(value: Option<String>) -> bool {
+    value.is_some_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
+
+/// Whether a flag read from the environment is set to `0` or `false`, for flags that are on
+/// by default.
+fn is_disabled(value: Option<String>) -> bool {
+    value.is_some_and(|value| value == "0" || value.eq_ignore_ascii_case("false"))
+}
+
+/// Whether `url` points at this machine, where inference servers rarely need a key.
+fn is_local_url(url: &str) -> bool {
+    let Some((_, rest)) = url.split_once("://") else {
+        return false;
+    };
+    let host = rest.split('/').next().unwrap_or_default();
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+/// Validate the configuration and load it, so that missing configuration is reported at
+/// startup instead of panicking on the first query.
+pub fn init() -> Result<(), Vec<ConfigError>> {
+    Config::validate()?;
+    std::sync::LazyLock::force(&CONFIG);
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::len_zero)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct SimpleResponseSchema {
+        #[schemars(description = "Summary of the text in two extremely short paragraphs")]
+        summary: Vec<String>,
+
+        #[schemars(description = "Tone of the text")]
+        tone: String,
+
+        #[schemars(description = "Number of words in the text")]
+        word_count: i64,
+
+        #[schemars(description = "Flair from 0 to 1")]
+        flair: f64,
+    }
+
+    #[tokio::test]
+    async fn test_simple_schema() {
+        let messages = vec![Message::user("Hello, world!")];
+        // Without an API key, check the schema and parsing against a canned answer instead.
+        let response: SimpleResponseSchema = if Config::from_env().is_ok() {
+            query_openai(messages).await.unwrap()
+        } else {
+            let mock = MockProvider::fixed(
+                r#"{"summary": ["A greeting.", "To everyone."], "tone": "Friendly",
+                    "word_count": 2, "flair": 0.4}"#,
+            );
+            let response = query_with_provider(&mock, messages).await.unwrap();
+            let request = &mock.requests()[0];
+            assert!(request.schema.strict());
+            assert_eq!(
+                request.schema.json_schema()["required"],
+                serde_json::json!(["summary", "tone", "word_count", "flair"])
+            );
+            response
+        };
+
+        assert!(response.summary.len() > 0);
+        assert!(response.tone.len() > 0);
+        assert!(response.word_count > 0);
+        assert!(response.flair >= 0.0 && response.flair <= 1.0);
+    }
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+    enum Sentiment {
+        Positive,
+        Neutral,
+        Negative,
+    }
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct EnumResponseSchema {
+        #[schemars(description = "Summary of the text")]
+        summary: String,
+
+        #[schemars(description = "Sentiment of the text")]
+        sentiment: Sentiment,
+
+        #[schemars(description = "Number of words in the text")]
+        word_count: i64,
+    }
+
+    #[tokio::test]
+    async fn test_enum_schema() {
+        let response = query_openai::<EnumResponseSchema>(vec![Message::user(
+            "I'm having a wonderful day today!".to_string(),
+        )])
+        .await
+        .unwrap();
+
+        assert!(response.summary.len() > 0);
+        assert!(response.word_count > 0);
+        // No need to validate the enum sentiment since the query will fail if it is not
+        // one of the values.
+    }
+
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct NestedResponseSchema {
+        #[schemars(description = "A list of responses to the message")]
+        responses: Vec<SimpleResponseSchema>,
+    }
+
+    #[tokio::test]
+    async fn test_nested_schema() {
+        let responses = query_openai::<NestedResponseSchema>(vec![Message::user(
+            "Hello, world! Reply with at least 3 different responses".to_string(),
+        )])
+        .await
+        .unwrap();
+        assert!(responses.responses.len() >= 3);
+
+        for response in responses.responses {
+            assert!(response.summary.len() > 0);
+            // assert!(response.tone.len() > 0);
+            // assert!(response.word_count > 0);
+            // assert!(response.flair >= 0.0 && response.flair <= 1.0);
+        }
+    }
+
+    /// Represents a complex schema that tests most features of the schema.
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct ComplexResponseSchema {
+        #[schemars(description = "Summary of the text")]
+        summary: String,
+
+        #[schemars(description = "Number of words in the text")]
+        word_count: i64,
+
+        #[schemars(description = "Flair from 0 to 1")]
+        flair: f64,
+
+        #[schemars(description = "A list of responses to the message")]
+        responses: Vec<SimpleResponseSchema>,
+
+        #[schemars(description = "A list of sentiments to the message")]
+        sentiments: Vec<Sentiment>,
+
+        // No description is allowed when the type is an object
+        object_in_object: SimpleResponseSchema,
+    }
+
+    #[tokio::test]
+    async fn test_schemars_default_schema() {
+        let schema = Schema {
+            name: "ComplexResponseSchema".to_string(),
+            schema: serde_json::to_value(schemars::schema_for!(ComplexResponseSchema))
+                .expect("Failed to convert schema to JSON value"),
+            strict: true,
+        };
+
+        let messages = vec![Message::user("Hello, world!")];
+
+        // Now lets start getting it to work.
+        let response = query_openai_inner(messages.clone(), schema).await;
+        assert!(response.is_err());
+
+        // Error querying api: HTTP status client error (400 Bad Request) for url (https://api.openai.com/v1/chat/completions)
+        // Raw output:
+        // {
+        //   "error": {
+        //     "message": "Invalid schema for response_format 'ComplexResponseSchema': In context=('properties', 'flair'), 'format' is not permitted.",
+        //     "type": "invalid_request_error",
+        //     "param": "response_format",
+        //     "code": null
+        //   }
+        // }
+
+        let schema = schemars::generate::SchemaSettings::default()
+            .with_transform(schemars::transform::RecursiveTransform(
+                |schema: &mut schemars::Schema| {
+                    schema.remove("format");
+                },
+            ))
+            .into_generator()
+            .into_root_schema_for::<ComplexResponseSchema>();
+
+        let schema = Schema {
+            name: "ComplexResponseSchema".to_string(),
+            schema: serde_json::to_value(schema).expect("Failed to convert schema to JSON value"),
+            strict: true,
+        };
+
+        let response = query_openai_inner(messages, schema).await.unwrap();
+        let response: ComplexResponseSchema =
+            serde_json::from_str(response.content().unwrap()).unwrap();
+
+        // Assert all fields in ComplexResponseSchema
+        assert!(!response.summary.is_empty(), "Summary should not be empty");
+        assert!(response.word_count > 0, "Word count should be positive");
+        assert!(
+            response.flair >= 0.0 && response.flair <= 1.0,
+            "Flair should be between 0 and 1"
+        );
+        assert!(
+            !response.responses.is_empty(),
+            "Responses should not be empty"
+        );
+        assert!(
+            !response.sentiments.is_empty(),
+            "Sentiments should not be empty"
+        );
+        assert!(
+            !response.object_in_object.summary.is_empty(),
+            "Object in object summary should not be empty"
+        );
+    }
+
+    #[test]
+    fn test_multi_schema_composition() {
+        let schema = <(SimpleResponseSchema, EnumResponseSchema)>::schema();
+        let simple_name = get_schema::<SimpleResponseSchema>().name;
+        let enum_name = get_schema::<EnumResponseSchema>().name;
+
+        assert_eq!(
+            schema.schema["required"],
+            serde_json::json!([simple_name, enum_name])
+        );
+        assert_eq!(schema.schema["additionalProperties"], false);
+        // The Sentiment definition must be hoisted to the root for its $ref to resolve.
+        assert!(schema.schema["$defs"]["Sentiment"].is_object());
+        assert!(schema.schema["properties"][&enum_name]["$defs"].is_null());
+        // The joined component names are too long for a schema name.
+        assert!(simple_name.len() + enum_name.len() > MAX_SCHEMA_NAME_LENGTH);
+        assert!(schema.name.len() <= MAX_SCHEMA_NAME_LENGTH);
+        assert!(validate_schema_name(&schema.name).is_ok());
+
+        let value = serde_json::json!({
+            simple_name: {
+                "summary": ["Short"],
+                "tone": "Friendly",
+                "word_count": 2,
+                "flair": 0.5,
+            },
+            enum_name: {
+                "summary": "Short",
+                "sentiment": "Positive",
+                "word_count": 2,
+            },
+        });
+        let (simple, with_enum) =
+            <(SimpleResponseSchema, EnumResponseSchema)>::from_value(value).unwrap();
+        assert_eq!(simple.tone, "Friendly");
+        assert!(matches!(with_enum.sentiment, Sentiment::Positive));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs an OpenAI API key"]
+    async fn test_multi_schema() {
+        let (simple, with_enum) =
+            query_openai_multi::<(SimpleResponseSchema, EnumResponseSchema)>(vec![Message::user(
+                "I'm having a wonderful day today!".to_string(),
+            )])
+            .await
+            .unwrap();
+
+        assert!(!simple.summary.is_empty());
+        assert!(!simple.tone.is_empty());
+        assert!(!with_enum.summary.is_empty());
+        assert!(with_enum.word_count > 0);
+    }
+
+    #[test]
+    fn test_canonical_openai_schema() {
+        let query = OpenAIChatCompletionQuery::new(
+            "gpt-4o-mini".to_string(),
+            Vec::new(),
+            get_schema::<ComplexResponseSchema>(),
+        );
+        let sent = serde_json::to_value(&query).unwrap();
+
+        let canonical = canonical_openai_schema::<ComplexResponseSchema>();
+        assert_eq!(sent["response_format"]["json_schema"], canonical);
+        assert_eq!(canonical["strict"], true);
+        assert!(
+            canonical["name"]
+                .as_str()
+                .unwrap()
+                .ends_with("ComplexResponseSchema")
+        );
+    }
+
+    #[test]
+    fn test_query_params() {
+        let query = |params| OpenAIChatCompletionQuery {
+            params,
+            ..OpenAIChatCompletionQuery::new(
+                "gpt-4o-mini".to_string(),
+                Vec::new(),
+                get_schema::<SimpleResponseSchema>(),
+            )
+        };
+
+        let unset = serde_json::to_value(query(QueryParams::default())).unwrap();
+        assert!(!unset.as_object().unwrap().contains_key("temperature"));
+        assert!(!unset.as_object().unwrap().contains_key("top_p"));
+        assert!(!unset.as_object().unwrap().contains_key("seed"));
+
+        let set = serde_json::to_value(query(QueryParams {
+            temperature: Some(0.0),
+            top_p: Some(0.5),
+            seed: Some(42),
+            ..QueryParams::default()
+        }))
+        .unwrap();
+        assert_eq!(set["temperature"], 0.0);
+        assert_eq!(set["top_p"], 0.5);
+        assert_eq!(set["seed"], 42);
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Haiku {
+        lines: Vec<String>,
+    }
+
+    #[tokio::test]
+    #[ignore = "needs an OpenAI API key"]
+    async fn test_seeded_queries() {
+        let query = || {
+            query_openai_with_opts::<Haiku>(
+                vec![Message::user("Write a haiku about the sea.")],
+                QueryParams {
+                    seed: Some(7),
+                    ..QueryParams::default()
+                },
+            )
+        };
+        // Determinism is best-effort, so this may rarely fail when the backend changes.
+        assert_eq!(query().await.unwrap(), query().await.unwrap());
+    }
+
+    #[test]
+    fn test_message_constructors() {
+        let messages = [
+            Message::developer("Be brief"),
+            Message::user("Hi"),
+            Message::assistant("Hello"),
+        ];
+        assert_eq!(
+            serde_json::to_value(&messages).unwrap(),
+            serde_json::json!([
+                { "role": "developer", "content": "Be brief" },
+                { "role": "user", "content": "Hi" },
+                { "role": "assistant", "content": "Hello" },
+            ])
+        );
+        assert_eq!(messages[1].role(), Role::User);
+        assert_eq!(messages[1].content(), "Hi");
+    }
+
+    #[test]
+    fn test_model_override() {
+        let query = |model: Option<&str>| {
+            OpenAIChatCompletionQuery::text("gpt-4o".to_string(), Vec::new()).with_params(
+                QueryParams {
+                    model: model.map(String::from),
+                    temperature: Some(0.0),
+                    ..QueryParams::default()
+                },
+            )
+        };
+
+        assert_eq!(query(None).model, "gpt-4o");
+        let overridden = serde_json::to_value(query(Some("gpt-4o-mini"))).unwrap();
+        assert_eq!(overridden["model"], "gpt-4o-mini");
+        assert_eq!(overridden["temperature"], 0.0);
+    }
+
+    #[test]
+    fn test_max_completion_tokens() {
+        let prepared = |legacy| {
+            let config = Config::builder()
+                .model("gpt-4o")
+                .legacy_max_tokens(legacy)
+                .build()
+                .unwrap();
+            let client = LlmClient::with_http_client(reqwest::Client::new(), config);
+            let mut query = OpenAIChatCompletionQuery::text("gpt-4o".to_string(), Vec::new())
+                .with_params(QueryParams {
+                    max_completion_tokens: Some(2000),
+                    ..QueryParams::default()
+                });
+            prepare_query(&client, &mut query).unwrap();
+            serde_json::to_value(query).unwrap()
+        };
+
+        let current = prepared(false);
+        assert_eq!(current["max_completion_tokens"], 2000);
+        assert!(current.get("max_tokens").is_none());
+        let legacy = prepared(true);
+        assert_eq!(legacy["max_tokens"], 2000);
+        assert!(legacy.get("max_completion_tokens").is_none());
+
+        let from_env = |value: &str| {
+            Config::from_vars(|var| match var {
+                "OPENAI_API_KEY" | "OPENAI_MODEL" => Some("set".to_string()),
+                LEGACY_MAX_TOKENS_VAR => Some(value.to_string()),
+                _ => None,
+            })
+            .unwrap()
+            .legacy_max_tokens
+        };
+        assert!(from_env("true"));
+        assert!(!from_env("0"));
+    }
+
+    #[test]
+    fn test_strict_config() {
+        let prepared = |strict| {
+            let config = Config::builder()
+                .model("gpt-4o")
+                .strict(strict)
+                .build()
+                .unwrap();
+            let client = LlmClient::with_http_client(reqwest::Client::new(), config);
+            let mut query = OpenAIChatCompletionQuery::new(
+                "gpt-4o".to_string(),
+                Vec::new(),
+                get_schema::<SimpleResponseSchema>(),
+            );
+            prepare_query(&client, &mut query).unwrap();
+            serde_json::to_value(query).unwrap()["response_format"]["json_schema"]["strict"].clone()
+        };
+        assert_eq!(prepared(true), true);
+        assert_eq!(prepared(false), false);
+        assert!(Config::builder().model("gpt-4o").build().unwrap().strict);
+
+        let from_env = |value: Option<&str>| {
+            Config::from_vars(|var| match var {
+                "OPENAI_API_KEY" | "OPENAI_MODEL" => Some("set".to_string()),
+                STRICT_VAR => value.map(str::to_string),
+                _ => None,
+            })
+            .unwrap()
+            .strict
+        };
+        assert!(from_env(None));
+        assert!(from_env(Some("true")));
+        assert!(!from_env(Some("false")));
+        assert!(!from_env(Some("0")));
+    }
+
+    #[test]
+    fn test_stop_sequences() {
+        let query = |stop: Option<Vec<&str>>| {
+            let params = QueryParams {
+                stop: stop.map(|stop| stop.into_iter().map(String::from).collect()),
+                ..QueryParams::default()
+            };
+            params.validate().map(|()| {
+                serde_json::to_value(OpenAIChatCompletionQuery {
+                    params,
+                    ..OpenAIChatCompletionQuery::text("gpt-4o".to_string(), Vec::new())
+                })
+                .unwrap()
+            })
+        };
+
+        assert_eq!(
+            query(Some(vec!["\n\n", "END"])).unwrap()["stop"],
+            serde_json::json!(["\n\n", "END"])
+        );
+        assert!(query(None).unwrap().get("stop").is_none());
+        assert!(query(Some(Vec::new())).unwrap().get("stop").is_none());
+        assert!(query(Some(vec!["a"; MAX_STOP_SEQUENCES])).is_ok());
+        assert!(matches!(
+            query(Some(vec!["a"; MAX_STOP_SEQUENCES + 1])),
+            Err(LlmError::InvalidParameter { name: "stop", .. })
+        ));
+    }
+
+    #[test]
+    fn test_penalties() {
+        let params = |presence_penalty, frequency_penalty| QueryParams {
+            presence_penalty,
+            frequency_penalty,
+            ..QueryParams::default()
+        };
+
+        let query = OpenAIChatCompletionQuery::text("gpt-4o".to_string(), Vec::new())
+            .with_params(params(Some(0.5), Some(-2.0)));
+        let body = serde_json::to_value(&query).unwrap();
+        assert_eq!(body["presence_penalty"], 0.5);
+        assert_eq!(body["frequency_penalty"], -2.0);
+        assert!(query.params.validate().is_ok());
+
+        let unset = serde_json::to_value(OpenAIChatCompletionQuery::text(
+            "gpt-4o".to_string(),
+            Vec::new(),
+        ))
+        .unwrap();
+        assert!(unset.get("presence_penalty").is_none());
+        assert!(unset.get("frequency_penalty").is_none());
+
+        assert!(matches!(
+            params(Some(2.5), None).validate(),
+            Err(LlmError::InvalidParameter {
+                name: "presence_penalty",
+                ..
+            })
+        ));
+        assert!(matches!(
+            params(None, Some(f32::NAN)).validate(),
+            Err(LlmError::InvalidParameter {
+                name: "frequency_penalty",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_reasoning_effort() {
+        let params = QueryParams {
+            reasoning_effort: Some(ReasoningEffort::High),
+            ..QueryParams::default()
+        };
+        let prepared = |model: &str| {
+            let mut query = OpenAIChatCompletionQuery {
+                params: params.clone(),
+                ..OpenAIChatCompletionQuery::text(model.to_string(), Vec::new())
+            };
+            query.params.drop_unsupported(&query.model);
+            serde_json::to_value(query).unwrap()
+        };
+
+        assert_eq!(prepared("o3-mini-2025-01-31")["reasoning_effort"], "high");
+        assert_eq!(prepared("o1")["reasoning_effort"], "high");
+        assert_eq!(prepared("openai/o4-mini")["reasoning_effort"], "high");
+        assert!(prepared("gpt-4o").get("reasoning_effort").is_none());
+        assert!(prepared("o1xyz").get("reasoning_effort").is_none());
+    }
+
+    #[test]
+    fn test_schema_with_opts() {
+        let default = get_schema::<SimpleResponseSchema>();
+        let schema = get_schema_with_opts::<SimpleResponseSchema>(&SchemaOptions {
+            strict: false,
+            name: Some("simple_summary".to_string()),
+            ..SchemaOptions::default()
+        })
+        .unwrap();
+        assert_eq!(schema.name, "simple_summary");
+        assert!(!schema.strict);
+        assert_eq!(schema.schema, default.schema);
+        assert!(default.strict);
+
+        for name in ["", "has space", &"a".repeat(MAX_SCHEMA_NAME_LENGTH + 1)] {
+            let options = SchemaOptions {
+                name: Some(name.to_string()),
+                ..SchemaOptions::default()
+            };
+            assert!(matches!(
+                get_schema_with_opts::<SimpleResponseSchema>(&options),
+                Err(LlmError::InvalidSchemaName(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_schema_named() {
+        #[derive(serde::Deserialize, schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Invoice {
+            total: f64,
+        }
+        #[derive(serde::Deserialize, schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Receipt {
+            total: f64,
+        }
+
+        let named = get_schema_named::<Invoice>("invoice").unwrap();
+        assert_eq!(named.name(), "invoice");
+        assert_eq!(named.schema, get_schema::<Invoice>().schema);
+        assert!(matches!(
+            get_schema_named::<Invoice>("an invoice"),
+            Err(LlmError::InvalidSchemaName(_))
+        ));
+
+        assert!(get_schema::<Receipt>().name().ends_with("_Receipt"));
+        set_schema_name::<Receipt>("receipt").unwrap();
+        assert_eq!(get_schema::<Receipt>().name(), "receipt");
+        // An explicit name still takes precedence.
+        assert_eq!(
+            get_schema_named::<Receipt>("till_receipt").unwrap().name(),
+            "till_receipt"
+        );
+        assert!(matches!(
+            set_schema_name::<Receipt>("receipt!"),
+            Err(LlmError::InvalidSchemaName(_))
+        ));
+        assert_eq!(get_schema::<Receipt>().name(), "receipt");
+    }
+
+    #[test]
+    fn test_schema_new() {
+        let json = serde_json::json!({
+            "type": "object",
+            "properties": { "city": { "type": "string" } },
+            "required": ["city"],
+            "additionalProperties": false,
+        });
+        let schema = Schema::new("capital", json.clone(), true).unwrap();
+        assert_eq!(schema.name(), "capital");
+        assert_eq!(*schema.json_schema(), json);
+        assert!(schema.strict());
+        assert_eq!(validate_schema(&schema), Ok(()));
+
+        let query = OpenAIChatCompletionQuery::new("gpt-4o".to_string(), Vec::new(), schema);
+        assert_eq!(
+            serde_json::to_value(&query).unwrap()["response_format"]["json_schema"],
+            serde_json::json!({ "name": "capital", "schema": json, "strict": true })
+        );
+
+        assert!(matches!(
+            Schema::new("has space", json, false),
+            Err(LlmError::InvalidSchemaName(_))
+        ));
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Route {
+        #[schemars(description = "Where the route starts")]
+        start: Stop,
+        stops: Vec<Stop>,
+        plan: Plan,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Stop {
+        name: String,
+    }
+
+    /// A recursive type, which cannot be inlined.
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Plan {
+        step: Stop,
+        then: Option<Box<Plan>>,
+    }
+
+    #[test]
+    fn test_inline_definitions() {
+        let options = SchemaOptions {
+            inline_definitions: true,
+            ..SchemaOptions::default()
+        };
+        let referenced = get_schema::<Route>().schema;
+        assert!(referenced["$defs"].get("Stop").is_some());
+
+        let schema = get_schema_with_opts::<Route>(&options).unwrap().schema;
+        let properties = &schema["properties"];
+        assert_eq!(properties["start"]["description"], "Where the route starts");
+        assert_eq!(properties["start"]["properties"]["name"]["type"], "string");
+        assert_eq!(properties["stops"]["items"]["type"], "object");
+        assert_eq!(properties["plan"]["$ref"], "#/$defs/Plan");
+
+        // Only the recursive definition is kept, with the others inlined into it.
+        let defs = schema["$defs"].as_object().unwrap();
+        assert_eq!(defs.keys().collect::<Vec<_>>(), ["Plan"]);
+        assert_eq!(defs["Plan"]["properties"]["step"]["type"], "object");
+        assert!(!schema.to_string().contains("#/$defs/Stop"));
+
+        let stops = get_schema_with_opts::<Vec<Stop>>(&options).unwrap().schema;
+        assert!(stops.get("$defs").is_none());
+        assert_eq!(stops["items"]["properties"]["name"]["type"], "string");
+
+        let route: Route = serde_json::from_str(
+            r#"{"start": {"name": "A"}, "stops": [], "plan": {"step": {"name": "B"}, "then": null}}"#,
+        )
+        .unwrap();
+        assert_eq!(route.plan.then, None);
+    }
+
+    #[test]
+    fn test_schema_cache() {
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Cached {
+            value: f64,
+        }
+
+        let first = get_schema::<Cached>();
+        assert_eq!(
+            SCHEMA_CACHE
+                .read()
+                .unwrap()
+                .get(&std::any::TypeId::of::<Cached>()),
+            Some(&first.schema)
+        );
+        let second = get_schema::<Cached>();
+        assert_eq!(first.schema, second.schema);
+        assert_eq!(first.name, second.name);
+    }
+
+    #[test]
+    fn test_schema_is_deterministic() {
+        let first = serde_json::to_string(&get_schema::<ComplexResponseSchema>()).unwrap();
+        let second = serde_json::to_string(&get_schema::<ComplexResponseSchema>()).unwrap();
+        assert_eq!(first, second);
+
+        // Properties follow the declaration order of the struct fields.
+        let schema = get_schema::<ComplexResponseSchema>().schema;
+        let properties: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(
+            properties,
+            [
+                "summary",
+                "word_count",
+                "flair",
+                "responses",
+                "sentiments",
+                "object_in_object"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_html_error_page() {
+        let body = "<html><head><title>502 Bad Gateway</title></head></html>";
+        let error = parse_response(Some("text/html; charset=utf-8"), body).unwrap_err();
+
+        match error {
+            LlmError::UnexpectedContentType {
+                content_type,
+                body_snippet,
+            } => {
+                assert_eq!(content_type, "text/html; charset=utf-8");
+                assert_eq!(body_snippet, body);
+            }
+            other => panic!("Expected UnexpectedContentType, got {other:?}"),
+        }
+
+        let body = r#"{"choices": [{"message": {"content": "{}"}}]}"#;
+        let response = parse_response(Some("application/json"), body).unwrap();
+        assert_eq!(response.content().unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_refusal() {
+        let response = parse_response(
+            Some("application/json"),
+            r#"{"choices": [{"message": {"content": null, "refusal": "I can't help with that."}}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            response.content(),
+            Err(LlmError::Refusal(refusal)) if refusal == "I can't help with that."
+        ));
+
+        let response = parse_response(None, r#"{"choices": [{"message": {}}]}"#).unwrap();
+        assert!(matches!(
+            response.content(),
+            Err(LlmError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn test_content_parts() {
+        let body = r#"{"choices": [{"message": {"content": [
+            {"type": "text", "text": "{\"summary\": "},
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}},
+            {"type": "output_text", "text": "\"Short\"}"}
+        ]}}]}"#;
+        let response = parse_response(Some("application/json"), body).unwrap();
+        assert_eq!(response.content().unwrap(), r#"{"summary": "Short"}"#);
+
+        let body = r#"{"choices": [{"message": {"content": [{"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}]}}]}"#;
+        let response = parse_response(Some("application/json"), body).unwrap();
+        assert!(matches!(
+            response.content(),
+            Err(LlmError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn test_unparseable_content() {
+        // Valid JSON for the API response, but the content does not match the schema.
+        let body = r#"{"choices": [{"message": {"content": "{\"summary\": 42}"}}]}"#;
+        let response = parse_response(Some("application/json"), body).unwrap();
+        let error = parse_structured::<SimpleResponseSchema>(response.content().unwrap());
+        assert!(matches!(error, Err(LlmError::ContentParse(_))));
+
+        let body = r#"{"choices": [{"message": {"content": "I can't help with that"}}]}"#;
+        let response = parse_response(Some("application/json"), body).unwrap();
+        assert!(parse_structured::<SimpleResponseSchema>(response.content().unwrap()).is_err());
+
+        let response = parse_response(Some("application/json"), r#"{"choices": []}"#).unwrap();
+        assert!(matches!(response.content(), Err(LlmError::EmptyChoices)));
+
+        let body = r#"{"choices": [{"message": {"content": "{\"summary\": \"The"}, "finish_reason": "length"}]}"#;
+        let response = parse_response(Some("application/json"), body).unwrap();
+        assert!(matches!(response.content(), Err(LlmError::Truncated)));
+        let body = r#"{"choices": [{"message": {"content": "{}"}, "finish_reason": "stop"}]}"#;
+        let response = parse_response(Some("application/json"), body).unwrap();
+        assert_eq!(response.content().unwrap(), "{}");
+        assert!(matches!(
+            parse_response(Some("application/json"), "{"),
+            Err(LlmError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn test_schema_name_prefix() {
+        assert_eq!(
+            schema_name("my_crate::Summary", "myapp_"),
+            "myapp_my_crate_Summary"
+        );
+        assert_eq!(schema_name("my_crate::Summary", ""), "my_crate_Summary");
+
+        let long_type_name = format!("my_crate::{}", "A".repeat(100));
+        let name = schema_name(&long_type_name, "myapp_");
+        assert_eq!(name.len(), MAX_SCHEMA_NAME_LENGTH);
+        assert!(name.starts_with("myapp_my_crate_"));
+
+        // Both truncated names share their first 55 characters.
+        let other = schema_name(&format!("{long_type_name}B"), "myapp_");
+        assert_eq!(other.len(), MAX_SCHEMA_NAME_LENGTH);
+        assert_ne!(name, other);
+        assert_eq!(name, schema_name(&long_type_name, "myapp_"));
+
+        let generic = schema_name(
+            std::any::type_name::<Vec<std::collections::HashMap<String, &[(u8, char)]>>>(),
+            "",
+        );
+        assert!(generic.len() <= MAX_SCHEMA_NAME_LENGTH);
         assert!(
-            response.flair >= 0.0 && response.flair <= 1.0,
-            "Flair should be between 0 and 1"
+            generic
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            "{generic}"
+        );
+
+        for prefix in ["my app".to_string(), "a".repeat(MAX_SCHEMA_NAME_LENGTH)] {
+            assert!(matches!(
+                set_schema_prefix(prefix.clone()),
+                Err(LlmError::InvalidSchemaName(name)) if name == prefix
+            ));
+        }
+    }
+
+    #[test]
+    fn test_shutdown() {
+        shutdown();
+        shutdown();
+
+        let _ = client();
+        assert!(CLIENT.read().unwrap().is_some());
+        shutdown();
+    }
+
+    /// Whether `key` appears anywhere in the schema.
+    fn contains_key(value: &serde_json::Value, key: &str) -> bool {
+        match value {
+            serde_json::Value::Object(object) => {
+                object.contains_key(key) || object.values().any(|v| contains_key(v, key))
+            }
+            serde_json::Value::Array(array) => array.iter().any(|v| contains_key(v, key)),
+            _ => false,
+        }
+    }
+
+    fn timestamp_schema(_generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "type": "object",
+            "properties": {
+                "timestamp": { "type": "string", "format": "date-time" },
+                "offset": { "type": "integer", "format": "int32" },
+            },
+            "required": ["timestamp", "offset"],
+        })
+    }
+
+    /// A newtype serialized as a plain float.
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    struct Score(#[allow(dead_code)] f64);
+
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct CustomSchemaResponse {
+        #[schemars(schema_with = "timestamp_schema")]
+        published: serde_json::Value,
+
+        #[schemars(with = "f64")]
+        flair: String,
+
+        #[schemars(with = "Vec<Score>")]
+        scores: Vec<f64>,
+    }
+
+    #[test]
+    fn test_custom_schema_overrides_are_transformed() {
+        let schema = get_schema::<CustomSchemaResponse>().schema;
+
+        assert!(!contains_key(&schema, "format"), "{schema:#}");
+        assert_eq!(
+            schema["properties"]["published"]["properties"]["timestamp"],
+            serde_json::json!({ "type": "string" })
+        );
+        assert_eq!(
+            schema["properties"]["flair"],
+            serde_json::json!({ "type": "number" })
+        );
+        assert_eq!(schema["$defs"]["Score"]["type"], "number");
+    }
+
+    #[test]
+    fn test_config_reports_all_missing_vars() {
+        let errors = Config::validate_vars(|_| None);
+        assert_eq!(
+            errors,
+            [
+                ConfigError::Missing("OPENAI_API_KEY"),
+                ConfigError::Missing("OPENAI_MODEL")
+            ]
+        );
+
+        let errors = Config::validate_vars(|var| (var == "OPENAI_MODEL").then(String::new));
+        assert_eq!(errors, [ConfigError::Missing("OPENAI_API_KEY")]);
+
+        let errors = Config::validate_vars(|var| (var == NO_AUTH_VAR).then(|| "1".to_string()));
+        assert_eq!(errors, [ConfigError::Missing("OPENAI_MODEL")]);
+
+        let errors = Config::validate_vars(|var| match var {
+            "OPENAI_MODEL" => Some("llama3".to_string()),
+            BASE_URL_VAR => Some("localhost:11434/v1".to_string()),
+            _ => None,
+        });
+        assert_eq!(
+            errors,
+            [
+                ConfigError::Missing("OPENAI_API_KEY"),
+                ConfigError::InvalidBaseUrl("localhost:11434/v1".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = Config::from_vars(|var| match var {
+            "OPENAI_API_KEY" => Some("sk-test".to_string()),
+            "OPENAI_MODEL" => Some("gpt-4o".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.api_key.as_deref(), Some("sk-test"));
+        assert_eq!(config.model, "gpt-4o");
+        assert_eq!(config.base_url, DEFAULT_BASE_URL);
+        assert_eq!(config.timeout, None);
+
+        let timeout = |secs: &'static str| {
+            Config::from_vars(move |var| match var {
+                "OPENAI_API_KEY" => Some("sk-test".to_string()),
+                "OPENAI_MODEL" => Some("gpt-4o".to_string()),
+                TIMEOUT_VAR => Some(secs.to_string()),
+                _ => None,
+            })
+            .map(|config| config.timeout)
+        };
+        assert_eq!(timeout("30"), Ok(Some(std::time::Duration::from_secs(30))));
+        assert_eq!(
+            timeout("2.5"),
+            Ok(Some(std::time::Duration::from_millis(2500)))
         );
+        assert_eq!(
+            timeout("soon"),
+            Err(ConfigError::InvalidTimeout("soon".to_string()))
+        );
+        assert!(timeout("-1").is_err());
+
+        let rate_limit = |rpm: &'static str| {
+            Config::from_vars(move |var| match var {
+                "OPENAI_API_KEY" => Some("sk-test".to_string()),
+                "OPENAI_MODEL" => Some("gpt-4o".to_string()),
+                RPM_LIMIT_VAR => Some(rpm.to_string()),
+                _ => None,
+            })
+            .map(|config| config.rate_limiter.is_some())
+        };
+        assert_eq!(rate_limit("500"), Ok(true));
+        assert_eq!(
+            rate_limit("fast"),
+            Err(ConfigError::InvalidRateLimit {
+                var: RPM_LIMIT_VAR,
+                value: "fast".to_string()
+            })
+        );
+        assert!(config.rate_limiter.is_none());
+
+        let no_auth = Config::from_vars(|var| match var {
+            "OPENAI_API_KEY" => Some("sk-test".to_string()),
+            "OPENAI_MODEL" => Some("llama3".to_string()),
+            NO_AUTH_VAR => Some("1".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(no_auth.api_key, None);
+
+        let missing = Config::from_vars(|var| (var == "OPENAI_API_KEY").then(String::new));
+        assert!(matches!(
+            missing.map_err(LlmError::from),
+            Err(LlmError::MissingConfig(var)) if var == "OPENAI_MODEL"
+        ));
+    }
+
+    #[test]
+    fn test_config_builder() {
+        let config = Config::builder()
+            .api_key("sk-test")
+            .model("gpt-4o-mini")
+            .base_url("https://proxy.example.com/v1/")
+            .build()
+            .unwrap();
+        assert_eq!(config.api_key.as_deref(), Some("sk-test"));
+        assert_eq!(config.model, "gpt-4o-mini");
+        assert_eq!(
+            config.chat_completions_url(),
+            "https://proxy.example.com/v1/chat/completions"
+        );
+
+        let local = Config::builder().model("llama3").build().unwrap();
+        assert_eq!(local.api_key, None);
+        assert_eq!(local.base_url, DEFAULT_BASE_URL);
+
+        assert!(matches!(
+            Config::builder().api_key("sk-test").build(),
+            Err(LlmError::MissingConfig(field)) if field == "model"
+        ));
+        assert!(matches!(
+            Config::builder()
+                .model("gpt-4o")
+                .base_url("localhost:8000")
+                .build(),
+            Err(LlmError::InvalidConfig(ConfigError::InvalidBaseUrl(_)))
+        ));
+    }
+
+    #[test]
+    fn test_base_url() {
+        let base_url = |url: Option<&str>| Config::base_url(|_| url.map(String::from));
+        assert_eq!(base_url(None), "https://api.openai.com/v1");
+        assert_eq!(base_url(Some("https://host/v1")), "https://host/v1");
+        assert_eq!(base_url(Some("https://host/v1/")), "https://host/v1");
+
+        let config = Config::builder()
+            .model("llama3")
+            .base_url("http://localhost:11434/v1/")
+            .build()
+            .unwrap();
+        assert_eq!(
+            config.chat_completions_url(),
+            "http://localhost:11434/v1/chat/completions"
+        );
+
+        // Local servers need no key.
+        let local =
+            |url: &'static str| move |var: &str| (var == BASE_URL_VAR).then(|| url.to_string());
+        assert!(Config::no_auth(local("http://localhost:11434/v1")));
+        assert!(Config::no_auth(local("http://127.0.0.1:8000/v1")));
+        assert!(Config::no_auth(local("http://[::1]:8000/v1")));
+        assert!(!Config::no_auth(local("https://api.groq.com/openai/v1")));
+        assert!(!Config::no_auth(local("https://localhost.example.com/v1")));
+    }
+
+    #[test]
+    fn test_no_auth_request() {
+        let authorization = |api_key| {
+            let request = reqwest::Client::new().post("https://api.openai.com/v1/chat/completions");
+            authorize(request, api_key)
+                .build()
+                .unwrap()
+                .headers()
+                .get(reqwest::header::AUTHORIZATION)
+                .cloned()
+        };
+
+        assert_eq!(authorization(None), None);
+        assert_eq!(authorization(Some("sk-test")).unwrap(), "Bearer sk-test");
+    }
+
+    #[test]
+    fn test_azure_config() {
+        let config = Config::builder()
+            .api_key("azure-key")
+            .azure(AzureConfig::new(
+                "https://my-resource.openai.azure.com",
+                "gpt-4o-prod",
+                "2024-10-21",
+            ))
+            .header("api-key", "other-key")
+            .build()
+            .unwrap();
+        assert_eq!(config.model, "gpt-4o-prod");
+        assert_eq!(
+            config.chat_completions_url(),
+            "https://my-resource.openai.azure.com/openai/deployments/gpt-4o-prod/chat/completions?api-version=2024-10-21"
+        );
+
+        let request = reqwest::Client::new().post(config.chat_completions_url());
+        let request = with_headers(request, &config).build().unwrap();
         assert!(
-            !response.responses.is_empty(),
-            "Responses should not be empty"
+            request
+                .headers()
+                .get(reqwest::header::AUTHORIZATION)
+                .is_none()
+        );
+        let api_key: Vec<_> = request.headers().get_all("api-key").iter().collect();
+        assert_eq!(api_key, ["azure-key"]);
+
+        assert!(matches!(
+            Config::builder()
+                .azure(AzureConfig::new(
+                    "my-resource.openai.azure.com",
+                    "gpt-4o",
+                    "2024-10-21"
+                ))
+                .build(),
+            Err(LlmError::InvalidConfig(ConfigError::InvalidBaseUrl(_)))
+        ));
+    }
+
+    #[test]
+    fn test_custom_headers() {
+        let config = Config::from_vars(|var| match var {
+            "OPENAI_API_KEY" => Some("sk-test".to_string()),
+            "OPENAI_MODEL" => Some("gpt-4o".to_string()),
+            "OPENAI_ORG_ID" => Some("org-123".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.headers["openai-organization"], "org-123");
+        assert!(!config.headers.contains_key("openai-project"));
+
+        let headers = |config: &Config| {
+            let request = reqwest::Client::new().post(config.chat_completions_url());
+            with_headers(request, config)
+                .build()
+                .unwrap()
+                .headers()
+                .clone()
+        };
+        let builder = Config::builder()
+            .model("gpt-4o")
+            .organization("org-123")
+            .project("proj-456")
+            .header("X-Proxy-Auth", "secret")
+            .header("Authorization", "Basic proxy");
+        let config = builder.clone().api_key("sk-test").build().unwrap();
+        let sent = headers(&config);
+        assert_eq!(sent["openai-organization"], "org-123");
+        assert_eq!(sent["openai-project"], "proj-456");
+        assert_eq!(sent["x-proxy-auth"], "secret");
+        let authorization: Vec<_> = sent
+            .get_all(reqwest::header::AUTHORIZATION)
+            .iter()
+            .collect();
+        assert_eq!(authorization, ["Bearer sk-test"]);
+
+        // Without an API key the custom authorization is sent instead.
+        let config = builder.build().unwrap();
+        assert_eq!(
+            headers(&config)[reqwest::header::AUTHORIZATION],
+            "Basic proxy"
+        );
+
+        assert!(matches!(
+            Config::builder().model("gpt-4o").header("Bad Name", "value").build(),
+            Err(LlmError::InvalidConfig(ConfigError::InvalidHeader(name))) if name == "Bad Name"
+        ));
+        let invalid = Config::from_vars(|var| match var {
+            "OPENAI_API_KEY" => Some("sk-test".to_string()),
+            "OPENAI_MODEL" => Some("gpt-4o".to_string()),
+            "OPENAI_PROJECT_ID" => Some("proj\n".to_string()),
+            _ => None,
+        });
+        assert!(matches!(invalid, Err(ConfigError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_system_prompt() {
+        let config = Config::builder()
+            .model("gpt-4o")
+            .system_prompt("Answer in French.")
+            .build()
+            .unwrap();
+        let client = LlmClient::with_http_client(reqwest::Client::new(), config);
+        let prepared = |messages| {
+            let mut query = OpenAIChatCompletionQuery::text("gpt-4o".to_string(), messages);
+            prepare_query(&client, &mut query).unwrap();
+            query.messages
+        };
+
+        let messages = prepared(vec![Message::user("Hello")]);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, Role::Developer);
+        assert_eq!(messages[0].content, "Answer in French.");
+
+        // An explicit developer message takes precedence.
+        let messages = prepared(vec![
+            Message::user("Hello"),
+            Message::developer("Answer in German."),
+        ]);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content, "Answer in German.");
+
+        let config = Config::from_vars(|var| match var {
+            "OPENAI_API_KEY" => Some("sk-test".to_string()),
+            "OPENAI_MODEL" => Some("gpt-4o".to_string()),
+            "OPENAI_SYSTEM_PROMPT" => Some("Be brief.".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.system_prompt.as_deref(), Some("Be brief."));
+    }
+
+    #[test]
+    fn test_dump_schema() {
+        let dir = std::env::temp_dir().join(format!("llm_schema_dump_{}", std::process::id()));
+        let schema = get_schema::<Vec<String>>();
+        let pretty = schema.to_pretty_string();
+        assert!(pretty.starts_with("{\n  \"name\": "), "{pretty}");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            canonical_openai_schema::<Vec<String>>()
+        );
+
+        // The dumped schema is the one sent, with strict mode turned off by the config.
+        let config = Config::from_vars(|var| match var {
+            "OPENAI_API_KEY" => Some("sk-test".to_string()),
+            "OPENAI_MODEL" => Some("gpt-4o".to_string()),
+            "OPENAI_STRICT" => Some("false".to_string()),
+            "OPENAI_DUMP_SCHEMA_DIR" => Some(dir.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .unwrap();
+        let dump_dir = config.dump_schema_dir.clone().unwrap();
+        let client = LlmClient::with_http_client(reqwest::Client::new(), config);
+        let mut query = OpenAIChatCompletionQuery::new(
+            "gpt-4o".to_string(),
+            vec![Message::user("Hello")],
+            schema.clone(),
+        );
+        prepare_query(&client, &mut query).unwrap();
+        dump_schema(&dump_dir, &query);
+
+        let dumped = std::fs::read_to_string(dir.join(format!("{}.json", schema.name()))).unwrap();
+        let dumped: serde_json::Value = serde_json::from_str(&dumped).unwrap();
+        assert_eq!(dumped["strict"], false);
+        assert_eq!(dumped["schema"], *schema.json_schema());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct FixedLengthResponse {
+        #[schemars(description = "The name and age of the author")]
+        author: (String, i64),
+
+        coordinates: [f64; 3],
+    }
+
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+    struct PermissiveOuter {
+        inner: PermissiveInner,
+        list: Vec<PermissiveInner>,
+    }
+
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+    struct PermissiveInner {
+        value: i64,
+        #[serde(flatten)]
+        extra: std::collections::BTreeMap<String, String>,
+    }
+
+    /// Every object schema with properties in `value`.
+    fn object_schemas(value: &serde_json::Value) -> Vec<&serde_json::Value> {
+        let mut objects = Vec::new();
+        match value {
+            serde_json::Value::Object(object) => {
+                if object.contains_key("properties") {
+                    objects.push(value);
+                }
+                objects.extend(object.values().flat_map(object_schemas));
+            }
+            serde_json::Value::Array(array) => {
+                objects.extend(array.iter().flat_map(object_schemas))
+            }
+            _ => {}
+        }
+        objects
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    enum Action {
+        Move { x: i64, y: i64 },
+        Say(String),
+        Stop,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct ActionPlan {
+        first: Action,
+        then: Option<Action>,
+    }
+
+    #[test]
+    fn test_data_carrying_enum() {
+        let schema = get_schema::<ActionPlan>().schema;
+        let action = &schema["$defs"]["Action"];
+        assert!(action.get("oneOf").is_none());
+        let variants = action["anyOf"].as_array().unwrap();
+        assert_eq!(variants.len(), 3);
+        for object in object_schemas(action) {
+            assert_eq!(object["additionalProperties"], false, "{object:#}");
+            assert_eq!(
+                object["required"].as_array().unwrap().len(),
+                object["properties"].as_object().unwrap().len(),
+                "{object:#}"
+            );
+        }
+        assert_eq!(crate::validate_schema(&get_schema::<ActionPlan>()), Ok(()));
+
+        let plan: ActionPlan =
+            serde_json::from_str(r#"{"first": {"Move": {"x": 1, "y": -2}}, "then": "Stop"}"#)
+                .unwrap();
+        assert_eq!(
+            plan,
+            ActionPlan {
+                first: Action::Move { x: 1, y: -2 },
+                then: Some(Action::Stop),
+            }
         );
+        let plan: ActionPlan =
+            serde_json::from_str(r#"{"first": {"Say": "Hi"}, "then": null}"#).unwrap();
+        assert_eq!(plan.first, Action::Say("Hi".to_string()));
+        assert_eq!(plan.then, None);
+    }
+
+    #[test]
+    fn test_additional_properties_forbidden() {
+        let schema = get_schema::<PermissiveOuter>().schema;
+        assert_eq!(schema["additionalProperties"], false);
+
+        // The flattened map keeps its value schema rather than being forbidden.
+        let inner = &schema["$defs"]["PermissiveInner"];
+        assert_eq!(
+            inner["additionalProperties"],
+            serde_json::json!({ "type": "string" })
+        );
+        let objects = object_schemas(&schema);
+        assert_eq!(objects.len(), 2);
         assert!(
-            !response.sentiments.is_empty(),
-            "Sentiments should not be empty"
+            objects
+                .iter()
+                .all(|o| o.get("additionalProperties").is_some())
+        );
+
+        let outer: PermissiveOuter = serde_json::from_str(
+            r#"{"inner": {"value": 1}, "list": [{"value": 2, "note": "kept"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(outer.inner.value, 1);
+        assert_eq!(outer.list[0].extra["note"], "kept");
+    }
+
+    #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+    struct OptionalFields {
+        title: String,
+        subtitle: Option<String>,
+    }
+
+    #[test]
+    fn test_all_properties_required() {
+        let schema = get_schema::<OptionalFields>().schema;
+        assert_eq!(schema["required"], serde_json::json!(["title", "subtitle"]));
+        assert_eq!(
+            schema["properties"]["subtitle"]["type"],
+            serde_json::json!(["string", "null"])
+        );
+
+        let parsed: OptionalFields =
+            serde_json::from_str(r#"{"title": "Dune", "subtitle": "Book one"}"#).unwrap();
+        assert_eq!(parsed.title, "Dune");
+        assert_eq!(parsed.subtitle.as_deref(), Some("Book one"));
+
+        // Every object of the recursive schemas must list all of its properties.
+        for schema in [
+            get_schema::<PermissiveOuter>().schema,
+            get_schema::<ComplexResponseSchema>().schema,
+        ] {
+            for object in object_schemas(&schema) {
+                let properties: Vec<&String> =
+                    object["properties"].as_object().unwrap().keys().collect();
+                let required: Vec<&str> = object["required"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|key| key.as_str().unwrap())
+                    .collect();
+                assert_eq!(properties, required, "{object:#}");
+            }
+        }
+    }
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    struct NullableFields {
+        subtitle: Option<String>,
+        #[serde(default)]
+        #[schemars(with = "String")]
+        note: Option<String>,
+        #[serde(default)]
+        #[schemars(with = "Sentiment")]
+        sentiment: Option<Sentiment>,
+        #[serde(default)]
+        count: i64,
+    }
+
+    #[test]
+    fn test_optional_fields_nullable() {
+        let schema = get_schema::<NullableFields>().schema;
+        let properties = &schema["properties"];
+        assert_eq!(
+            schema["required"],
+            serde_json::json!(["subtitle", "note", "sentiment", "count"])
+        );
+        assert_eq!(
+            properties["subtitle"]["type"],
+            serde_json::json!(["string", "null"])
+        );
+        assert_eq!(
+            properties["note"]["type"],
+            serde_json::json!(["string", "null"])
         );
+        assert_eq!(
+            properties["sentiment"]["anyOf"][1],
+            serde_json::json!({ "type": "null" })
+        );
+        // `null` is not a valid answer for a field with another default.
+        assert_eq!(properties["count"]["type"], "integer");
+
+        let parsed: NullableFields = serde_json::from_str(
+            r#"{"subtitle": null, "note": null, "sentiment": null, "count": 3}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.subtitle, None);
+        assert_eq!(parsed.note, None);
+        assert!(parsed.sentiment.is_none());
+        assert_eq!(parsed.count, 3);
+    }
+
+    #[test]
+    fn test_tuple_and_fixed_array_schema() {
+        let schema = get_schema::<FixedLengthResponse>().schema;
+        for key in ["prefixItems", "minItems", "maxItems"] {
+            assert!(!contains_key(&schema, key), "{key} in {schema:#}");
+        }
+
+        let author = &schema["properties"]["author"];
+        assert_eq!(
+            author["items"],
+            serde_json::json!({ "anyOf": [{ "type": "string" }, { "type": "integer" }] })
+        );
+        // The length and the order of the types are added to the description.
+        assert_eq!(
+            author["description"],
+            "The name and age of the author. Exactly 2 items, in this order: string, integer"
+        );
+
+        let coordinates = &schema["properties"]["coordinates"];
+        assert_eq!(
+            coordinates["items"],
+            serde_json::json!({ "type": "number" })
+        );
+        assert_eq!(coordinates["description"], "Exactly 3 items");
+
+        let response: FixedLengthResponse = serde_json::from_value(serde_json::json!({
+            "author": ["Ada", 36],
+            "coordinates": [1.0, 2.0, 3.0],
+        }))
+        .unwrap();
+        assert_eq!(response.author, ("Ada".to_string(), 36));
+        assert_eq!(response.coordinates, [1.0, 2.0, 3.0]);
+
+        // The length is still enforced when parsing.
         assert!(
-            !response.object_in_object.summary.is_empty(),
-            "Object in object summary should not be empty"
+            serde_json::from_value::<FixedLengthResponse>(serde_json::json!({
+                "author": ["Ada", 36],
+                "coordinates": [1.0, 2.0],
+            }))
+            .is_err()
+        );
+        // So is the type of each position, which the schema does not express.
+        assert!(
+            serde_json::from_value::<FixedLengthResponse>(serde_json::json!({
+                "author": [36, "Ada"],
+                "coordinates": [1.0, 2.0, 3.0],
+            }))
+            .is_err()
+        );
+    }
+
+    // A recursive type, which schemars places in `$defs` and refers to by `#/$defs/TreeNode`.
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct TreeNode {
+        weight: i64,
+        children: Vec<TreeNode>,
+    }
+
+    #[derive(Debug, Clone, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(deny_unknown_fields)]
+    struct TreeResponse {
+        root: TreeNode,
+    }
+
+    #[test]
+    fn test_recursive_definitions_are_transformed() {
+        let schema = get_schema::<TreeResponse>().schema;
+        let node = &schema["$defs"]["TreeNode"];
+        assert_eq!(schema["properties"]["root"]["$ref"], "#/$defs/TreeNode");
+        assert_eq!(
+            node["properties"]["children"]["items"]["$ref"],
+            "#/$defs/TreeNode"
         );
+        assert_eq!(node["additionalProperties"], false);
+        assert_eq!(node["required"], serde_json::json!(["weight", "children"]));
+        assert!(!contains_key(node, "format"));
+
+        let response: TreeResponse = serde_json::from_value(serde_json::json!({
+            "root": { "weight": 1, "children": [{ "weight": 2, "children": [] }] }
+        }))
+        .unwrap();
+        assert_eq!(response.root.weight, 1);
+        assert_eq!(response.root.children[0].weight, 2);
+        assert!(response.root.children[0].children.is_empty());
     }
 }
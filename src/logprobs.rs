@@ -0,0 +1,241 @@
+use crate::{
+    LlmClient, LlmError, Message, OpenAIChatCompletionQuery, OpenAIChatCompletionResponse,
+    QueryParams, parse_structured, run_query, try_get_schema,
+};
+
+/// Log probabilities of the tokens in a choice's content.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Logprobs {
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+impl Logprobs {
+    /// The mean log probability of the tokens of the content, a rough measure of how certain
+    /// the model was about its answer. `None` without any tokens.
+    pub fn average_logprob(&self) -> Option<f64> {
+        let tokens = self
+            .content
+            .as_deref()
+            .filter(|tokens| !tokens.is_empty())?;
+        Some(tokens.iter().map(|token| token.logprob).sum::<f64>() / tokens.len() as f64)
+    }
+}
+
+/// A parsed response together with the log probabilities of the tokens it was parsed from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithLogprobs<T> {
+    pub value: T,
+    /// `None` if the API did not return any, such as for compatible backends without
+    /// support for them.
+    pub logprobs: Option<Logprobs>,
+}
+
+/// Query OpenAI like [`crate::query_openai`], also returning the log probability of each
+/// token of the response and of the `top_logprobs` most likely alternatives for it.
+pub async fn query_openai_with_logprobs<T>(
+    messages: Vec<Message>,
+    top_logprobs: Option<u8>,
+) -> Result<WithLogprobs<T>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let client = LlmClient::default();
+    let query = OpenAIChatCompletionQuery::new(
+        client.config.model.clone(),
+        messages,
+        try_get_schema::<T>()?,
+    )
+    .with_params(QueryParams {
+        logprobs: true,
+        top_logprobs,
+        ..QueryParams::default()
+    });
+    let (response, _) = run_query(&client, query).await?;
+    with_logprobs(response)
+}
+
+fn with_logprobs<T>(response: OpenAIChatCompletionResponse) -> Result<WithLogprobs<T>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let value = parse_structured(response.content()?)?;
+    let logprobs = response
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.logprobs);
+    Ok(WithLogprobs { value, logprobs })
+}
+
+/// The log probability of a single token. `bytes` holds the UTF-8 bytes of the token, which
+/// is the only faithful representation when a multi-byte character is split across tokens,
+/// as `token` cannot hold half a character.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// One of the most likely alternatives for a token.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Map each character of the content to the log probability that it was generated. A
+/// character made up of bytes from several tokens gets the sum of their log probabilities,
+/// i.e. the log of the joint probability. Invalid UTF-8 is replaced by `U+FFFD`.
+pub fn char_logprobs(tokens: &[TokenLogprob]) -> Vec<(char, f64)> {
+    let mut chars = Vec::new();
+    // Bytes of a character not yet complete and the log probability of the tokens they
+    // came from.
+    let mut pending: Vec<u8> = Vec::new();
+    let mut pending_logprob = 0.0;
+
+    for token in tokens {
+        let bytes = token.bytes.as_deref().unwrap_or(token.token.as_bytes());
+        let mut counted = false;
+
+        for &byte in bytes {
+            if !counted {
+                pending_logprob += token.logprob;
+                counted = true;
+            }
+            pending.push(byte);
+
+            let c = match std::str::from_utf8(&pending) {
+                Ok(decoded) => decoded.chars().next().expect("One byte was added"),
+                // Incomplete character, so wait for the rest of its bytes.
+                Err(error) if error.error_len().is_none() => continue,
+                Err(_) => char::REPLACEMENT_CHARACTER,
+            };
+            chars.push((c, pending_logprob));
+            pending.clear();
+            pending_logprob = 0.0;
+            counted = false;
+        }
+    }
+
+    if !pending.is_empty() {
+        chars.push((char::REPLACEMENT_CHARACTER, pending_logprob));
+    }
+    chars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_logprobs_multibyte() {
+        // "né!" where the two bytes of "é" are split across tokens, as OpenAI does for
+        // characters that are rare in its vocabulary.
+        let tokens: Vec<TokenLogprob> = serde_json::from_value(serde_json::json!([
+            { "token": "n", "logprob": -0.5, "bytes": [110], "top_logprobs": [] },
+            { "token": "\\xc3", "logprob": -0.25, "bytes": [195] },
+            { "token": "\\xa9!", "logprob": -1.0, "bytes": [169, 33] },
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            char_logprobs(&tokens),
+            [('n', -0.5), ('é', -1.25), ('!', -1.0)]
+        );
+    }
+
+    #[test]
+    fn test_char_logprobs_invalid_utf8() {
+        let tokens = vec![
+            TokenLogprob {
+                token: "a".to_string(),
+                logprob: -0.1,
+                bytes: None,
+                top_logprobs: Vec::new(),
+            },
+            TokenLogprob {
+                token: String::new(),
+                logprob: -2.0,
+                bytes: Some(vec![0xe2, 0x82]),
+                top_logprobs: Vec::new(),
+            },
+        ];
+
+        assert_eq!(
+            char_logprobs(&tokens),
+            [('a', -0.1), (char::REPLACEMENT_CHARACTER, -2.0)]
+        );
+    }
+
+    #[test]
+    fn test_with_logprobs() {
+        let response: OpenAIChatCompletionResponse = serde_json::from_value(serde_json::json!({
+            "choices": [{
+                "message": { "content": "[\"a\"]" },
+                "logprobs": { "content": [
+                    { "token": "[\"", "logprob": -0.5, "bytes": null, "top_logprobs": [] },
+                    { "token": "a", "logprob": -1.5, "bytes": null, "top_logprobs": [
+                        { "token": "a", "logprob": -1.5, "bytes": [97] },
+                        { "token": "b", "logprob": -2.0, "bytes": [98] },
+                    ] },
+                    { "token": "\"]", "logprob": -1.0, "bytes": null, "top_logprobs": [] },
+                ] },
+            }],
+        }))
+        .unwrap();
+
+        let response = with_logprobs::<Vec<String>>(response).unwrap();
+        assert_eq!(response.value, ["a"]);
+        let logprobs = response.logprobs.unwrap();
+        assert_eq!(logprobs.average_logprob(), Some(-1.0));
+        assert_eq!(logprobs.content.unwrap()[1].top_logprobs[1].token, "b");
+        assert_eq!(Logprobs { content: None }.average_logprob(), None);
+    }
+
+    #[test]
+    fn test_logprobs_params() {
+        let params = |logprobs, top_logprobs| QueryParams {
+            logprobs,
+            top_logprobs,
+            ..QueryParams::default()
+        };
+        let body = |params| {
+            serde_json::to_value(
+                OpenAIChatCompletionQuery::text("gpt-4o".to_string(), Vec::new())
+                    .with_params(params),
+            )
+            .unwrap()
+        };
+
+        let enabled = body(params(true, Some(3)));
+        assert_eq!(enabled["logprobs"], true);
+        assert_eq!(enabled["top_logprobs"], 3);
+        let disabled = body(params(false, None));
+        assert!(disabled.get("logprobs").is_none());
+        assert!(disabled.get("top_logprobs").is_none());
+
+        assert!(
+            params(true, Some(crate::MAX_TOP_LOGPROBS))
+                .validate()
+                .is_ok()
+        );
+        assert!(matches!(
+            params(true, Some(21)).validate(),
+            Err(LlmError::InvalidParameter {
+                name: "top_logprobs",
+                ..
+            })
+        ));
+        assert!(matches!(
+            params(false, Some(1)).validate(),
+            Err(LlmError::InvalidParameter {
+                name: "top_logprobs",
+                ..
+            })
+        ));
+    }
+}
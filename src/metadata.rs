@@ -0,0 +1,37 @@
+use crate::{
+    LlmError, Message, RetryStats, Usage, parse_structured, query_openai_inner_with_stats,
+    try_get_schema,
+};
+
+/// A parsed response together with details about the request that produced it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithMetadata<T> {
+    pub value: T,
+    /// Tokens used, if reported by the API.
+    pub usage: Option<Usage>,
+    /// The backend configuration that produced the response, if reported by the API. When it
+    /// changes, seeded requests may no longer reproduce earlier results, see
+    /// [`crate::QueryParams::seed`].
+    pub system_fingerprint: Option<String>,
+    /// The attempts the request took, to diagnose flaky endpoints and tune the
+    /// [`crate::RetryPolicy`].
+    pub retries: RetryStats,
+}
+
+/// Query OpenAI like [`crate::query_openai`], returning details about the request along with
+/// the parsed response.
+pub async fn query_openai_with_metadata<T>(
+    messages: Vec<Message>,
+) -> Result<WithMetadata<T>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let (response, retries) =
+        query_openai_inner_with_stats(messages, try_get_schema::<T>()?).await?;
+    Ok(WithMetadata {
+        value: parse_structured(response.content()?)?,
+        usage: response.usage,
+        system_fingerprint: response.system_fingerprint,
+        retries,
+    })
+}
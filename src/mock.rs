@@ -0,0 +1,99 @@
+use crate::{CompletionRequest, LlmError, Provider};
+
+type Respond = dyn Fn(&CompletionRequest) -> Result<String, LlmError> + Send + Sync;
+
+/// A [`Provider`] that answers from a closure instead of the network, to test schemas and
+/// prompts deterministically and without an API key. Use it with
+/// [`crate::query_with_provider`]. Every request is recorded, so tests can also check what
+/// would have been sent.
+pub struct MockProvider {
+    respond: Box<Respond>,
+    requests: std::sync::Mutex<Vec<CompletionRequest>>,
+}
+
+impl MockProvider {
+    /// Answer each request with the JSON text returned by `respond`, or fail with its error.
+    pub fn new(
+        respond: impl Fn(&CompletionRequest) -> Result<String, LlmError> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            respond: Box::new(respond),
+            requests: Default::default(),
+        }
+    }
+
+    /// Answer every request with `json`.
+    pub fn fixed(json: impl Into<String>) -> Self {
+        let json = json.into();
+        Self::new(move |_| Ok(json.clone()))
+    }
+
+    /// The requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<CompletionRequest> {
+        self.requests
+            .lock()
+            .expect("Mock requests lock poisoned")
+            .clone()
+    }
+}
+
+impl Provider for MockProvider {
+    async fn complete(&self, request: CompletionRequest) -> Result<String, LlmError> {
+        let response = (self.respond)(&request);
+        self.requests
+            .lock()
+            .expect("Mock requests lock poisoned")
+            .push(request);
+        response
+    }
+}
+
+impl std::fmt::Debug for MockProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockProvider")
+            .field("requests", &self.requests)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, query_with_provider};
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Greeting {
+        text: String,
+    }
+
+    #[tokio::test]
+    async fn test_mock_provider() {
+        let mock = MockProvider::new(|request| {
+            let last = request
+                .messages
+                .last()
+                .map(Message::content)
+                .unwrap_or_default();
+            Ok(serde_json::json!({ "text": format!("Hello, {last}!") }).to_string())
+        });
+        let greeting: Greeting = query_with_provider(&mock, vec![Message::user("Ada")])
+            .await
+            .unwrap();
+        assert_eq!(greeting.text, "Hello, Ada!");
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].messages[0].content(), "Ada");
+        assert_eq!(
+            requests[0].schema.name(),
+            crate::get_schema::<Greeting>().name()
+        );
+
+        let failing = MockProvider::new(|_| Err(LlmError::EmptyChoices));
+        assert!(matches!(
+            query_with_provider::<Greeting, _>(&failing, Vec::new()).await,
+            Err(LlmError::EmptyChoices)
+        ));
+        assert_eq!(failing.requests().len(), 1);
+    }
+}
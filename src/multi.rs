@@ -0,0 +1,251 @@
+use serde::de::Error;
+
+use crate::{
+    DEFINITION_REF_PREFIX, LlmError, Message, Schema, get_schema, parse_structured,
+    query_openai_inner, schema_name, take_definitions,
+};
+
+/// A group of response types requested together in a single query. Each component type
+/// becomes a named sub-object of one root schema, which saves the cost of sending the same
+/// prompt several times when multiple distinct pieces of structured data are needed.
+///
+/// Implemented for tuples of up to six types, e.g. `(Summary, Entities, Sentiment)`.
+pub trait MultiOutput: Sized {
+    /// The combined schema sent to OpenAI.
+    fn schema() -> Schema;
+
+    /// Parse the combined response back into the individual component types.
+    fn from_value(value: serde_json::Value) -> Result<Self, LlmError>;
+}
+
+/// Query OpenAI once and parse the response into several types at once. See [`MultiOutput`].
+pub async fn query_openai_multi<M: MultiOutput>(messages: Vec<Message>) -> Result<M, LlmError> {
+    let response = query_openai_inner(messages, M::schema()).await?;
+    M::from_value(parse_structured(response.content()?)?)
+}
+
+/// Pick the property key for each component. The schema name is used so the model has a
+/// meaningful hint about what belongs where, with the position appended if the same type
+/// appears more than once.
+fn component_keys(names: &[String]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::with_capacity(names.len());
+    for (index, name) in names.iter().enumerate() {
+        if keys.contains(name) {
+            keys.push(format!("{name}_{index}"));
+        } else {
+            keys.push(name.clone());
+        }
+    }
+    keys
+}
+
+/// Combine the component schemas into a single root object schema. Definitions from each
+/// component are hoisted into the root `$defs` so that their `#/$defs/...` references keep
+/// resolving, see [`hoist_definitions`].
+fn compose(components: Vec<Schema>) -> Schema {
+    let names: Vec<String> = components.iter().map(|c| c.name.clone()).collect();
+    let keys = component_keys(&names);
+
+    let mut properties = serde_json::Map::new();
+    let mut defs = serde_json::Map::new();
+
+    for (key, component) in keys.iter().zip(components) {
+        let mut schema = component.schema;
+        let component_defs = take_definitions(&mut schema);
+        hoist_definitions(&mut defs, component_defs, &mut schema);
+        properties.insert(key.clone(), schema);
+    }
+
+    let mut schema = serde_json::json!({
+        "type": "object",
+        "properties": properties,
+        "required": keys,
+        "additionalProperties": false,
+    });
+    if !defs.is_empty() {
+        schema["$defs"] = serde_json::Value::Object(defs);
+    }
+
+    Schema {
+        // The joined names are usually longer than OpenAI accepts, so they are shortened
+        // like long type names.
+        name: schema_name(&names.join("_and_"), ""),
+        schema,
+        strict: true,
+    }
+}
+
+/// Move the definitions of a component into the root `defs`. Two components can define
+/// different types under the same name, such as `Item` from two modules, so a definition
+/// that differs from the one already in `defs` is renamed with a numbered suffix, and the
+/// references to it in the component's schema and definitions are rewritten.
+fn hoist_definitions(
+    defs: &mut serde_json::Map<String, serde_json::Value>,
+    mut component_defs: serde_json::Map<String, serde_json::Value>,
+    schema: &mut serde_json::Value,
+) {
+    let mut renames = std::collections::HashMap::new();
+    for (name, def) in &component_defs {
+        if defs.get(name).is_none_or(|existing| existing == def) {
+            continue;
+        }
+        let renamed = (2..)
+            .map(|index| format!("{name}_{index}"))
+            .find(|candidate| {
+                !defs.contains_key(candidate)
+                    && !component_defs.contains_key(candidate)
+                    && !renames.values().any(|renamed| renamed == candidate)
+            })
+            .expect("Unbounded suffixes");
+        renames.insert(name.clone(), renamed);
+    }
+
+    if !renames.is_empty() {
+        rename_references(schema, &renames);
+        for def in component_defs.values_mut() {
+            rename_references(def, &renames);
+        }
+    }
+    for (name, def) in component_defs {
+        let name = renames.get(&name).cloned().unwrap_or(name);
+        defs.entry(name).or_insert(def);
+    }
+}
+
+/// Point every `$ref` to a definition in `renames` at its new name.
+fn rename_references(
+    schema: &mut serde_json::Value,
+    renames: &std::collections::HashMap<String, String>,
+) {
+    match schema {
+        serde_json::Value::Object(object) => {
+            let renamed = object
+                .get("$ref")
+                .and_then(|reference| reference.as_str())
+                .and_then(|reference| reference.strip_prefix(DEFINITION_REF_PREFIX))
+                .and_then(|name| renames.get(name));
+            if let Some(renamed) = renamed {
+                object["$ref"] = format!("{DEFINITION_REF_PREFIX}{renamed}").into();
+            }
+            for value in object.values_mut() {
+                rename_references(value, renames);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                rename_references(item, renames);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Take the sub-object for a component out of the combined response and parse it.
+fn take_component<T>(
+    value: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let component = value
+        .remove(key)
+        .ok_or_else(|| LlmError::MissingField(key.to_string()))?;
+    serde_json::from_value(component).map_err(LlmError::ContentParse)
+}
+
+macro_rules! impl_multi_output {
+    ($($T:ident),+) => {
+        impl<$($T),+> MultiOutput for ($($T,)+)
+        where
+            $($T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static),+
+        {
+            fn schema() -> Schema {
+                compose(vec![$(get_schema::<$T>()),+])
+            }
+
+            fn from_value(value: serde_json::Value) -> Result<Self, LlmError> {
+                let serde_json::Value::Object(mut value) = value else {
+                    return Err(LlmError::ContentParse(serde_json::Error::custom(
+                        "Expected a JSON object in response from OpenAI",
+                    )));
+                };
+                let names = [$(get_schema::<$T>().name),+];
+                let mut keys = component_keys(&names).into_iter();
+                Ok(($(
+                    take_component::<$T>(
+                        &mut value,
+                        &keys.next().expect("One key per component"),
+                    )?,
+                )+))
+            }
+        }
+    };
+}
+
+impl_multi_output!(A, B);
+impl_multi_output!(A, B, C);
+impl_multi_output!(A, B, C, D);
+impl_multi_output!(A, B, C, D, E);
+impl_multi_output!(A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod shop {
+        #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+        pub struct Item {
+            pub price: u32,
+        }
+
+        #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+        pub struct Order {
+            pub item: Item,
+        }
+    }
+
+    mod game {
+        #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+        pub struct Item {
+            pub weight: f64,
+        }
+
+        #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+        pub struct Inventory {
+            pub items: Vec<Item>,
+        }
+    }
+
+    #[test]
+    fn test_conflicting_definitions() {
+        type Output = (shop::Order, game::Inventory, shop::Order);
+        let schema = Output::schema().schema;
+        let defs = schema["$defs"].as_object().unwrap();
+        // The same definition is shared, a different one with the same name is renamed.
+        assert_eq!(defs.len(), 2);
+        assert!(defs["Item"]["properties"]["price"].is_object());
+        assert!(defs["Item_2"]["properties"]["weight"].is_object());
+
+        let properties = schema["properties"].as_object().unwrap();
+        let [order, inventory, _] = properties.values().collect::<Vec<_>>()[..] else {
+            panic!("Expected three components: {properties:?}");
+        };
+        assert_eq!(order["properties"]["item"]["$ref"], "#/$defs/Item");
+        assert_eq!(
+            inventory["properties"]["items"]["items"]["$ref"],
+            "#/$defs/Item_2"
+        );
+
+        let keys: Vec<&String> = properties.keys().collect();
+        let value = serde_json::json!({
+            keys[0]: { "item": { "price": 3 } },
+            keys[1]: { "items": [{ "weight": 1.5 }] },
+            keys[2]: { "item": { "price": 4 } },
+        });
+        let (first, inventory, second) = Output::from_value(value).unwrap();
+        assert_eq!(first.item.price, 3);
+        assert_eq!(inventory.items[0].weight, 1.5);
+        assert_eq!(second.item.price, 4);
+    }
+}
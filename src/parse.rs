@@ -0,0 +1,405 @@
+use crate::LlmError;
+
+/// How array fields of the structured output are treated before it is parsed, as a model
+/// may answer with `[]` or leave a field out where neither was meant. Fields are designated
+/// by JSON pointer, such as `/responses` or `/object_in_object/items`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArrayPolicy {
+    non_empty: Vec<String>,
+    default_empty: Vec<String>,
+}
+
+impl ArrayPolicy {
+    /// A policy accepting empty arrays and treating missing arrays as an error, which is
+    /// how serde parses `Vec` fields.
+    pub const fn new() -> Self {
+        Self {
+            non_empty: Vec::new(),
+            default_empty: Vec::new(),
+        }
+    }
+
+    /// Reject the response with [`LlmError::EmptyArray`] if the array at `pointer` is empty.
+    pub fn non_empty(mut self, pointer: impl Into<String>) -> Self {
+        self.non_empty.push(pointer.into());
+        self
+    }
+
+    /// Parse the array at `pointer` as empty if the field is missing.
+    pub fn default_empty(mut self, pointer: impl Into<String>) -> Self {
+        self.default_empty.push(pointer.into());
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.non_empty.is_empty() && self.default_empty.is_empty()
+    }
+
+    fn apply(&self, value: &mut serde_json::Value) -> Result<(), LlmError> {
+        for pointer in &self.default_empty {
+            let Some((parent, key)) = pointer.rsplit_once('/') else {
+                continue;
+            };
+            let key = key.replace("~1", "/").replace("~0", "~");
+            if let Some(serde_json::Value::Object(parent)) = value.pointer_mut(parent) {
+                parent
+                    .entry(key)
+                    .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+            }
+        }
+
+        for pointer in &self.non_empty {
+            if let Some(serde_json::Value::Array(array)) = value.pointer(pointer)
+                && array.is_empty()
+            {
+                return Err(LlmError::EmptyArray(pointer.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decimal places to round float fields of the structured output to before it is parsed,
+/// so that values like `0.30000000000000004` compare stably downstream. Fields are
+/// designated by JSON pointer, where a `*` segment matches every element of an array or
+/// value of an object, such as `/sentiments/*/flair`. Nothing is rounded by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FloatPrecision(Vec<(String, u32)>);
+
+impl FloatPrecision {
+    /// Round no fields.
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Round the floats at `pointer` to `decimals` decimal places.
+    pub fn round(mut self, pointer: impl Into<String>, decimals: u32) -> Self {
+        self.0.push((pointer.into(), decimals));
+        self
+    }
+
+    fn apply(&self, value: &mut serde_json::Value) {
+        for (pointer, decimals) in &self.0 {
+            let segments: Vec<String> = pointer
+                .split('/')
+                .skip(1)
+                .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+                .collect();
+            round_at(value, &segments, *decimals);
+        }
+    }
+}
+
+fn round_at(value: &mut serde_json::Value, segments: &[String], decimals: u32) {
+    let Some((segment, rest)) = segments.split_first() else {
+        if let Some(float) = value.as_f64()
+            && value.is_f64()
+        {
+            let scale = 10f64.powi(decimals as i32);
+            if let Some(rounded) = serde_json::Number::from_f64((float * scale).round() / scale) {
+                *value = serde_json::Value::Number(rounded);
+            }
+        }
+        return;
+    };
+
+    match value {
+        serde_json::Value::Array(items) if segment == "*" => {
+            for item in items {
+                round_at(item, rest, decimals);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            if let Some(item) = segment.parse().ok().and_then(|i: usize| items.get_mut(i)) {
+                round_at(item, rest, decimals);
+            }
+        }
+        serde_json::Value::Object(fields) if segment == "*" => {
+            for field in fields.values_mut() {
+                round_at(field, rest, decimals);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            if let Some(field) = fields.get_mut(segment) {
+                round_at(field, rest, decimals);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The JSON in `content`, without the markdown code fences or surrounding prose models
+/// sometimes add in `json_object` mode or on compatible backends. Content that already is
+/// valid JSON is returned unchanged apart from surrounding whitespace.
+fn extract_json(content: &str) -> &str {
+    let content = content.trim();
+    if serde_json::from_str::<serde::de::IgnoredAny>(content).is_ok() {
+        return content;
+    }
+
+    // Skip the info string after the opening fence, such as `json`.
+    if let Some((_, fenced)) = content.split_once("```")
+        && let Some((_, fenced)) = fenced.split_once('\n')
+        && let Some((fenced, _)) = fenced.split_once("```")
+    {
+        return fenced.trim();
+    }
+
+    let start = content.find(['{', '[']);
+    let end = content.rfind(['}', ']']);
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => &content[start..=end],
+        _ => content,
+    }
+}
+
+/// Parse the structured output in the content of a response into `T`.
+///
+/// Derived structs already reject duplicate fields, but maps and `serde_json::Value`
+/// anywhere in `T` silently keep the last value when a key appears more than once. With
+/// `reject_duplicate_keys` the content is scanned first and duplicates at any depth are
+/// reported as [`LlmError::DuplicateKey`]. Array fields are then checked against `arrays`
+/// and floats rounded according to `floats`. Code fences and prose around the JSON are
+/// ignored.
+pub(crate) fn parse_content<T>(
+    content: &str,
+    reject_duplicate_keys: bool,
+    arrays: &ArrayPolicy,
+    floats: &FloatPrecision,
+) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let content = extract_json(content);
+    if reject_duplicate_keys
+        && let FirstDuplicateKey(Some(key)) =
+            serde_json::from_str(content).map_err(LlmError::ContentParse)?
+    {
+        return Err(LlmError::DuplicateKey(key));
+    }
+
+    if arrays.is_empty() && floats.0.is_empty() {
+        return serde_json::from_str(content).map_err(LlmError::ContentParse);
+    }
+    let mut value = serde_json::from_str(content).map_err(LlmError::ContentParse)?;
+    arrays.apply(&mut value)?;
+    floats.apply(&mut value);
+    serde_json::from_value(value).map_err(LlmError::ContentParse)
+}
+
+/// The first key found to be duplicated within a JSON object, at any depth.
+struct FirstDuplicateKey(Option<String>);
+
+impl<'de> serde::Deserialize<'de> for FirstDuplicateKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(FirstDuplicateKeyVisitor)
+    }
+}
+
+struct FirstDuplicateKeyVisitor;
+
+impl<'de> serde::de::Visitor<'de> for FirstDuplicateKeyVisitor {
+    type Value = FirstDuplicateKey;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("any JSON value")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut keys = std::collections::HashSet::new();
+        let mut duplicate = None;
+        // Keep reading after a duplicate is found, as the whole value must be consumed.
+        while let Some(key) = map.next_key::<String>()? {
+            let FirstDuplicateKey(nested) = map.next_value()?;
+            if !keys.insert(key.clone()) {
+                duplicate = duplicate.or(Some(key));
+            } else {
+                duplicate = duplicate.or(nested);
+            }
+        }
+        Ok(FirstDuplicateKey(duplicate))
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut duplicate = None;
+        while let Some(FirstDuplicateKey(nested)) = seq.next_element()? {
+            duplicate = duplicate.or(nested);
+        }
+        Ok(FirstDuplicateKey(duplicate))
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<Self::Value, E> {
+        Ok(FirstDuplicateKey(None))
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<Self::Value, E> {
+        Ok(FirstDuplicateKey(None))
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<Self::Value, E> {
+        Ok(FirstDuplicateKey(None))
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<Self::Value, E> {
+        Ok(FirstDuplicateKey(None))
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<Self::Value, E> {
+        Ok(FirstDuplicateKey(None))
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(FirstDuplicateKey(None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Response {
+        tone: String,
+        nested: Vec<serde_json::Value>,
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        let duplicated = r#"{"tone": "Happy", "nested": [], "tone": "Sad"}"#;
+        let response: serde_json::Value = parse_content(
+            duplicated,
+            false,
+            &ArrayPolicy::new(),
+            &FloatPrecision::new(),
+        )
+        .unwrap();
+        assert_eq!(response["tone"], "Sad");
+
+        let error = parse_content::<serde_json::Value>(
+            duplicated,
+            true,
+            &ArrayPolicy::new(),
+            &FloatPrecision::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            Some(&error),
+            Some(LlmError::DuplicateKey(key)) if key == "tone"
+        ));
+
+        let nested = r#"{"tone": "Happy", "nested": [{"a": 1}, {"b": true, "b": null}]}"#;
+        assert!(
+            parse_content::<Response>(nested, false, &ArrayPolicy::new(), &FloatPrecision::new())
+                .is_ok()
+        );
+        let error =
+            parse_content::<Response>(nested, true, &ArrayPolicy::new(), &FloatPrecision::new())
+                .unwrap_err();
+        assert!(matches!(
+            Some(&error),
+            Some(LlmError::DuplicateKey(key)) if key == "b"
+        ));
+
+        // The same key in sibling objects is not a duplicate.
+        let siblings = r#"{"tone": "Happy", "nested": [{"a": 1}, {"a": 2.5, "b": "x"}]}"#;
+        let response: Response =
+            parse_content(siblings, true, &ArrayPolicy::new(), &FloatPrecision::new()).unwrap();
+        assert_eq!(response.tone, "Happy");
+        assert_eq!(response.nested.len(), 2);
+    }
+
+    #[test]
+    fn test_array_policy() {
+        let empty = r#"{"tone": "Happy", "nested": []}"#;
+        let missing = r#"{"tone": "Happy"}"#;
+
+        let default = ArrayPolicy::new();
+        assert!(parse_content::<Response>(empty, false, &default, &FloatPrecision::new()).is_ok());
+        assert!(
+            parse_content::<Response>(missing, false, &default, &FloatPrecision::new()).is_err()
+        );
+
+        let non_empty = ArrayPolicy::new().non_empty("/nested");
+        let error = parse_content::<Response>(empty, false, &non_empty, &FloatPrecision::new())
+            .unwrap_err();
+        assert!(matches!(
+            Some(&error),
+            Some(LlmError::EmptyArray(field)) if field == "/nested"
+        ));
+        let filled = r#"{"tone": "Happy", "nested": [1]}"#;
+        assert!(
+            parse_content::<Response>(filled, false, &non_empty, &FloatPrecision::new()).is_ok()
+        );
+        assert!(
+            parse_content::<Response>(missing, false, &non_empty, &FloatPrecision::new()).is_err()
+        );
+
+        let default_empty = ArrayPolicy::new().default_empty("/nested");
+        let response: Response =
+            parse_content(missing, false, &default_empty, &FloatPrecision::new()).unwrap();
+        assert!(response.nested.is_empty());
+        assert!(
+            parse_content::<Response>(empty, false, &default_empty, &FloatPrecision::new()).is_ok()
+        );
+
+        // A defaulted array may still be required to be non-empty.
+        let both = default_empty.non_empty("/nested");
+        assert!(parse_content::<Response>(missing, false, &both, &FloatPrecision::new()).is_err());
+        assert!(parse_content::<Response>(empty, false, &both, &FloatPrecision::new()).is_err());
+    }
+
+    #[test]
+    fn test_float_precision() {
+        let content = r#"{"flair": 0.30000000000000004, "count": 3, "sentiments": [{"score": 0.125}, {"score": 2.0049}]}"#;
+        let floats = FloatPrecision::new()
+            .round("/flair", 2)
+            .round("/sentiments/*/score", 2);
+
+        let unrounded: serde_json::Value =
+            parse_content(content, false, &ArrayPolicy::new(), &FloatPrecision::new()).unwrap();
+        assert_eq!(unrounded["flair"], 0.30000000000000004);
+
+        let rounded: serde_json::Value =
+            parse_content(content, false, &ArrayPolicy::new(), &floats).unwrap();
+        assert_eq!(
+            rounded,
+            serde_json::json!({
+                "flair": 0.3,
+                "count": 3,
+                "sentiments": [{ "score": 0.13 }, { "score": 2.0 }],
+            })
+        );
+        assert!(rounded["count"].is_u64());
+    }
+
+    #[test]
+    fn test_code_fenced_json() {
+        let clean = r#"{"tone": "Happy", "nested": []}"#;
+        assert_eq!(extract_json(clean), clean);
+        assert_eq!(
+            extract_json(r#""a {quoted} string""#),
+            r#""a {quoted} string""#
+        );
+
+        let fenced = "```json\n{\"tone\": \"Happy\", \"nested\": [1]}\n```";
+        let response: Response =
+            parse_content(fenced, true, &ArrayPolicy::new(), &FloatPrecision::new()).unwrap();
+        assert_eq!(response.tone, "Happy");
+        assert_eq!(response.nested, [serde_json::json!(1)]);
+
+        let prose = "Here is the answer:\n{\"tone\": \"Sad\", \"nested\": []}\nHope it helps!";
+        let response: Response =
+            parse_content(prose, false, &ArrayPolicy::new(), &FloatPrecision::new()).unwrap();
+        assert_eq!(response.tone, "Sad");
+
+        assert!(
+            parse_content::<Response>(
+                "No JSON here",
+                false,
+                &ArrayPolicy::new(),
+                &FloatPrecision::new()
+            )
+            .is_err()
+        );
+    }
+}
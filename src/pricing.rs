@@ -0,0 +1,126 @@
+use crate::Usage;
+
+/// Prices of OpenAI models in US dollars per 1,000 input and output tokens, as published at
+/// the time of writing. Dated snapshots resolve to the longest matching prefix, as for
+/// [`crate::context_window`].
+const OPENAI_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4.1", 0.002, 0.008),
+    ("gpt-4.1-mini", 0.0004, 0.0016),
+    ("gpt-4.1-nano", 0.0001, 0.0004),
+    ("gpt-4o", 0.0025, 0.01),
+    ("gpt-4o-mini", 0.00015, 0.0006),
+    ("gpt-4-turbo", 0.01, 0.03),
+    ("gpt-4", 0.03, 0.06),
+    ("gpt-3.5-turbo", 0.0005, 0.0015),
+    ("o1", 0.015, 0.06),
+    ("o1-mini", 0.0011, 0.0044),
+    ("o3", 0.002, 0.008),
+    ("o3-mini", 0.0011, 0.0044),
+    ("o4-mini", 0.0011, 0.0044),
+];
+
+/// What a model costs per 1,000 tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input_per_1k: f64,
+    pub output_per_1k: f64,
+}
+
+/// Prices per model, to compute what requests cost with [`Usage::cost`]. The default table
+/// holds the prices of common OpenAI models. Prices change, so check them against OpenAI's
+/// pricing page and override outdated ones or add those of other providers with
+/// [`Pricing::price`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pricing {
+    /// By model name prefix.
+    prices: Vec<(String, ModelPrice)>,
+}
+
+impl Default for Pricing {
+    fn default() -> Self {
+        OPENAI_PRICES
+            .iter()
+            .fold(Self::new(), |pricing, (model, input, output)| {
+                pricing.price(*model, *input, *output)
+            })
+    }
+}
+
+impl Pricing {
+    /// A table without any prices.
+    pub const fn new() -> Self {
+        Self { prices: Vec::new() }
+    }
+
+    /// Price every model whose name starts with `model` at `input_per_1k` and
+    /// `output_per_1k` dollars per 1,000 tokens, replacing an earlier price for the same
+    /// prefix.
+    pub fn price(
+        mut self,
+        model: impl Into<String>,
+        input_per_1k: f64,
+        output_per_1k: f64,
+    ) -> Self {
+        let model = model.into();
+        let price = ModelPrice {
+            input_per_1k,
+            output_per_1k,
+        };
+        match self.prices.iter_mut().find(|(prefix, _)| *prefix == model) {
+            Some((_, existing)) => *existing = price,
+            None => self.prices.push((model, price)),
+        }
+        self
+    }
+
+    /// The price of `model`, or `None` if it is not in the table.
+    pub fn get(&self, model: &str) -> Option<ModelPrice> {
+        self.prices
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, price)| *price)
+    }
+}
+
+impl Usage {
+    /// What the tokens cost in dollars when used by `model`, or `None` if `pricing` has no
+    /// price for it. Sum the costs of several requests to get the cost of a batch.
+    pub fn cost(&self, pricing: &Pricing, model: &str) -> Option<f64> {
+        let price = pricing.get(model)?;
+        Some(
+            (self.prompt_tokens as f64 * price.input_per_1k
+                + self.completion_tokens as f64 * price.output_per_1k)
+                / 1000.0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost() {
+        let usage = Usage {
+            prompt_tokens: 2000,
+            completion_tokens: 500,
+            total_tokens: 2500,
+        };
+        let pricing = Pricing::default();
+
+        let cost = usage.cost(&pricing, "gpt-4o-2024-08-06").unwrap();
+        assert!((cost - 0.01).abs() < 1e-12, "{cost}");
+        let mini = usage.cost(&pricing, "gpt-4o-mini").unwrap();
+        assert!((mini - 0.0006).abs() < 1e-12, "{mini}");
+        assert_eq!(usage.cost(&pricing, "llama3.1:8b"), None);
+
+        let custom = pricing
+            .price("llama3.1", 0.0, 0.0)
+            .price("gpt-4o", 0.001, 0.002);
+        assert_eq!(usage.cost(&custom, "llama3.1:8b"), Some(0.0));
+        let cost = usage.cost(&custom, "gpt-4o").unwrap();
+        assert!((cost - 0.003).abs() < 1e-12, "{cost}");
+        assert_eq!(Pricing::new().get("gpt-4o"), None);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{LlmError, Message, QueryParams, Schema, repair, try_get_schema};
+
+/// What a [`Provider`] is asked to complete: the conversation and the schema its answer must
+/// follow.
+#[derive(Debug, Clone)]
+pub struct CompletionRequest {
+    pub messages: Vec<Message>,
+    pub schema: Schema,
+    /// Optional parameters, which providers without an equivalent ignore.
+    pub params: QueryParams,
+}
+
+/// A backend that answers with structured output, such as OpenAI's chat completions or
+/// another vendor's API. Providers only turn a request into the JSON text of the answer,
+/// while generating the schema and parsing the answer into `T` is shared, so the same types
+/// work with every provider.
+///
+/// [`crate::LlmClient`] is the default provider for OpenAI and OpenAI-compatible APIs.
+pub trait Provider {
+    /// Complete the conversation, returning the structured output as JSON text.
+    fn complete(
+        &self,
+        request: CompletionRequest,
+    ) -> impl Future<Output = Result<String, LlmError>> + Send;
+}
+
+/// Query `provider` for structured output of type `T`, like [`crate::query_openai`] does
+/// with the default OpenAI client.
+pub async fn query_with_provider<T, P>(provider: &P, messages: Vec<Message>) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    P: Provider,
+{
+    let request = CompletionRequest {
+        messages,
+        schema: try_get_schema::<T>()?,
+        params: QueryParams::default(),
+    };
+    Ok(repair::complete_and_parse(provider, request).await?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every request with the same text, recording the schema names it was asked
+    /// for.
+    struct Fixed {
+        answer: &'static str,
+        schemas: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl Provider for Fixed {
+        async fn complete(&self, request: CompletionRequest) -> Result<String, LlmError> {
+            assert!(request.schema.strict());
+            assert_eq!(request.schema.json_schema()["type"], "object");
+            self.schemas
+                .lock()
+                .unwrap()
+                .push(request.schema.name().to_string());
+            Ok(self.answer.to_string())
+        }
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Capital {
+        city: String,
+    }
+
+    #[tokio::test]
+    async fn test_query_with_provider() {
+        let provider = Fixed {
+            answer: r#"{"city": "Oslo"}"#,
+            schemas: Default::default(),
+        };
+        let capital: Capital =
+            query_with_provider(&provider, vec![Message::user("The capital of Norway?")])
+                .await
+                .unwrap();
+        assert_eq!(
+            capital,
+            Capital {
+                city: "Oslo".to_string()
+            }
+        );
+        assert_eq!(
+            *provider.schemas.lock().unwrap(),
+            [try_get_schema::<Capital>().unwrap().name]
+        );
+
+        let provider = Fixed {
+            answer: r#"{"town": "Oslo"}"#,
+            schemas: Default::default(),
+        };
+        assert!(matches!(
+            query_with_provider::<Capital, _>(&provider, Vec::new()).await,
+            Err(LlmError::ContentParse(_))
+        ));
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::{Duration, Instant};
+
+/// Client-side limits on the requests and prompt tokens sent per minute, to stay under the
+/// rate limits of an account rather than have bursts of requests rejected with 429 and
+/// retried. See [`crate::ConfigBuilder::rate_limit`].
+///
+/// Each limit is a token bucket holding a minute's worth, so a burst of up to the whole
+/// minute's quota is sent at once and later requests wait for the bucket to refill. Prompt
+/// tokens are estimated with [`crate::estimate_prompt_tokens`], so leave some margin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    requests_per_minute: Option<u32>,
+    tokens_per_minute: Option<u32>,
+}
+
+impl RateLimit {
+    /// No limits.
+    pub const fn new() -> Self {
+        Self {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+        }
+    }
+
+    /// Send at most `requests` requests per minute.
+    pub fn requests_per_minute(mut self, requests: u32) -> Self {
+        self.requests_per_minute = Some(requests);
+        self
+    }
+
+    /// Send at most `tokens` prompt tokens per minute.
+    pub fn tokens_per_minute(mut self, tokens: u32) -> Self {
+        self.tokens_per_minute = Some(tokens);
+        self
+    }
+
+    pub(crate) fn is_unlimited(&self) -> bool {
+        self.requests_per_minute.is_none() && self.tokens_per_minute.is_none()
+    }
+}
+
+/// The buckets of a [`RateLimit`], shared by every client with the same configuration.
+pub(crate) struct RateLimiter {
+    buckets: std::sync::Mutex<(Option<Bucket>, Option<Bucket>)>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        let now = Instant::now();
+        let bucket = |per_minute: Option<u32>| per_minute.map(|limit| Bucket::new(limit, now));
+        Self {
+            buckets: std::sync::Mutex::new((
+                bucket(limit.requests_per_minute),
+                bucket(limit.tokens_per_minute),
+            )),
+        }
+    }
+
+    /// Wait until a request with `tokens` prompt tokens fits in the limits, and take it out
+    /// of them.
+    pub(crate) async fn acquire(&self, tokens: usize) {
+        loop {
+            let wait = self.reserve(Instant::now(), tokens);
+            if wait.is_zero() {
+                return;
+            }
+            tracing::debug!(?wait, "Waiting for the client-side rate limit");
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take a request with `tokens` prompt tokens out of the limits at `now` and return
+    /// zero, or return how long to wait until it fits without taking anything.
+    fn reserve(&self, now: Instant, tokens: usize) -> Duration {
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        let (requests, tokens_bucket) = &mut *buckets;
+        let mut needs: Vec<(&mut Bucket, f64)> = Vec::new();
+        if let Some(bucket) = requests {
+            needs.push((bucket, 1.0));
+        }
+        if let Some(bucket) = tokens_bucket {
+            // A prompt larger than the whole limit would never fit, so it only waits for a
+            // full bucket.
+            let tokens = (tokens as f64).min(bucket.per_minute);
+            needs.push((bucket, tokens));
+        }
+
+        let mut wait = Duration::ZERO;
+        for (bucket, amount) in &mut needs {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait(*amount));
+        }
+        if wait.is_zero() {
+            for (bucket, amount) in needs {
+                bucket.available -= amount;
+            }
+        }
+        wait
+    }
+}
+
+struct Bucket {
+    per_minute: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        Self {
+            per_minute: per_minute.into(),
+            available: per_minute.into(),
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_minute / 60.0).min(self.per_minute);
+        self.updated = now;
+    }
+
+    /// How long until `amount` is available.
+    fn wait(&self, amount: f64) -> Duration {
+        let missing = amount - self.available;
+        if missing <= 0.0 || self.per_minute == 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing * 60.0 / self.per_minute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requests_per_minute() {
+        let limiter = RateLimiter::new(RateLimit::new().requests_per_minute(60));
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+
+        // The whole minute's quota is available at once.
+        for _ in 0..60 {
+            assert_eq!(limiter.reserve(start, 0), Duration::ZERO);
+        }
+        // Then one request per second refills.
+        assert_eq!(limiter.reserve(start, 0), secs(1));
+        assert_eq!(limiter.reserve(start + secs(1), 0), Duration::ZERO);
+        assert_eq!(limiter.reserve(start + secs(1), 0), secs(1));
+    }
+
+    #[test]
+    fn test_tokens_per_minute() {
+        let limiter = RateLimiter::new(
+            RateLimit::new()
+                .requests_per_minute(100)
+                .tokens_per_minute(6000),
+        );
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+
+        assert_eq!(limiter.reserve(start, 5000), Duration::ZERO);
+        // 1000 tokens are left and 100 refill per second.
+        assert_eq!(limiter.reserve(start, 2000), secs(10));
+        assert_eq!(limiter.reserve(start + secs(10), 2000), Duration::ZERO);
+        // A prompt over the limit waits for a full bucket.
+        assert_eq!(limiter.reserve(start + secs(10), 10_000), secs(60));
+        assert_eq!(limiter.reserve(start + secs(70), 10_000), Duration::ZERO);
+
+        let unlimited = RateLimiter::new(RateLimit::new());
+        assert_eq!(unlimited.reserve(start, usize::MAX), Duration::ZERO);
+    }
+}
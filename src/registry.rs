@@ -0,0 +1,199 @@
+use crate::{LlmError, MAX_SCHEMA_NAME_LENGTH};
+
+/// What to do when two different types end up with the same schema name, for example
+/// `a::b_c::Item` and `a_b::c::Item`, or long names truncated to the same prefix. OpenAI
+/// does not mind, but caches and logs keyed on the name can no longer tell them apart.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Use the colliding name as is.
+    #[default]
+    Allow,
+    /// Append a numbered suffix to the name of every type after the first.
+    Disambiguate,
+    /// Refuse to create a schema for any type after the first.
+    Error,
+}
+
+/// The schema names given out so far. Types are identified by their `TypeId`, as type names
+/// are not guaranteed to be unique.
+#[derive(Debug, Default)]
+pub(crate) struct SchemaRegistry {
+    /// The name given to each type, by the name it asked for.
+    given: std::collections::HashMap<(String, std::any::TypeId), String>,
+    /// The type each given name belongs to, with its type name for error messages.
+    owners: std::collections::HashMap<String, (std::any::TypeId, &'static str)>,
+}
+
+impl SchemaRegistry {
+    /// Register a type under a schema name and return the name to use for it.
+    pub(crate) fn register(
+        &mut self,
+        name: String,
+        type_id: std::any::TypeId,
+        type_name: &'static str,
+        policy: CollisionPolicy,
+    ) -> Result<String, LlmError> {
+        if policy == CollisionPolicy::Allow {
+            return Ok(name);
+        }
+        if let Some(given) = self.given.get(&(name.clone(), type_id)) {
+            return Ok(given.clone());
+        }
+
+        let given = match self.owners.get(&name) {
+            None => name.clone(),
+            Some(&(_, existing_type_name)) => match policy {
+                CollisionPolicy::Allow => unreachable!("Handled above"),
+                CollisionPolicy::Error => {
+                    return Err(LlmError::SchemaNameCollision {
+                        name,
+                        type_name: type_name.to_string(),
+                        existing_type_name: existing_type_name.to_string(),
+                    });
+                }
+                // The numbered name may itself belong to another type, such as one named
+                // `Item_2`, so the number is raised until the name is free.
+                CollisionPolicy::Disambiguate => (2..)
+                    .map(|index| {
+                        let suffix = format!("_{index}");
+                        let name: String = name
+                            .chars()
+                            .take(MAX_SCHEMA_NAME_LENGTH - suffix.len())
+                            .collect();
+                        name + &suffix
+                    })
+                    .find(|candidate| !self.owners.contains_key(candidate))
+                    .expect("Unbounded suffixes"),
+            },
+        };
+        self.owners.insert(given.clone(), (type_id, type_name));
+        self.given.insert((name, type_id), given.clone());
+        Ok(given)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod one {
+        pub mod two {
+            #[derive(schemars::JsonSchema)]
+            pub struct Three;
+        }
+    }
+
+    mod one_two {
+        #[derive(schemars::JsonSchema)]
+        pub struct Three;
+    }
+
+    #[test]
+    fn test_schema_name_collision() {
+        let first = (
+            std::any::TypeId::of::<one::two::Three>(),
+            std::any::type_name::<one::two::Three>(),
+        );
+        let second = (
+            std::any::TypeId::of::<one_two::Three>(),
+            std::any::type_name::<one_two::Three>(),
+        );
+        let name = crate::get_schema::<one::two::Three>().name;
+        assert_eq!(name, crate::get_schema::<one_two::Three>().name);
+
+        let mut registry = SchemaRegistry::default();
+        let policy = CollisionPolicy::Disambiguate;
+        assert_eq!(
+            registry
+                .register(name.clone(), first.0, first.1, policy)
+                .unwrap(),
+            name
+        );
+        assert_eq!(
+            registry
+                .register(name.clone(), second.0, second.1, policy)
+                .unwrap(),
+            format!("{name}_2")
+        );
+        // The same type keeps the name it was given first.
+        assert_eq!(
+            registry
+                .register(name.clone(), first.0, first.1, policy)
+                .unwrap(),
+            name
+        );
+
+        let mut registry = SchemaRegistry::default();
+        let policy = CollisionPolicy::Error;
+        assert!(
+            registry
+                .register(name.clone(), first.0, first.1, policy)
+                .is_ok()
+        );
+        assert!(
+            registry
+                .register(name.clone(), first.0, first.1, policy)
+                .is_ok()
+        );
+        assert!(
+            registry
+                .register(name.clone(), second.0, second.1, policy)
+                .is_err()
+        );
+
+        let mut registry = SchemaRegistry::default();
+        let long_name = "a".repeat(MAX_SCHEMA_NAME_LENGTH);
+        let policy = CollisionPolicy::Disambiguate;
+        registry
+            .register(long_name.clone(), first.0, first.1, policy)
+            .unwrap();
+        let disambiguated = registry
+            .register(long_name, second.0, second.1, policy)
+            .unwrap();
+        assert_eq!(disambiguated.len(), MAX_SCHEMA_NAME_LENGTH);
+        assert!(disambiguated.ends_with("_2"));
+
+        // Types are told apart by their `TypeId` even if their type names are the same.
+        let mut registry = SchemaRegistry::default();
+        let policy = CollisionPolicy::Error;
+        assert!(
+            registry
+                .register(name.clone(), first.0, first.1, policy)
+                .is_ok()
+        );
+        assert!(
+            registry
+                .register(name.clone(), second.0, first.1, policy)
+                .is_err()
+        );
+
+        // A numbered name is not given out if another type already has it.
+        let third = (std::any::TypeId::of::<u8>(), "u8");
+        let mut registry = SchemaRegistry::default();
+        let policy = CollisionPolicy::Disambiguate;
+        let numbered = format!("{name}_2");
+        assert_eq!(
+            registry
+                .register(numbered.clone(), third.0, third.1, policy)
+                .unwrap(),
+            numbered
+        );
+        registry
+            .register(name.clone(), first.0, first.1, policy)
+            .unwrap();
+        assert_eq!(
+            registry
+                .register(name.clone(), second.0, second.1, policy)
+                .unwrap(),
+            format!("{name}_3")
+        );
+        // Nor is a name asked for later given to a second type once it was handed out.
+        let fourth = (std::any::TypeId::of::<u16>(), "u16");
+        assert_eq!(
+            registry
+                .register(format!("{name}_3"), fourth.0, fourth.1, policy)
+                .unwrap(),
+            format!("{name}_3_2")
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{
+    CompletionRequest, LlmError, Message, Provider, REPAIR_ATTEMPTS, parse_structured, schema_check,
+};
+
+/// Complete `request` with `provider` and parse the answer into `T`, asking the model to
+/// fix an answer that does not parse up to the configured number of times, see
+/// [`crate::set_repair_attempts`]. Returns the parsed answer with the JSON text it was
+/// parsed from.
+pub(crate) async fn complete_and_parse<T, P>(
+    provider: &P,
+    request: CompletionRequest,
+) -> Result<(T, String), LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+    P: Provider,
+{
+    schema_check::warn_unknown_fields::<T>(&request.schema);
+    let attempts = REPAIR_ATTEMPTS.load(std::sync::atomic::Ordering::Relaxed);
+    complete_with_repairs(provider, request, attempts).await
+}
+
+async fn complete_with_repairs<T, P>(
+    provider: &P,
+    mut request: CompletionRequest,
+    attempts: usize,
+) -> Result<(T, String), LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+    P: Provider,
+{
+    let mut repairs = 0;
+    loop {
+        let content = provider.complete(request.clone()).await?;
+        let error = match parse_structured(&content) {
+            Ok(value) => return Ok((value, content)),
+            Err(LlmError::ContentParse(error)) if repairs < attempts => error,
+            Err(error) => return Err(error),
+        };
+        repairs += 1;
+
+        request.messages.push(Message::assistant(content));
+        request.messages.push(Message::user(format!(
+            "The previous response could not be parsed: {error}\nReply again with the JSON fixed."
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockProvider, QueryParams};
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Count {
+        count: u32,
+    }
+
+    fn request() -> CompletionRequest {
+        CompletionRequest {
+            messages: vec![Message::user("How many legs does a spider have?")],
+            schema: crate::get_schema::<Count>(),
+            params: QueryParams::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repair() {
+        // Answers with a float first, then with the fixed integer once told about the error.
+        let mock = MockProvider::new(|request| {
+            Ok(match request.messages.len() {
+                1 => r#"{"count": 8.0}"#.to_string(),
+                _ => r#"{"count": 8}"#.to_string(),
+            })
+        });
+        let (count, raw) = complete_with_repairs::<Count, _>(&mock, request(), 1)
+            .await
+            .unwrap();
+        assert_eq!(count, Count { count: 8 });
+        assert_eq!(raw, r#"{"count": 8}"#);
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        let repair = &requests[1].messages;
+        assert_eq!(repair.len(), 3);
+        assert_eq!(repair[1].content(), r#"{"count": 8.0}"#);
+        assert!(repair[2].content().contains("invalid type: floating point"));
+
+        // Without repair attempts, and once they are used up, the parse error is returned.
+        let mock = MockProvider::fixed(r#"{"count": "eight"}"#);
+        assert!(matches!(
+            complete_with_repairs::<Count, _>(&mock, request(), 0).await,
+            Err(LlmError::ContentParse(_))
+        ));
+        assert_eq!(mock.requests().len(), 1);
+        assert!(matches!(
+            complete_with_repairs::<Count, _>(&mock, request(), 2).await,
+            Err(LlmError::ContentParse(_))
+        ));
+        assert_eq!(mock.requests().len(), 4);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    LlmClient, LlmError, Message, OpenAIChatCompletionQuery, QueryParams, ReasoningEffort,
+    ResponseMode, Role, parse_structured, run_query, try_get_schema,
+};
+
+/// A request for structured output of type `T`, built up one message and option at a time
+/// and sent with [`Request::send`].
+#[derive(Debug, Clone)]
+pub struct Request<T> {
+    messages: Vec<Message>,
+    params: QueryParams,
+    output: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T> Default for Request<T> {
+    fn default() -> Self {
+        Self {
+            messages: Vec::new(),
+            params: QueryParams::default(),
+            output: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T> Request<T>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    /// An empty request for the configured model.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a developer message.
+    pub fn developer(self, content: impl Into<String>) -> Self {
+        self.message(Role::Developer, content.into())
+    }
+
+    /// Append a user message.
+    pub fn user(self, content: impl Into<String>) -> Self {
+        self.message(Role::User, content.into())
+    }
+
+    /// Append an assistant message, for example an earlier reply in a multi-turn
+    /// conversation.
+    pub fn assistant(self, content: impl Into<String>) -> Self {
+        self.message(Role::Assistant, content.into())
+    }
+
+    /// Append an already built message.
+    pub fn push(mut self, message: Message) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    /// Send the request to `model` instead of the configured one, see
+    /// [`QueryParams::model`].
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.params.model = Some(model.into());
+        self
+    }
+
+    /// Set the sampling temperature, see [`QueryParams::temperature`].
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.params.temperature = Some(temperature);
+        self
+    }
+
+    /// Set nucleus sampling, see [`QueryParams::top_p`].
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.params.top_p = Some(top_p);
+        self
+    }
+
+    /// Limit the number of generated tokens, see [`QueryParams::max_completion_tokens`].
+    pub fn max_completion_tokens(mut self, tokens: u32) -> Self {
+        self.params.max_completion_tokens = Some(tokens);
+        self
+    }
+
+    /// Set how long reasoning models think, see [`QueryParams::reasoning_effort`].
+    pub fn reasoning_effort(mut self, effort: ReasoningEffort) -> Self {
+        self.params.reasoning_effort = Some(effort);
+        self
+    }
+
+    /// Sample deterministically on a best-effort basis, see [`QueryParams::seed`].
+    pub fn seed(mut self, seed: i64) -> Self {
+        self.params.seed = Some(seed);
+        self
+    }
+
+    /// Stop generating at any of `sequences`, see [`QueryParams::stop`].
+    pub fn stop(mut self, sequences: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.params.stop = Some(sequences.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Penalize tokens that already appeared at all, see [`QueryParams::presence_penalty`].
+    pub fn presence_penalty(mut self, penalty: f32) -> Self {
+        self.params.presence_penalty = Some(penalty);
+        self
+    }
+
+    /// Penalize tokens by how often they already appeared, see
+    /// [`QueryParams::frequency_penalty`].
+    pub fn frequency_penalty(mut self, penalty: f32) -> Self {
+        self.params.frequency_penalty = Some(penalty);
+        self
+    }
+
+    /// Constrain the response with `mode`, see [`QueryParams::response_mode`].
+    pub fn response_mode(mut self, mode: ResponseMode) -> Self {
+        self.params.response_mode = mode;
+        self
+    }
+
+    /// Return the log probability of each token, along with the `top` most likely
+    /// alternatives if set, see [`QueryParams::logprobs`].
+    pub fn logprobs(mut self, top: Option<u8>) -> Self {
+        self.params.logprobs = true;
+        self.params.top_logprobs = top;
+        self
+    }
+
+    /// Replace all parameters at once, including the model.
+    pub fn params(mut self, params: QueryParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Send the request and parse the response into `T`.
+    pub async fn send(self) -> Result<T, LlmError> {
+        self.send_with(&LlmClient::default()).await
+    }
+
+    /// Send the request with `client` instead of the default one.
+    pub async fn send_with(self, client: &LlmClient) -> Result<T, LlmError> {
+        let query = self.into_query(&client.config.model)?;
+        let (response, _) = run_query(client, query).await?;
+        parse_structured(response.content()?)
+    }
+
+    fn message(self, role: Role, content: String) -> Self {
+        self.push(Message::new(role, content))
+    }
+
+    fn into_query(self, default_model: &str) -> Result<OpenAIChatCompletionQuery, LlmError> {
+        Ok(OpenAIChatCompletionQuery::new(
+            default_model.to_string(),
+            self.messages,
+            try_get_schema::<T>()?,
+        )
+        .with_params(self.params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_builder() {
+        let request = Request::<Vec<String>>::new()
+            .developer("Answer with a list of cities")
+            .user("Which are the largest cities in Sweden?")
+            .assistant(r#"{"items": ["Stockholm"]}"#)
+            .user("And in Norway?")
+            .temperature(0.0)
+            .seed(42)
+            .stop(["END"])
+            .frequency_penalty(0.5);
+
+        let query =
+            serde_json::to_value(request.clone().into_query("gpt-4o-mini").unwrap()).unwrap();
+        assert_eq!(query["model"], "gpt-4o-mini");
+        assert_eq!(query["temperature"], 0.0);
+        assert!(query.get("top_p").is_none());
+        assert_eq!(query["seed"], 42);
+        assert_eq!(query["stop"], serde_json::json!(["END"]));
+        assert_eq!(query["frequency_penalty"], 0.5);
+        assert!(query.get("presence_penalty").is_none());
+        let roles: Vec<&str> = query["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, ["developer", "user", "assistant", "user"]);
+        assert_eq!(
+            query["response_format"]["json_schema"],
+            crate::canonical_openai_schema::<Vec<String>>()
+        );
+
+        let query = request.model("o3-mini").into_query("gpt-4o-mini").unwrap();
+        assert_eq!(query.model, "o3-mini");
+    }
+}
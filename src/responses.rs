@@ -0,0 +1,439 @@
+use crate::{
+    ApiError, CompletionRequest, ContentPart, LlmClient, LlmError, Message, Provider, Role,
+    estimate_prompt_tokens, retry, with_headers,
+};
+
+/// A [`Provider`] for OpenAI's Responses API at `/responses`, which newer features are
+/// added to first, with the same configuration as an [`LlmClient`]. The schema is sent as the
+/// `text.format` of the request and the output text is parsed like the content of a chat
+/// completion, so the same types work with both endpoints.
+///
+/// Chat completions remain the default: use this with [`crate::query_with_provider`].
+#[derive(Clone)]
+pub struct ResponsesProvider {
+    client: LlmClient,
+}
+
+impl Default for ResponsesProvider {
+    /// The default [`LlmClient`], with the configuration from the environment. Panics if the
+    /// configuration is missing, see [`crate::init`].
+    fn default() -> Self {
+        Self::new(LlmClient::default())
+    }
+}
+
+impl ResponsesProvider {
+    /// Send requests with the HTTP client, configuration and retry policy of `client`.
+    pub fn new(client: LlmClient) -> Self {
+        Self { client }
+    }
+
+    async fn post(
+        &self,
+        body: &serde_json::Value,
+        tokens: usize,
+    ) -> Result<ResponsesResponse, LlmError> {
+        let config = &self.client.config;
+        if let Some(limiter) = &config.rate_limiter {
+            limiter.acquire(tokens).await;
+        }
+        let mut request = self.client.http.post(config.responses_url());
+        if let Some(timeout) = config.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = with_headers(request, config).json(body).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| retry::parse_retry_after(value.to_str().ok()?));
+            let body = response.text().await?;
+            return Err(LlmError::ApiStatus {
+                status: status.as_u16(),
+                error: ApiError::parse(&body).map(Box::new),
+                body,
+                retry_after,
+            });
+        }
+        serde_json::from_str(&response.text().await?).map_err(LlmError::InvalidResponse)
+    }
+}
+
+impl Provider for ResponsesProvider {
+    async fn complete(&self, mut request: CompletionRequest) -> Result<String, LlmError> {
+        request.params.validate()?;
+        let body = request_body(&self.client, &mut request);
+        let tokens = estimate_prompt_tokens(&request.messages, Some(&request.schema));
+        let policy = self.client.current_retry_policy();
+        let response = retry::with_retries(&policy, || self.post(&body, tokens)).await?;
+        output_text(response)
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ResponsesResponse {
+    /// `incomplete` when the model stopped early, see `incomplete_details`.
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    incomplete_details: Option<IncompleteDetails>,
+    #[serde(default)]
+    output: Vec<OutputItem>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct IncompleteDetails {
+    reason: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OutputItem {
+    Message {
+        content: Vec<OutputContent>,
+    },
+    /// Reasoning summaries and calls to built-in tools.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OutputContent {
+    OutputText {
+        text: String,
+    },
+    Refusal {
+        refusal: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// The body of a Responses API request for `request`, with the same adaptations to the
+/// configuration of `client` as chat completion requests where the API has an equivalent.
+fn request_body(client: &LlmClient, request: &mut CompletionRequest) -> serde_json::Value {
+    let config = &client.config;
+    let model = request
+        .params
+        .model
+        .clone()
+        .unwrap_or_else(|| config.model.clone());
+    request.params.drop_unsupported(&model);
+    if let Some(prompt) = &config.system_prompt
+        && !request
+            .messages
+            .iter()
+            .any(|message| message.role.is_instructions())
+    {
+        request
+            .messages
+            .insert(0, Message::developer(prompt.clone()));
+    }
+    let input: Vec<serde_json::Value> = request.messages.iter().flat_map(input_items).collect();
+
+    let mut body = serde_json::json!({
+        "model": model,
+        "input": input,
+        "text": {
+            "format": {
+                "type": "json_schema",
+                "name": request.schema.name(),
+                "schema": request.schema.json_schema(),
+                "strict": request.schema.strict() && config.strict,
+            },
+        },
+        // Responses are stored for 30 days by default, which chat completions are not.
+        "store": false,
+    });
+    let params = &request.params;
+    if let Some(temperature) = params.temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = top_p.into();
+    }
+    if let Some(max_tokens) = params.max_completion_tokens {
+        body["max_output_tokens"] = max_tokens.into();
+    }
+    if let Some(effort) = params.reasoning_effort {
+        body["reasoning"] = serde_json::json!({ "effort": effort });
+    }
+    body
+}
+
+/// The `input` items of the Responses API for a message. Tool calls and their results are
+/// items of their own rather than messages.
+fn input_items(message: &Message) -> Vec<serde_json::Value> {
+    if let Role::Tool = message.role {
+        return vec![serde_json::json!({
+            "type": "function_call_output",
+            "call_id": message.tool_call_id,
+            "output": message.content,
+        })];
+    }
+
+    let calls = message.tool_calls.iter().map(|call| {
+        serde_json::json!({
+            "type": "function_call",
+            "call_id": call.id,
+            "name": call.function.name,
+            "arguments": call.function.arguments,
+        })
+    });
+    let has_content = !message.content.is_empty() || !message.parts.is_empty();
+    let text = (has_content || message.tool_calls.is_empty()).then(|| input_message(message));
+    text.into_iter().chain(calls).collect()
+}
+
+/// A message in the `input` format of the Responses API. Text-only messages keep their
+/// content as a string, while content parts become `input_text` and `input_image` parts, or
+/// `output_text` for the model's own messages.
+fn input_message(message: &Message) -> serde_json::Value {
+    let role = match message.role {
+        Role::Developer => "developer",
+        Role::System => "system",
+        Role::User | Role::Tool => "user",
+        Role::Assistant => "assistant",
+    };
+    if message.parts.is_empty() {
+        return serde_json::json!({ "role": role, "content": message.content });
+    }
+
+    let text_type = match message.role {
+        Role::Assistant => "output_text",
+        _ => "input_text",
+    };
+    let text = (!message.content.is_empty()).then(|| ContentPart::text(message.content.clone()));
+    let content: Vec<serde_json::Value> = text
+        .iter()
+        .chain(&message.parts)
+        .map(|part| match part {
+            ContentPart::Text { text } => serde_json::json!({ "type": text_type, "text": text }),
+            ContentPart::ImageUrl { image_url } => {
+                let mut image =
+                    serde_json::json!({ "type": "input_image", "image_url": image_url.url });
+                if let Some(detail) = &image_url.detail {
+                    image["detail"] = detail.as_str().into();
+                }
+                image
+            }
+        })
+        .collect();
+    serde_json::json!({ "role": role, "content": content })
+}
+
+/// The output text of the response, which is the structured output as JSON text.
+fn output_text(response: ResponsesResponse) -> Result<String, LlmError> {
+    if response.status.as_deref() == Some("incomplete") {
+        let reason = response
+            .incomplete_details
+            .and_then(|details| details.reason);
+        if reason.as_deref() == Some("max_output_tokens") {
+            return Err(LlmError::Truncated);
+        }
+    }
+
+    let mut text = None;
+    for item in response.output {
+        let OutputItem::Message { content } = item else {
+            continue;
+        };
+        for part in content {
+            match part {
+                OutputContent::OutputText { text: part } => {
+                    text.get_or_insert_with(String::new).push_str(&part);
+                }
+                OutputContent::Refusal { refusal } => return Err(LlmError::Refusal(refusal)),
+                OutputContent::Other => {}
+            }
+        }
+    }
+    text.ok_or(LlmError::EmptyChoices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryParams;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Capital {
+        city: String,
+    }
+
+    fn test_client(config: crate::ConfigBuilder) -> LlmClient {
+        LlmClient::with_http_client(reqwest::Client::new(), config.build().unwrap())
+    }
+
+    #[test]
+    fn test_request_body() {
+        let client = test_client(crate::Config::builder().model("gpt-4.1").strict(false));
+        let mut request = CompletionRequest {
+            messages: vec![
+                Message::developer("Answer briefly."),
+                Message::user("The capital of Norway?"),
+                Message::assistant(r#"{"city": "Oslo"}"#),
+                Message::from_parts(
+                    Role::User,
+                    vec![
+                        ContentPart::text("And this one?"),
+                        ContentPart::image_url("https://example.com/map.png").detail("low"),
+                    ],
+                ),
+            ],
+            schema: crate::get_schema::<Capital>(),
+            params: QueryParams {
+                temperature: Some(0.0),
+                max_completion_tokens: Some(100),
+                reasoning_effort: Some(crate::ReasoningEffort::Low),
+                ..QueryParams::default()
+            },
+        };
+
+        let body = request_body(&client, &mut request);
+        assert_eq!(body["model"], "gpt-4.1");
+        assert_eq!(body["temperature"], 0.0);
+        assert_eq!(body["max_output_tokens"], 100);
+        assert!(body.get("top_p").is_none());
+        // gpt-4.1 is not a reasoning model.
+        assert!(body.get("reasoning").is_none());
+        assert_eq!(
+            body["text"]["format"],
+            serde_json::json!({
+                "type": "json_schema",
+                "name": request.schema.name(),
+                "schema": request.schema.json_schema(),
+                "strict": false,
+            })
+        );
+        assert_eq!(
+            body["input"],
+            serde_json::json!([
+                { "role": "developer", "content": "Answer briefly." },
+                { "role": "user", "content": "The capital of Norway?" },
+                { "role": "assistant", "content": r#"{"city": "Oslo"}"# },
+                {
+                    "role": "user",
+                    "content": [
+                        { "type": "input_text", "text": "And this one?" },
+                        {
+                            "type": "input_image",
+                            "image_url": "https://example.com/map.png",
+                            "detail": "low",
+                        },
+                    ],
+                },
+            ])
+        );
+
+        let client = test_client(
+            crate::Config::builder()
+                .model("gpt-4.1")
+                .system_prompt("Be precise."),
+        );
+        let mut request = CompletionRequest {
+            messages: vec![Message::user("The capital of Norway?")],
+            schema: crate::get_schema::<Capital>(),
+            params: QueryParams {
+                model: Some("o3-mini".to_string()),
+                reasoning_effort: Some(crate::ReasoningEffort::Low),
+                ..QueryParams::default()
+            },
+        };
+        let body = request_body(&client, &mut request);
+        assert_eq!(body["model"], "o3-mini");
+        assert_eq!(body["reasoning"], serde_json::json!({ "effort": "low" }));
+        assert_eq!(body["text"]["format"]["strict"], true);
+
+        // Tool calls and their results are items of their own.
+        let call: crate::ToolCall = serde_json::from_value(serde_json::json!({
+            "id": "call_1",
+            "type": "function",
+            "function": { "name": "get_weather", "arguments": r#"{"city": "Oslo"}"# },
+        }))
+        .unwrap();
+        let items: Vec<serde_json::Value> = [
+            Message::assistant_tool_calls("", vec![call]),
+            Message::tool_result("call_1", "sunny"),
+        ]
+        .iter()
+        .flat_map(input_items)
+        .collect();
+        assert_eq!(
+            items,
+            [
+                serde_json::json!({
+                    "type": "function_call",
+                    "call_id": "call_1",
+                    "name": "get_weather",
+                    "arguments": r#"{"city": "Oslo"}"#,
+                }),
+                serde_json::json!({
+                    "type": "function_call_output",
+                    "call_id": "call_1",
+                    "output": "sunny",
+                }),
+            ]
+        );
+        assert_eq!(
+            body["input"][0],
+            serde_json::json!({ "role": "developer", "content": "Be precise." })
+        );
+    }
+
+    #[test]
+    fn test_output_text() {
+        let response = |response: serde_json::Value| {
+            output_text(serde_json::from_value::<ResponsesResponse>(response).unwrap())
+        };
+
+        let answered = response(serde_json::json!({
+            "status": "completed",
+            "output": [
+                { "type": "reasoning", "id": "rs_1", "summary": [] },
+                {
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{ "type": "output_text", "text": r#"{"city": "Oslo"}"#, "annotations": [] }],
+                },
+            ],
+        }))
+        .unwrap();
+        let capital: Capital = crate::parse_structured(&answered).unwrap();
+        assert_eq!(
+            capital,
+            Capital {
+                city: "Oslo".to_string()
+            }
+        );
+
+        assert!(matches!(
+            response(serde_json::json!({
+                "status": "completed",
+                "output": [{
+                    "type": "message",
+                    "content": [{ "type": "refusal", "refusal": "I can't." }],
+                }],
+            })),
+            Err(LlmError::Refusal(refusal)) if refusal == "I can't."
+        ));
+        assert!(matches!(
+            response(serde_json::json!({
+                "status": "incomplete",
+                "incomplete_details": { "reason": "max_output_tokens" },
+                "output": [{
+                    "type": "message",
+                    "content": [{ "type": "output_text", "text": r#"{"ci"# }],
+                }],
+            })),
+            Err(LlmError::Truncated)
+        ));
+        assert!(matches!(
+            response(serde_json::json!({ "status": "completed", "output": [] })),
+            Err(LlmError::EmptyChoices)
+        ));
+    }
+}
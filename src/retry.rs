@@ -0,0 +1,255 @@
+use crate::LlmError;
+
+/// What to do after a failed attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Wait for the given duration and try again.
+    Retry(std::time::Duration),
+    /// Give up and return the error.
+    Fail,
+}
+
+type Decide = dyn Fn(&LlmError, usize) -> RetryDecision + Send + Sync;
+
+/// Decides which failed requests are retried and how long to wait in between. The closure
+/// receives the error and the number of attempts made so far, starting at 1.
+///
+/// The policy sees the errors of sending a request and reading its response, such as
+/// [`LlmError::ApiStatus`] or an [`LlmError::InvalidResponse`] body. Content that does not
+/// parse into the response type is not retried by the policy but repaired, see
+/// [`crate::set_repair_attempts`].
+///
+/// The default policy is [`RetryPolicy::exponential`] with three retries, starting at 500ms
+/// and waiting at most 30s.
+#[derive(Clone)]
+pub struct RetryPolicy(std::sync::Arc<Decide>);
+
+impl RetryPolicy {
+    /// Create a policy from a closure deciding on each failed attempt.
+    pub fn new(decide: impl Fn(&LlmError, usize) -> RetryDecision + Send + Sync + 'static) -> Self {
+        Self(std::sync::Arc::new(decide))
+    }
+
+    /// Retry rate limits (429) and server errors (5xx) up to `max_retries` times, and fail
+    /// immediately on everything else, such as a 400 for a rejected schema. Waits as long as
+    /// the `Retry-After` header of the response asks, or otherwise backs off exponentially
+    /// from `base_delay` with random jitter, so that clients rate limited together do not
+    /// retry together. Never waits longer than `max_delay`.
+    pub fn exponential(
+        max_retries: usize,
+        base_delay: std::time::Duration,
+        max_delay: std::time::Duration,
+    ) -> Self {
+        Self::new(move |error, attempt| {
+            let LlmError::ApiStatus {
+                status,
+                retry_after,
+                ..
+            } = error
+            else {
+                return RetryDecision::Fail;
+            };
+            if !(*status == 429 || *status >= 500) || attempt > max_retries {
+                return RetryDecision::Fail;
+            }
+            let delay = retry_after.unwrap_or_else(|| {
+                let backoff = base_delay.saturating_mul(2u32.saturating_pow(attempt as u32 - 1));
+                with_jitter(backoff.min(max_delay))
+            });
+            RetryDecision::Retry(delay.min(max_delay))
+        })
+    }
+
+    /// A policy that never retries.
+    pub fn never() -> Self {
+        Self::new(|_, _| RetryDecision::Fail)
+    }
+
+    /// Decide what to do after the given attempt failed.
+    pub fn decide(&self, error: &LlmError, attempt: usize) -> RetryDecision {
+        (self.0)(error, attempt)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::exponential(
+            3,
+            std::time::Duration::from_millis(500),
+            std::time::Duration::from_secs(30),
+        )
+    }
+}
+
+/// A random duration between half of `delay` and `delay`.
+fn with_jitter(delay: std::time::Duration) -> std::time::Duration {
+    use std::hash::{BuildHasher, Hasher};
+
+    // Each `RandomState` is seeded differently, which is random enough for spreading out
+    // retries without depending on a random number generator.
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let fraction = random as f64 / u64::MAX as f64;
+    delay / 2 + (delay / 2).mul_f64(fraction)
+}
+
+/// Parse the value of a `Retry-After` header given in seconds. The HTTP date form is not
+/// supported, as OpenAI does not send it.
+pub(crate) fn parse_retry_after(value: &str) -> Option<std::time::Duration> {
+    std::time::Duration::try_from_secs_f64(value.trim().parse().ok()?).ok()
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RetryPolicy").finish_non_exhaustive()
+    }
+}
+
+/// How many attempts a request took and how long was spent waiting between them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryStats {
+    /// Attempts made, including the first. Zero when the response came from the cache.
+    pub attempts: usize,
+    /// Total time spent waiting before retries.
+    pub total_delay: std::time::Duration,
+}
+
+/// Run `operation` until it succeeds or the policy decides to stop retrying.
+pub(crate) async fn with_retries<T, F, Fut>(
+    policy: &RetryPolicy,
+    operation: F,
+) -> Result<T, LlmError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, LlmError>>,
+{
+    Ok(with_retry_stats(policy, operation).await?.0)
+}
+
+/// Run `operation` like [`with_retries`], also returning how many attempts it took.
+pub(crate) async fn with_retry_stats<T, F, Fut>(
+    policy: &RetryPolicy,
+    mut operation: F,
+) -> Result<(T, RetryStats), LlmError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, LlmError>>,
+{
+    let mut stats = RetryStats::default();
+    loop {
+        stats.attempts += 1;
+        let error = match operation().await {
+            Ok(value) => return Ok((value, stats)),
+            Err(error) => error,
+        };
+        match policy.decide(&error, stats.attempts) {
+            RetryDecision::Retry(delay) => {
+                tokio::time::sleep(delay).await;
+                stats.total_delay += delay;
+            }
+            RetryDecision::Fail => return Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_status(status: u16) -> LlmError {
+        LlmError::ApiStatus {
+            status,
+            body: String::new(),
+            error: None,
+            retry_after: None,
+        }
+    }
+
+    fn delay(decision: RetryDecision) -> std::time::Duration {
+        match decision {
+            RetryDecision::Retry(delay) => delay,
+            RetryDecision::Fail => panic!("Expected a retry"),
+        }
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let ms = std::time::Duration::from_millis;
+        let policy = RetryPolicy::default();
+        let first = delay(policy.decide(&api_status(429), 1));
+        assert!((ms(250)..=ms(500)).contains(&first), "{first:?}");
+        let third = delay(policy.decide(&api_status(503), 3));
+        assert!((ms(1000)..=ms(2000)).contains(&third), "{third:?}");
+        assert_eq!(policy.decide(&api_status(503), 4), RetryDecision::Fail);
+        assert_eq!(policy.decide(&api_status(400), 1), RetryDecision::Fail);
+        assert_eq!(
+            policy.decide(&LlmError::EmptyChoices, 1),
+            RetryDecision::Fail
+        );
+    }
+
+    #[test]
+    fn test_exponential_policy() {
+        let secs = std::time::Duration::from_secs;
+        let policy = RetryPolicy::exponential(10, secs(1), secs(5));
+
+        // The backoff doubles until it reaches the maximum delay.
+        let backoff = delay(policy.decide(&api_status(500), 8));
+        assert!((secs(5) / 2..=secs(5)).contains(&backoff), "{backoff:?}");
+
+        // Retry-After is honored as long as it does not exceed the maximum delay.
+        let rate_limited = |retry_after| LlmError::ApiStatus {
+            status: 429,
+            body: String::new(),
+            error: None,
+            retry_after: Some(retry_after),
+        };
+        assert_eq!(
+            policy.decide(&rate_limited(secs(2)), 1),
+            RetryDecision::Retry(secs(2))
+        );
+        assert_eq!(
+            policy.decide(&rate_limited(secs(60)), 1),
+            RetryDecision::Retry(secs(5))
+        );
+        assert_eq!(policy.decide(&api_status(400), 1), RetryDecision::Fail);
+        assert_eq!(policy.decide(&api_status(500), 11), RetryDecision::Fail);
+
+        assert_eq!(parse_retry_after("2"), Some(secs(2)));
+        assert_eq!(
+            parse_retry_after(" 0.5 "),
+            Some(std::time::Duration::from_millis(500))
+        );
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_retry_after("-1"), None);
+    }
+
+    #[tokio::test]
+    async fn test_retry_stats() {
+        let delay = std::time::Duration::from_millis(1);
+        let policy = RetryPolicy::new(move |_, _| RetryDecision::Retry(delay));
+
+        // Rate limited twice, then a successful response.
+        let mut calls = 0;
+        let (value, stats) = with_retry_stats(&policy, || {
+            calls += 1;
+            let result = if calls <= 2 {
+                Err(api_status(429))
+            } else {
+                Ok("response")
+            };
+            async move { result }
+        })
+        .await
+        .unwrap();
+        assert_eq!(value, "response");
+        assert_eq!(
+            stats,
+            RetryStats {
+                attempts: 3,
+                total_delay: delay * 2,
+            }
+        );
+    }
+}
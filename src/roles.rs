@@ -0,0 +1,181 @@
+use std::borrow::Cow;
+
+use crate::{Message, Role, is_reasoning_model};
+
+/// The names roles are sent as, for OpenAI-compatible endpoints that expect other names or
+/// casing than OpenAI, such as `model` instead of `assistant`. Responses are read the same
+/// way, and the default OpenAI names are always accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoleNames {
+    developer: Cow<'static, str>,
+    system: Cow<'static, str>,
+    user: Cow<'static, str>,
+    assistant: Cow<'static, str>,
+    tool: Cow<'static, str>,
+}
+
+impl Default for RoleNames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RoleNames {
+    /// The names OpenAI uses: `developer`, `system`, `user`, `assistant` and `tool`.
+    pub const fn new() -> Self {
+        Self {
+            developer: Cow::Borrowed("developer"),
+            system: Cow::Borrowed("system"),
+            user: Cow::Borrowed("user"),
+            assistant: Cow::Borrowed("assistant"),
+            tool: Cow::Borrowed("tool"),
+        }
+    }
+
+    /// Send developer messages with the role `name`.
+    pub fn developer(mut self, name: impl Into<String>) -> Self {
+        self.developer = Cow::Owned(name.into());
+        self
+    }
+
+    /// Send system messages with the role `name`.
+    pub fn system(mut self, name: impl Into<String>) -> Self {
+        self.system = Cow::Owned(name.into());
+        self
+    }
+
+    /// Send user messages with the role `name`.
+    pub fn user(mut self, name: impl Into<String>) -> Self {
+        self.user = Cow::Owned(name.into());
+        self
+    }
+
+    /// Send assistant messages with the role `name`.
+    pub fn assistant(mut self, name: impl Into<String>) -> Self {
+        self.assistant = Cow::Owned(name.into());
+        self
+    }
+
+    /// Send tool results with the role `name`.
+    pub fn tool(mut self, name: impl Into<String>) -> Self {
+        self.tool = Cow::Owned(name.into());
+        self
+    }
+
+    pub(crate) fn name(&self, role: &Role) -> &str {
+        match role {
+            Role::Developer => &self.developer,
+            Role::System => &self.system,
+            Role::User => &self.user,
+            Role::Assistant => &self.assistant,
+            Role::Tool => &self.tool,
+        }
+    }
+
+    pub(crate) fn role(&self, name: &str) -> anyhow::Result<Role> {
+        let roles = [
+            Role::Developer,
+            Role::System,
+            Role::User,
+            Role::Assistant,
+            Role::Tool,
+        ];
+        let default = Self::new();
+        roles
+            .iter()
+            .find(|role| self.name(role) == name)
+            .or_else(|| roles.iter().find(|role| default.name(role) == name))
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Unknown role '{name}'"))
+    }
+}
+
+/// Which role instructions are sent with. Newer OpenAI models expect `developer`, while older
+/// ones and most compatible backends only understand `system`, so messages of either role
+/// are sent as the one the model understands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InstructionRole {
+    /// Use `developer` for OpenAI models known to support it and `system` for all others.
+    #[default]
+    Auto,
+    /// Always use `developer`.
+    Developer,
+    /// Always use `system`.
+    System,
+    /// Send messages with the role they were created with.
+    Unchanged,
+}
+
+/// Model families that expect the `developer` role.
+const DEVELOPER_ROLE_MODELS: &[&str] = &["gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "chatgpt-4o"];
+
+impl InstructionRole {
+    /// The role instructions are sent with to `model`, or `None` to leave them unchanged.
+    fn resolve(self, model: &str) -> Option<Role> {
+        match self {
+            InstructionRole::Auto if supports_developer_role(model) => Some(Role::Developer),
+            InstructionRole::Auto => Some(Role::System),
+            InstructionRole::Developer => Some(Role::Developer),
+            InstructionRole::System => Some(Role::System),
+            InstructionRole::Unchanged => None,
+        }
+    }
+
+    /// Send the developer and system messages of `messages` with the role `model`
+    /// understands.
+    pub(crate) fn normalize(self, messages: &mut [Message], model: &str) {
+        let Some(role) = self.resolve(model) else {
+            return;
+        };
+        for message in messages {
+            if message.role.is_instructions() {
+                message.role = role;
+            }
+        }
+    }
+}
+
+fn supports_developer_role(model: &str) -> bool {
+    let family = model.rsplit('/').next().unwrap_or(model);
+    is_reasoning_model(model)
+        || DEVELOPER_ROLE_MODELS
+            .iter()
+            .any(|prefix| family.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_role() {
+        let messages = || vec![Message::developer("Be brief."), Message::user("Hi")];
+        let roles = |role: InstructionRole, model| {
+            let mut messages = messages();
+            messages.push(Message::new(Role::System, "Be kind."));
+            role.normalize(&mut messages, model);
+            messages.iter().map(Message::role).collect::<Vec<_>>()
+        };
+
+        for model in ["gpt-4o-mini", "o3-mini", "openai/gpt-4.1"] {
+            assert_eq!(
+                roles(InstructionRole::Auto, model),
+                [Role::Developer, Role::User, Role::Developer]
+            );
+        }
+        for model in ["gpt-3.5-turbo", "gpt-4-turbo", "llama3.1:8b"] {
+            assert_eq!(
+                roles(InstructionRole::Auto, model),
+                [Role::System, Role::User, Role::System]
+            );
+        }
+        assert_eq!(
+            roles(InstructionRole::Developer, "llama3.1:8b"),
+            [Role::Developer, Role::User, Role::Developer]
+        );
+        assert_eq!(
+            roles(InstructionRole::Unchanged, "gpt-3.5-turbo"),
+            [Role::Developer, Role::User, Role::System]
+        );
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::HashSet;
+use std::sync::{LazyLock, Mutex};
+
+use crate::Schema;
+
+/// Constraints on the value of numbers, strings and arrays, which strict mode rejects while
+/// the schema means the same to the model without them. Removed from strict schemas
+/// generated by [`crate::get_schema`], see [`strip_constraints`].
+const CONSTRAINT_KEYWORDS: &[&str] = &[
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "multipleOf",
+    "minLength",
+    "maxLength",
+    "pattern",
+    "minItems",
+    "maxItems",
+];
+
+/// Keywords strict mode rejects, besides `format` and the [`CONSTRAINT_KEYWORDS`].
+const UNSUPPORTED_KEYWORDS: &[&str] = &[
+    "uniqueItems",
+    "contains",
+    "minProperties",
+    "maxProperties",
+    "patternProperties",
+    "propertyNames",
+    "unevaluatedProperties",
+    "unevaluatedItems",
+];
+
+/// A part of a schema that strict mode rejects. `pointer` is the JSON pointer of the
+/// offending (sub)schema within the schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaIssue {
+    /// A string format such as `date-time`.
+    Format { pointer: String },
+    /// An object that does not set `additionalProperties` to `false`.
+    AdditionalProperties { pointer: String },
+    /// A property missing from the `required` list of its object.
+    NotRequired { pointer: String, property: String },
+    /// Another keyword strict mode does not support, such as `minLength` or `pattern`.
+    UnsupportedKeyword { pointer: String, keyword: String },
+    /// A property serde does not deserialize into any field, see [`check_field_names`].
+    UnknownField { pointer: String, property: String },
+}
+
+impl std::fmt::Display for SchemaIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaIssue::Format { pointer } => {
+                write!(
+                    f,
+                    "'{pointer}' has a format, which strict mode does not support"
+                )
+            }
+            SchemaIssue::AdditionalProperties { pointer } => write!(
+                f,
+                "Object '{pointer}' must set additionalProperties to false"
+            ),
+            SchemaIssue::NotRequired { pointer, property } => write!(
+                f,
+                "Property '{property}' of '{pointer}' must be required, make it an Option instead"
+            ),
+            SchemaIssue::UnsupportedKeyword { pointer, keyword } => write!(
+                f,
+                "'{pointer}' uses '{keyword}', which strict mode does not support"
+            ),
+            SchemaIssue::UnknownField { pointer, property } => write!(
+                f,
+                "Property '{property}' of '{pointer}' is not a field serde deserializes, check \
+                    that the #[serde] and #[schemars] renames agree"
+            ),
+        }
+    }
+}
+
+/// Check `schema` against the known constraints of strict mode, to find out why OpenAI would
+/// reject it without sending a request. The schemas created by [`crate::get_schema`] are
+/// adapted to most of these, so this is mainly useful for hand-written or modified schemas.
+///
+/// Reports every issue found rather than stopping at the first one.
+pub fn validate_schema(schema: &Schema) -> Result<(), Vec<SchemaIssue>> {
+    let mut issues = Vec::new();
+    check(schema.json_schema(), "", &mut issues);
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
+
+/// Check the subschema at `pointer` and everything below it.
+fn check(schema: &serde_json::Value, pointer: &str, issues: &mut Vec<SchemaIssue>) {
+    let Some(object) = schema.as_object() else {
+        return;
+    };
+    let at = || {
+        if pointer.is_empty() {
+            "/".to_string()
+        } else {
+            pointer.to_string()
+        }
+    };
+
+    if object.contains_key("format") {
+        issues.push(SchemaIssue::Format { pointer: at() });
+    }
+    for keyword in CONSTRAINT_KEYWORDS.iter().chain(UNSUPPORTED_KEYWORDS) {
+        if object.contains_key(*keyword) {
+            issues.push(SchemaIssue::UnsupportedKeyword {
+                pointer: at(),
+                keyword: keyword.to_string(),
+            });
+        }
+    }
+
+    if let Some(serde_json::Value::Object(properties)) = object.get("properties") {
+        if object.get("additionalProperties") != Some(&serde_json::Value::Bool(false)) {
+            issues.push(SchemaIssue::AdditionalProperties { pointer: at() });
+        }
+        let required = object.get("required").and_then(serde_json::Value::as_array);
+        for (property, subschema) in properties {
+            let listed =
+                required.is_some_and(|required| required.contains(&property.as_str().into()));
+            if !listed {
+                issues.push(SchemaIssue::NotRequired {
+                    pointer: at(),
+                    property: property.clone(),
+                });
+            }
+            check(
+                subschema,
+                &format!("{pointer}/properties/{}", escape(property)),
+                issues,
+            );
+        }
+    }
+
+    for keyword in ["items", "additionalProperties", "not"] {
+        if let Some(subschema) = object.get(keyword) {
+            check(subschema, &format!("{pointer}/{keyword}"), issues);
+        }
+    }
+    for keyword in ["anyOf", "oneOf", "allOf", "prefixItems"] {
+        if let Some(serde_json::Value::Array(subschemas)) = object.get(keyword) {
+            for (index, subschema) in subschemas.iter().enumerate() {
+                check(subschema, &format!("{pointer}/{keyword}/{index}"), issues);
+            }
+        }
+    }
+    if let Some(serde_json::Value::Object(defs)) = object.get("$defs") {
+        for (name, subschema) in defs {
+            check(
+                subschema,
+                &format!("{pointer}/$defs/{}", escape(name)),
+                issues,
+            );
+        }
+    }
+}
+
+/// Remove the [`CONSTRAINT_KEYWORDS`] from `schema` and everything below it, such as the
+/// `minimum` of unsigned integers or the range of `#[schemars(range(min = 0, max = 1))]`.
+/// OpenAI does not enforce them in strict mode, so values outside of the range still parse
+/// and must be checked after parsing, for example with [`crate::query_openai_validated`].
+pub(crate) fn strip_constraints(schema: &mut serde_json::Value) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+    for keyword in CONSTRAINT_KEYWORDS {
+        object.remove(*keyword);
+    }
+
+    for keyword in ["properties", "$defs"] {
+        if let Some(serde_json::Value::Object(subschemas)) = object.get_mut(keyword) {
+            subschemas.values_mut().for_each(strip_constraints);
+        }
+    }
+    for keyword in ["items", "additionalProperties", "not"] {
+        if let Some(subschema) = object.get_mut(keyword) {
+            strip_constraints(subschema);
+        }
+    }
+    for keyword in ["anyOf", "oneOf", "allOf", "prefixItems"] {
+        if let Some(serde_json::Value::Array(subschemas)) = object.get_mut(keyword) {
+            subschemas.iter_mut().for_each(strip_constraints);
+        }
+    }
+}
+
+/// Check that serde deserializes every top-level property of `schema` into a field of `T`.
+/// The schema derive follows `#[serde(rename)]` and `#[serde(rename_all)]`, but a
+/// `#[schemars(rename)]` that disagrees with them or a hand-written `JsonSchema` impl can
+/// name properties differently from the fields serde expects. The model then sends values
+/// serde ignores, and `Option` or `#[serde(default)]` fields are silently left empty.
+///
+/// Only structs with named fields can be checked, any other `T` passes. Nested types are not
+/// checked. Debug builds warn about these issues on the first query for each schema.
+pub fn check_field_names<T>(schema: &Schema) -> Result<(), Vec<SchemaIssue>>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let (Some(fields), Some(properties)) = (
+        serde_fields::<T>(),
+        schema.json_schema()["properties"].as_object(),
+    ) else {
+        return Ok(());
+    };
+    let issues: Vec<SchemaIssue> = properties
+        .keys()
+        .filter(|property| !fields.contains(&property.as_str()))
+        .map(|property| SchemaIssue::UnknownField {
+            pointer: "/".to_string(),
+            property: property.clone(),
+        })
+        .collect();
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
+
+/// Schemas [`warn_unknown_fields`] already warned about.
+static WARNED_SCHEMAS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// Log the issues found by [`check_field_names`] as `tracing` warnings in debug builds, once
+/// per schema name.
+pub(crate) fn warn_unknown_fields<T>(schema: &Schema)
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let Err(issues) = check_field_names::<T>(schema) else {
+        return;
+    };
+    let first = WARNED_SCHEMAS
+        .lock()
+        .expect("Warned schemas lock poisoned")
+        .insert(schema.name().to_string());
+    if first {
+        for issue in issues {
+            tracing::warn!(
+                schema = schema.name(),
+                %issue,
+                "Schema does not match the fields of its type"
+            );
+        }
+    }
+}
+
+/// The names serde accepts for the fields of `T`, including aliases, if it is a struct with
+/// named fields. Found by deserializing `T` from [`FieldNames`], which fails right after
+/// serde tells it the fields it expects.
+fn serde_fields<T>() -> Option<&'static [&'static str]>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let fields = std::cell::Cell::new(None);
+    let _ = T::deserialize(FieldNames(&fields));
+    fields.get()
+}
+
+/// A deserializer that records the fields of the struct it is asked for and fails.
+struct FieldNames<'a>(&'a std::cell::Cell<Option<&'static [&'static str]>>);
+
+impl<'de> serde::Deserializer<'de> for FieldNames<'_> {
+    type Error = serde::de::value::Error;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(serde::de::Error::custom("Not a struct"))
+    }
+
+    fn deserialize_struct<V: serde::de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.set(Some(fields));
+        Err(serde::de::Error::custom("Fields recorded"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+/// Escape a key for use in a JSON pointer.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Person {
+        name: String,
+        nickname: Option<String>,
+        friends: Vec<Friend>,
+    }
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Friend {
+        name: String,
+    }
+
+    fn schema(json: serde_json::Value) -> Schema {
+        Schema {
+            name: "test".to_string(),
+            schema: json,
+            strict: true,
+        }
+    }
+
+    #[test]
+    fn test_validate_schema() {
+        assert_eq!(validate_schema(&crate::get_schema::<Person>()), Ok(()));
+
+        let issues = validate_schema(&schema(serde_json::json!({
+            "type": "object",
+            "properties": {
+                "format": { "type": "string", "format": "date-time" },
+                "tags": {
+                    "type": "array",
+                    "items": { "type": "string", "pattern": "^[a-z]+$" },
+                    "minItems": 1,
+                },
+                "address": {
+                    "type": "object",
+                    "properties": { "street": { "type": "string" } },
+                    "required": ["street"],
+                },
+            },
+            "required": ["format", "tags"],
+            "additionalProperties": false,
+        })))
+        .unwrap_err();
+        assert_eq!(
+            issues,
+            [
+                SchemaIssue::Format {
+                    pointer: "/properties/format".to_string(),
+                },
+                SchemaIssue::UnsupportedKeyword {
+                    pointer: "/properties/tags".to_string(),
+                    keyword: "minItems".to_string(),
+                },
+                SchemaIssue::UnsupportedKeyword {
+                    pointer: "/properties/tags/items".to_string(),
+                    keyword: "pattern".to_string(),
+                },
+                SchemaIssue::NotRequired {
+                    pointer: "/".to_string(),
+                    property: "address".to_string(),
+                },
+                SchemaIssue::AdditionalProperties {
+                    pointer: "/properties/address".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            issues[0].to_string(),
+            "'/properties/format' has a format, which strict mode does not support"
+        );
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    #[serde(rename_all = "camelCase")]
+    struct Contact {
+        first_name: String,
+        #[serde(rename = "mail")]
+        email_address: Option<String>,
+    }
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    #[serde(rename_all = "camelCase")]
+    #[schemars(rename_all = "snake_case")]
+    #[allow(dead_code)]
+    struct Mismatched {
+        first_name: String,
+        nickname: Option<String>,
+    }
+
+    #[test]
+    fn test_check_field_names() {
+        let schema = crate::get_schema::<Contact>();
+        let properties = schema.json_schema()["properties"].as_object().unwrap();
+        assert_eq!(properties.keys().collect::<Vec<_>>(), ["firstName", "mail"]);
+        assert_eq!(check_field_names::<Contact>(&schema), Ok(()));
+        let contact: Contact =
+            crate::parse_structured(r#"{"firstName": "Ada", "mail": "ada@example.com"}"#).unwrap();
+        assert_eq!(
+            contact,
+            Contact {
+                first_name: "Ada".to_string(),
+                email_address: Some("ada@example.com".to_string()),
+            }
+        );
+
+        let mismatched = crate::get_schema::<Mismatched>();
+        assert_eq!(
+            check_field_names::<Mismatched>(&mismatched),
+            Err(vec![SchemaIssue::UnknownField {
+                pointer: "/".to_string(),
+                property: "first_name".to_string(),
+            }])
+        );
+
+        // Types other than structs pass.
+        assert_eq!(
+            check_field_names::<Vec<String>>(&crate::get_schema::<Vec<String>>()),
+            Ok(())
+        );
+    }
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Rating {
+        #[schemars(range(min = 0.0, max = 1.0))]
+        score: f32,
+        votes: u32,
+        #[schemars(length(min = 1, max = 280))]
+        comment: String,
+        tags: Vec<Tag>,
+    }
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    #[allow(dead_code)]
+    struct Tag {
+        #[schemars(regex(pattern = r"^[a-z]+$"))]
+        name: String,
+        weight: Option<u8>,
+    }
+
+    #[test]
+    fn test_strip_constraints() {
+        let strict = crate::get_schema::<Rating>();
+        assert_eq!(validate_schema(&strict), Ok(()));
+        let json = strict.json_schema().to_string();
+        for keyword in CONSTRAINT_KEYWORDS {
+            assert!(
+                !json.contains(&format!(r#""{keyword}""#)),
+                "{keyword}: {json}"
+            );
+        }
+
+        // Without strict mode the constraints are kept, as the model may still follow them.
+        let lenient = crate::get_schema_with_opts::<Rating>(&crate::SchemaOptions {
+            strict: false,
+            ..Default::default()
+        })
+        .unwrap();
+        let properties = &lenient.json_schema()["properties"];
+        assert_eq!(properties["score"]["maximum"], 1.0);
+        assert_eq!(properties["comment"]["maxLength"], 280);
+
+        // Properties named like keywords are left alone.
+        let mut schema = serde_json::json!({
+            "type": "object",
+            "properties": { "minimum": { "type": "integer", "minimum": 0 } },
+        });
+        strip_constraints(&mut schema);
+        assert_eq!(
+            schema["properties"],
+            serde_json::json!({ "minimum": { "type": "integer" } })
+        );
+    }
+}
@@ -0,0 +1,631 @@
+use futures::{Stream, StreamExt};
+
+use crate::{
+    LlmClient, LlmError, Message, OpenAIChatCompletionQuery, Schema, parse_structured, post_query,
+    prepare_query, retry, schema_name, take_definitions, try_get_schema,
+};
+
+/// Query OpenAI and yield the content of the response as it is generated, for example to
+/// render a long answer while it arrives. The content is the JSON of `T` in pieces, so only
+/// the concatenation of all deltas parses into `T`, see [`collect_structured`].
+pub async fn query_openai_stream<T>(
+    messages: Vec<Message>,
+) -> Result<impl Stream<Item = Result<String, LlmError>>, LlmError>
+where
+    T: schemars::JsonSchema + 'static,
+{
+    LlmClient::default().query_stream::<T>(messages).await
+}
+
+/// Concatenate the content deltas of a stream from [`query_openai_stream`] and parse the
+/// result into `T` once the stream ends.
+pub async fn collect_structured<T, S>(deltas: S) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+    S: Stream<Item = Result<String, LlmError>>,
+{
+    let mut deltas = std::pin::pin!(deltas);
+    let mut content = String::new();
+    while let Some(delta) = deltas.next().await {
+        content.push_str(&delta?);
+    }
+    parse_structured(&content)
+}
+
+/// Query OpenAI for a list of records and yield each record as soon as it has been fully
+/// generated, rather than waiting for the whole list. Ideal for extracting many rows from a
+/// large document while consuming them early.
+///
+/// OpenAI requires the root of a schema to be an object, so the records are requested as the
+/// array `records` inside a wrapping object. A stream that ends before the list is closed
+/// yields an [`LlmError::ContentParse`] error last.
+pub async fn query_openai_stream_records<T>(
+    messages: Vec<Message>,
+) -> Result<impl Stream<Item = Result<T, LlmError>>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let deltas =
+        query_openai_stream_inner(&LlmClient::default(), messages, records_schema::<T>()?).await?;
+
+    let state = RecordStream {
+        deltas: Box::pin(deltas),
+        splitter: RecordSplitter::default(),
+        pending: std::collections::VecDeque::new(),
+        finished: false,
+    };
+    let stream = futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(record) = state.pending.pop_front() {
+                return Some((parse_structured::<T>(&record), state));
+            }
+            if state.finished {
+                return None;
+            }
+            match state.deltas.next().await {
+                Some(Ok(delta)) => state.pending.extend(state.splitter.push(&delta)),
+                Some(Err(error)) => {
+                    state.finished = true;
+                    return Some((Err(error), state));
+                }
+                None => {
+                    state.finished = true;
+                    if !state.splitter.is_complete() {
+                        let error = LlmError::ContentParse(serde::de::Error::custom(
+                            "Stream ended before the list of records was complete",
+                        ));
+                        return Some((Err(error), state));
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(stream)
+}
+
+/// Query OpenAI and yield successive snapshots of `T` while the response is generated, each
+/// parsed from the JSON so far with its open strings, arrays and objects closed. Useful to
+/// show a form filling in live.
+///
+/// Fields that have not arrived yet must deserialize to their default, so `T` needs
+/// `#[serde(default)]` on the struct, which in turn requires it to implement `Default`.
+/// Snapshots are best-effort: a number or string may still grow in the next snapshot, and
+/// JSON that cannot be closed yet, such as a half-written key, yields no snapshot. The last
+/// snapshot is parsed from the complete response like any other query, and an error is
+/// yielded instead if that fails.
+pub async fn query_openai_stream_partial<T>(
+    messages: Vec<Message>,
+) -> Result<impl Stream<Item = Result<T, LlmError>>, LlmError>
+where
+    T: Default + for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let deltas =
+        query_openai_stream_inner(&LlmClient::default(), messages, try_get_schema::<T>()?).await?;
+    Ok(partial_snapshots(deltas))
+}
+
+/// Turn a stream of content deltas into snapshots of `T`, see
+/// [`query_openai_stream_partial`].
+fn partial_snapshots<T, S>(deltas: S) -> impl Stream<Item = Result<T, LlmError>>
+where
+    T: for<'a> serde::Deserialize<'a>,
+    S: Stream<Item = Result<String, LlmError>>,
+{
+    let state = PartialStream {
+        deltas: Box::pin(deltas),
+        content: String::new(),
+        last_snapshot: None,
+        finished: false,
+    };
+    futures::stream::unfold(state, |mut state| async move {
+        while !state.finished {
+            match state.deltas.next().await {
+                Some(Ok(delta)) => {
+                    state.content.push_str(&delta);
+                    let Some(closed) = close_partial_json(&state.content) else {
+                        continue;
+                    };
+                    if state.last_snapshot.as_ref() == Some(&closed) {
+                        continue;
+                    }
+                    if let Ok(snapshot) = serde_json::from_str(&closed) {
+                        state.last_snapshot = Some(closed);
+                        return Some((Ok(snapshot), state));
+                    }
+                }
+                Some(Err(error)) => {
+                    state.finished = true;
+                    return Some((Err(error), state));
+                }
+                None => {
+                    state.finished = true;
+                    let complete = parse_structured(&state.content);
+                    return Some((complete, state));
+                }
+            }
+        }
+        None
+    })
+}
+
+struct PartialStream<S> {
+    deltas: std::pin::Pin<Box<S>>,
+    content: String,
+    /// The closed JSON the last snapshot was parsed from, to skip deltas that change
+    /// nothing, such as whitespace or half a key.
+    last_snapshot: Option<String>,
+    finished: bool,
+}
+
+/// Complete the start of a JSON document by closing its open string and containers. Tokens
+/// that cannot be completed, such as a half-written key, literal or number, are dropped by
+/// cutting the text back until the result is valid JSON. `None` if no prefix can be
+/// completed, for example before the first `{`.
+fn close_partial_json(partial: &str) -> Option<String> {
+    let mut end = partial.trim_end().len();
+    while end > 0 {
+        let closed = close_at(&partial[..end]);
+        if serde_json::from_str::<serde::de::IgnoredAny>(&closed).is_ok() {
+            return Some(closed);
+        }
+        end = partial[..end]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(index, _)| index);
+    }
+    None
+}
+
+/// `partial` with its open string and containers closed, which is not necessarily valid.
+fn close_at(partial: &str) -> String {
+    let mut open = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in partial.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => open.push('}'),
+            '[' => open.push(']'),
+            '}' | ']' => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+
+    let mut closed = partial.to_string();
+    if in_string {
+        closed.push('"');
+    }
+    closed.extend(open.iter().rev());
+    closed
+}
+
+struct RecordStream<S> {
+    deltas: std::pin::Pin<Box<S>>,
+    splitter: RecordSplitter,
+    pending: std::collections::VecDeque<String>,
+    finished: bool,
+}
+
+/// A schema wrapping a list of `T` in an object.
+fn records_schema<T: schemars::JsonSchema + 'static>() -> Result<Schema, LlmError> {
+    let mut item = try_get_schema::<T>()?;
+    let defs = take_definitions(&mut item.schema);
+
+    let mut schema = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "records": {
+                "type": "array",
+                "items": item.schema,
+            },
+        },
+        "required": ["records"],
+        "additionalProperties": false,
+    });
+    if !defs.is_empty() {
+        schema["$defs"] = serde_json::Value::Object(defs);
+    }
+
+    Ok(Schema {
+        // Shortened like long type names, as the item name may already be close to the
+        // limit.
+        name: schema_name(&format!("{}_records", item.name), ""),
+        schema,
+        strict: true,
+    })
+}
+
+/// Send a streaming query and return the stream of content deltas. Failing to connect or a
+/// non-success status is retried according to the configured [`crate::RetryPolicy`], but
+/// failures once the stream has started are not.
+pub(crate) async fn query_openai_stream_inner(
+    client: &LlmClient,
+    messages: Vec<Message>,
+    schema: Schema,
+) -> Result<impl Stream<Item = Result<String, LlmError>> + use<>, LlmError> {
+    let mut query = OpenAIChatCompletionQuery {
+        stream: true,
+        ..OpenAIChatCompletionQuery::new(client.config.model.clone(), messages, schema)
+    };
+    prepare_query(client, &mut query)?;
+
+    let policy = client.current_retry_policy();
+    let response = retry::with_retries(&policy, || post_query(client, &query)).await?;
+
+    Ok(content_deltas(response.bytes_stream()))
+}
+
+#[derive(serde::Deserialize)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+}
+
+#[derive(serde::Deserialize)]
+struct StreamChoice {
+    delta: Delta,
+}
+
+#[derive(serde::Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+/// Content deltas decoded so far from a server-sent events body.
+#[derive(Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl SseDecoder {
+    /// Add a chunk of the body and return the content of the events it completed. Events
+    /// are only decoded once complete, so chunks may split them anywhere, including within
+    /// a multi-byte character.
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>, LlmError> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut deltas = Vec::new();
+        while !self.done {
+            let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") else {
+                break;
+            };
+            let event: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            deltas.extend(self.decode_event(&event)?);
+        }
+        Ok(deltas)
+    }
+
+    fn decode_event(&mut self, event: &[u8]) -> Result<Vec<String>, LlmError> {
+        let event = String::from_utf8_lossy(event);
+
+        let mut deltas = Vec::new();
+        for data in event.lines().filter_map(|line| line.strip_prefix("data: ")) {
+            if data == "[DONE]" {
+                self.done = true;
+                break;
+            }
+            let chunk: StreamChunk =
+                serde_json::from_str(data).map_err(LlmError::InvalidResponse)?;
+            deltas.extend(
+                chunk
+                    .choices
+                    .into_iter()
+                    .filter_map(|choice| choice.delta.content),
+            );
+        }
+        Ok(deltas)
+    }
+}
+
+/// Decode a server-sent events body from the chat completions API into its content deltas.
+fn content_deltas<S, B, E>(body: S) -> impl Stream<Item = Result<String, LlmError>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<LlmError>,
+{
+    body.scan(SseDecoder::default(), |decoder, chunk| {
+        let deltas = match chunk {
+            _ if decoder.done => None,
+            Ok(chunk) => Some(match decoder.push(chunk.as_ref()) {
+                Ok(deltas) => deltas.into_iter().map(Ok).collect(),
+                Err(error) => vec![Err(error)],
+            }),
+            Err(error) => Some(vec![Err(error.into())]),
+        };
+        futures::future::ready(deltas)
+    })
+    .flat_map(futures::stream::iter)
+}
+
+/// Splits the streamed JSON of an object holding a single array into the elements of the
+/// array as each of them completes.
+#[derive(Default)]
+struct RecordSplitter {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    current: String,
+    done: bool,
+}
+
+/// Depth of the elements of the array, which sits inside the root object.
+const RECORD_DEPTH: usize = 2;
+
+impl RecordSplitter {
+    /// Add streamed text and return the records it completed.
+    fn push(&mut self, text: &str) -> Vec<String> {
+        let mut records = Vec::new();
+        for c in text.chars() {
+            if self.done {
+                break;
+            }
+            let in_records = self.depth >= RECORD_DEPTH;
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == '\\' {
+                    self.escaped = true;
+                } else if c == '"' {
+                    self.in_string = false;
+                }
+                if in_records {
+                    self.current.push(c);
+                }
+                continue;
+            }
+
+            match c {
+                '{' | '[' => {
+                    self.depth += 1;
+                    if self.depth > RECORD_DEPTH {
+                        self.current.push(c);
+                    }
+                }
+                '}' | ']' if self.depth == RECORD_DEPTH => {
+                    self.flush(&mut records);
+                    self.depth -= 1;
+                    self.done = true;
+                }
+                '}' | ']' => {
+                    if self.depth > RECORD_DEPTH {
+                        self.current.push(c);
+                    }
+                    self.depth = self.depth.saturating_sub(1);
+                }
+                ',' if self.depth == RECORD_DEPTH => self.flush(&mut records),
+                _ => {
+                    if c == '"' {
+                        self.in_string = true;
+                    }
+                    if in_records {
+                        self.current.push(c);
+                    }
+                }
+            }
+        }
+        records
+    }
+
+    fn flush(&mut self, records: &mut Vec<String>) {
+        let record = self.current.trim();
+        if !record.is_empty() {
+            records.push(record.to_string());
+        }
+        self.current.clear();
+    }
+
+    /// Whether the closing bracket of the array has been seen.
+    fn is_complete(&self) -> bool {
+        self.done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(content: &str) -> String {
+        format!(
+            "data: {}\n\n",
+            serde_json::json!({ "choices": [{ "delta": { "content": content } }] })
+        )
+    }
+
+    #[tokio::test]
+    async fn test_content_deltas_across_chunks() {
+        let body = format!("{}{}data: [DONE]\n\n", chunk("Hel"), chunk("lo"));
+        // Split the body at awkward places, including in the middle of an event.
+        let (first, rest) = body.split_at(10);
+        let (second, third) = rest.split_at(40);
+        let chunks = vec![first, second, third]
+            .into_iter()
+            .map(|chunk| Ok::<_, LlmError>(chunk.as_bytes().to_vec()));
+
+        let deltas: Vec<String> = content_deltas(futures::stream::iter(chunks))
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+        assert_eq!(deltas, ["Hel", "lo"]);
+    }
+
+    #[tokio::test]
+    async fn test_content_deltas_split_character() {
+        let body = format!("{}{}data: [DONE]\n\n", chunk("Grüße"), chunk("🦀"));
+        let bytes = body.as_bytes();
+        // Split inside the two bytes of "ü" and the four bytes of "🦀".
+        let u = body.find('ü').unwrap() + 1;
+        let crab = body.find('🦀').unwrap() + 2;
+        let chunks = vec![&bytes[..u], &bytes[u..crab], &bytes[crab..]]
+            .into_iter()
+            .map(|chunk| Ok::<_, LlmError>(chunk.to_vec()));
+
+        let deltas: Vec<String> = content_deltas(futures::stream::iter(chunks))
+            .map(|delta| delta.unwrap())
+            .collect()
+            .await;
+        assert_eq!(deltas, ["Grüße", "🦀"]);
+    }
+
+    #[tokio::test]
+    async fn test_collect_structured() {
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Answer {
+            answer: u32,
+        }
+
+        let body = format!(
+            "{}{}{}data: [DONE]\n\n",
+            chunk("{\"ans"),
+            chunk("wer\": 4"),
+            chunk("2}")
+        );
+        let chunks = futures::stream::iter([Ok::<_, LlmError>(body.into_bytes())]);
+        let answer: Answer = collect_structured(content_deltas(chunks)).await.unwrap();
+        assert_eq!(answer, Answer { answer: 42 });
+
+        let truncated =
+            futures::stream::iter([Ok::<_, LlmError>(chunk("{\"answer\"").into_bytes())]);
+        assert!(matches!(
+            collect_structured::<Answer, _>(content_deltas(truncated)).await,
+            Err(LlmError::ContentParse(_))
+        ));
+    }
+
+    #[test]
+    fn test_close_partial_json() {
+        let closed = |partial| close_partial_json(partial);
+        assert_eq!(closed(""), None);
+        assert_eq!(closed("{"), Some("{}".to_string()));
+        assert_eq!(
+            closed(r#"{"name": "Ad"#),
+            Some(r#"{"name": "Ad"}"#.to_string())
+        );
+        assert_eq!(
+            closed(r#"{"name": "Ada", "ag"#),
+            Some(r#"{"name": "Ada"}"#.to_string())
+        );
+        assert_eq!(
+            closed(r#"{"name": "Ada", "age":"#),
+            Some(r#"{"name": "Ada"}"#.to_string())
+        );
+        assert_eq!(
+            closed(r#"{"name": "Ada", "age": 3"#),
+            Some(r#"{"name": "Ada", "age": 3}"#.to_string())
+        );
+        assert_eq!(
+            closed(r#"{"tags": ["a", tr"#),
+            Some(r#"{"tags": ["a"]}"#.to_string())
+        );
+        assert_eq!(
+            closed(r#"{"quote": "say \"hi\"#),
+            Some(r#"{"quote": "say \"hi"}"#.to_string())
+        );
+        assert_eq!(
+            closed(r#"{"nested": {"a": [{"b": 1}, {"#),
+            Some(r#"{"nested": {"a": [{"b": 1}, {}]}}"#.to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_snapshots() {
+        #[derive(Debug, Default, PartialEq, serde::Deserialize)]
+        #[serde(default)]
+        struct Form {
+            name: String,
+            age: u32,
+        }
+
+        let deltas = [r#"{"na"#, r#"me": "A"#, r#"da", "#, r#""age": 36"#, "}"];
+        let deltas = futures::stream::iter(deltas.map(|delta| Ok(delta.to_string())));
+        let snapshots: Vec<Form> = partial_snapshots(deltas)
+            .map(|snapshot| snapshot.unwrap())
+            .collect()
+            .await;
+        let form = |name: &str, age| Form {
+            name: name.to_string(),
+            age,
+        };
+        // The key alone and the trailing comma change nothing, and the closing brace gives
+        // the same form as before.
+        assert_eq!(
+            snapshots,
+            [
+                form("", 0),
+                form("A", 0),
+                form("Ada", 0),
+                form("Ada", 36),
+                form("Ada", 36),
+            ]
+        );
+
+        let truncated = futures::stream::iter([Ok(r#"{"name": "A"#.to_string())]);
+        let snapshots: Vec<Result<Form, LlmError>> = partial_snapshots(truncated).collect().await;
+        assert_eq!(*snapshots[0].as_ref().unwrap(), form("A", 0));
+        assert!(matches!(snapshots[1], Err(LlmError::ContentParse(_))));
+    }
+
+    #[test]
+    fn test_record_splitter() {
+        let mut splitter = RecordSplitter::default();
+        let json =
+            r#"{"records": [{"name": "a, [b]", "tags": ["x", "y"]}, {"name": "\"c}\""}, 3]}"#;
+
+        let mut records = Vec::new();
+        for c in json.chars() {
+            records.extend(splitter.push(&c.to_string()));
+        }
+        assert_eq!(
+            records,
+            [
+                r#"{"name": "a, [b]", "tags": ["x", "y"]}"#,
+                r#"{"name": "\"c}\""}"#,
+                "3"
+            ]
+        );
+        assert!(splitter.is_complete());
+    }
+
+    #[test]
+    fn test_record_splitter_incomplete() {
+        let mut splitter = RecordSplitter::default();
+        let records = splitter.push(r#"{"records": [{"name": "a"}, {"name": "#);
+        assert_eq!(records, [r#"{"name": "a"}"#]);
+        assert!(!splitter.is_complete());
+    }
+
+    #[test]
+    fn test_records_schema() {
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Row {
+            name: String,
+        }
+
+        let schema = records_schema::<Row>().unwrap();
+        assert_eq!(
+            schema.schema["properties"]["records"]["items"]["properties"]["name"]["type"],
+            "string"
+        );
+        assert_eq!(schema.schema["required"], serde_json::json!(["records"]));
+
+        // The suffix does not push a name close to the limit over it.
+        crate::set_schema_name::<Row>("r".repeat(60)).unwrap();
+        let schema = records_schema::<Row>().unwrap();
+        assert_eq!(schema.name.len(), 64);
+        assert!(schema.name.starts_with(&"r".repeat(40)));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{LlmError, Message, OpenAIChatCompletionQuery, ResponseFormat, STRICT_SUPPORT};
+
+/// Which endpoints and models cannot handle strict `json_schema` response formats. Entries
+/// are prefixes, so `gpt-3.5` covers every `gpt-3.5-turbo` snapshot. Empty by default, in
+/// which case strict schemas are always sent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StrictSupport {
+    unsupported_base_urls: Vec<String>,
+    unsupported_models: Vec<String>,
+}
+
+impl StrictSupport {
+    /// Support where every endpoint and model handles strict mode.
+    pub const fn new() -> Self {
+        Self {
+            unsupported_base_urls: Vec::new(),
+            unsupported_models: Vec::new(),
+        }
+    }
+
+    /// Mark every endpoint whose URL starts with `prefix` as not supporting strict mode.
+    pub fn unsupported_base_url(mut self, prefix: impl Into<String>) -> Self {
+        self.unsupported_base_urls.push(prefix.into());
+        self
+    }
+
+    /// Mark every model whose name starts with `prefix` as not supporting strict mode.
+    pub fn unsupported_model(mut self, prefix: impl Into<String>) -> Self {
+        self.unsupported_models.push(prefix.into());
+        self
+    }
+
+    /// Whether the model at the given endpoint supports strict mode.
+    pub fn supports_strict(&self, url: &str, model: &str) -> bool {
+        !self
+            .unsupported_base_urls
+            .iter()
+            .any(|u| url.starts_with(u))
+            && !self.unsupported_models.iter().any(|m| model.starts_with(m))
+    }
+}
+
+/// What to do when a strict schema is about to be sent to an endpoint or model that does
+/// not support strict mode according to [`StrictSupport`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StrictMismatch {
+    /// Send the request unchanged and log a warning with `tracing`.
+    #[default]
+    Warn,
+    /// Fall back to the `json_object` response format, describing the schema to the model in
+    /// a developer message instead. The response is still parsed into the same type, but
+    /// nothing guarantees it matches the schema.
+    Downgrade,
+    /// Refuse to send the request.
+    Error,
+}
+
+/// How the response is constrained to the schema.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResponseMode {
+    /// Send the schema as a `json_schema` response format, which the API enforces.
+    #[default]
+    JsonSchema,
+    /// Use the `json_object` response format for models that do not support `json_schema`,
+    /// describing the schema to the model in a developer message instead. The response is
+    /// still parsed into the same type, but nothing guarantees it matches the schema.
+    JsonObject,
+}
+
+/// Switch `query` to the `json_object` response format if it has a schema, see
+/// [`ResponseMode::JsonObject`].
+pub(crate) fn use_json_object(query: &mut OpenAIChatCompletionQuery) {
+    let ResponseFormat::JsonSchema { json_schema } = &query.response_format else {
+        return;
+    };
+    let instructions = format!(
+        "Respond with a JSON object following this JSON schema:\n{}",
+        json_schema.schema
+    );
+    query.messages.insert(0, Message::developer(instructions));
+    query.response_format = ResponseFormat::JsonObject;
+}
+
+/// Apply the configured [`StrictMismatch`] policy to `query` if it uses strict mode where it
+/// is not supported by the endpoint at `url`.
+pub(crate) fn enforce_support(
+    query: &mut OpenAIChatCompletionQuery,
+    url: &str,
+) -> Result<(), LlmError> {
+    let (support, on_mismatch) = &*STRICT_SUPPORT.read().expect("Strict support lock poisoned");
+    enforce(query, url, support, *on_mismatch)
+}
+
+fn enforce(
+    query: &mut OpenAIChatCompletionQuery,
+    url: &str,
+    support: &StrictSupport,
+    on_mismatch: StrictMismatch,
+) -> Result<(), LlmError> {
+    let ResponseFormat::JsonSchema { json_schema } = &query.response_format else {
+        return Ok(());
+    };
+    if !json_schema.strict || support.supports_strict(url, &query.model) {
+        return Ok(());
+    }
+
+    match on_mismatch {
+        StrictMismatch::Warn => {
+            tracing::warn!(
+                schema = %json_schema.name,
+                model = %query.model,
+                %url,
+                "Sending a strict schema to a model that does not support strict mode"
+            );
+        }
+        StrictMismatch::Downgrade => use_json_object(query),
+        StrictMismatch::Error => {
+            return Err(LlmError::StrictUnsupported {
+                model: query.model.clone(),
+                url: url.to_string(),
+                schema: json_schema.name.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+
+    fn query(model: &str) -> OpenAIChatCompletionQuery {
+        OpenAIChatCompletionQuery::new(
+            model.to_string(),
+            vec![Message::user("Hello, world!")],
+            crate::get_schema::<Vec<String>>(),
+        )
+    }
+
+    fn is_strict(query: &OpenAIChatCompletionQuery) -> bool {
+        matches!(
+            &query.response_format,
+            ResponseFormat::JsonSchema { json_schema } if json_schema.strict
+        )
+    }
+
+    #[test]
+    fn test_strict_support() {
+        let support = StrictSupport::new()
+            .unsupported_model("gpt-3.5")
+            .unsupported_base_url("http://localhost");
+        let url = "https://api.openai.com/v1/chat/completions";
+
+        assert!(support.supports_strict(url, "gpt-4o"));
+        assert!(!support.supports_strict(url, "gpt-3.5-turbo-0125"));
+        assert!(!support.supports_strict("http://localhost:11434/v1", "gpt-4o"));
+        assert!(StrictSupport::default().supports_strict(url, "gpt-3.5-turbo"));
+    }
+
+    #[test]
+    fn test_strict_mismatch_policies() {
+        let support = StrictSupport::new().unsupported_model("gpt-3.5");
+        let url = "https://api.openai.com/v1/chat/completions";
+
+        for policy in [
+            StrictMismatch::Warn,
+            StrictMismatch::Downgrade,
+            StrictMismatch::Error,
+        ] {
+            let mut supported = query("gpt-4o");
+            enforce(&mut supported, url, &support, policy).unwrap();
+            assert!(is_strict(&supported));
+            assert_eq!(supported.messages.len(), 1);
+            assert_eq!(
+                serde_json::to_value(&supported).unwrap()["response_format"]["type"],
+                "json_schema"
+            );
+        }
+
+        let mut warned = query("gpt-3.5-turbo");
+        enforce(&mut warned, url, &support, StrictMismatch::Warn).unwrap();
+        assert!(is_strict(&warned));
+
+        let mut downgraded = query("gpt-3.5-turbo");
+        enforce(&mut downgraded, url, &support, StrictMismatch::Downgrade).unwrap();
+        assert!(matches!(
+            downgraded.response_format,
+            ResponseFormat::JsonObject
+        ));
+        assert!(matches!(downgraded.messages[0].role, Role::Developer));
+        assert!(downgraded.messages[0].content.contains(r#""type":"array""#));
+        assert_eq!(
+            serde_json::to_value(&downgraded).unwrap()["response_format"],
+            serde_json::json!({ "type": "json_object" })
+        );
+
+        let mut refused = query("gpt-3.5-turbo");
+        assert!(matches!(
+            enforce(&mut refused, url, &support, StrictMismatch::Error),
+            Err(LlmError::StrictUnsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_json_object_response_mode() {
+        let config = crate::Config::builder().model("gpt-4o").build().unwrap();
+        let client = crate::LlmClient::with_http_client(reqwest::Client::new(), config);
+        let prepared = |mode| {
+            let mut query = query("gpt-4o").with_params(crate::QueryParams {
+                response_mode: mode,
+                ..Default::default()
+            });
+            crate::prepare_query(&client, &mut query).unwrap();
+            query
+        };
+
+        let schema = prepared(ResponseMode::JsonSchema);
+        assert!(is_strict(&schema));
+        assert_eq!(schema.messages.len(), 1);
+
+        let object = prepared(ResponseMode::JsonObject);
+        assert!(matches!(object.response_format, ResponseFormat::JsonObject));
+        assert_eq!(object.messages.len(), 2);
+        assert!(matches!(object.messages[0].role, Role::Developer));
+        assert!(object.messages[0].content.contains(r#""type":"array""#));
+    }
+}
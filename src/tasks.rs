@@ -0,0 +1,123 @@
+//! Ready-made extractions for common tasks, each with its own schema and prompt. They also
+//! serve as examples of how to design types for structured outputs.
+
+use crate::{LlmError, Message, parse_structured, query_openai_inner, try_get_schema};
+
+/// A summary of a text.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Summary {
+    /// A summary of the text in a few sentences.
+    pub summary: String,
+    /// The most important points of the text, most important first.
+    pub key_points: Vec<String>,
+}
+
+/// A named entity mentioned in a text.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Entity {
+    /// The name of the entity as written in the text.
+    pub name: String,
+    pub kind: EntityKind,
+}
+
+/// What kind of thing an [`Entity`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+pub enum EntityKind {
+    Person,
+    Organization,
+    Location,
+    Date,
+    Product,
+    Event,
+    Other,
+}
+
+/// The overall sentiment of a text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, schemars::JsonSchema)]
+pub enum Sentiment {
+    Positive,
+    Negative,
+    Neutral,
+    /// Both clearly positive and clearly negative.
+    Mixed,
+}
+
+/// OpenAI requires the root of a schema to be an object, so lists and enums are wrapped.
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Entities {
+    /// Every entity in the text, in the order they first appear.
+    entities: Vec<Entity>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SentimentResponse {
+    sentiment: Sentiment,
+}
+
+/// Summarize `text`.
+pub async fn summarize(text: impl Into<String>) -> Result<Summary, LlmError> {
+    run("Summarize the text given by the user.", text.into()).await
+}
+
+/// Extract the named entities mentioned in `text`.
+pub async fn extract_entities(text: impl Into<String>) -> Result<Vec<Entity>, LlmError> {
+    let Entities { entities } = run(
+        "Extract every named entity mentioned in the text given by the user. List each \
+            entity once.",
+        text.into(),
+    )
+    .await?;
+    Ok(entities)
+}
+
+/// Classify the overall sentiment of `text`.
+pub async fn classify_sentiment(text: impl Into<String>) -> Result<Sentiment, LlmError> {
+    let SentimentResponse { sentiment } = run(
+        "Classify the overall sentiment of the text given by the user.",
+        text.into(),
+    )
+    .await?;
+    Ok(sentiment)
+}
+
+async fn run<T>(instructions: &str, text: String) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let messages = vec![Message::developer(instructions), Message::user(text)];
+
+    let response = query_openai_inner(messages, try_get_schema::<T>()?).await?;
+    parse_structured(response.content()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_schemas() {
+        for schema in [
+            crate::get_schema::<Summary>().schema,
+            crate::get_schema::<Entities>().schema,
+            crate::get_schema::<SentimentResponse>().schema,
+        ] {
+            assert_eq!(schema["type"], "object");
+            assert_eq!(schema["additionalProperties"], false);
+        }
+
+        let Entities { entities } = serde_json::from_str(
+            r#"{"entities": [{"name": "Ada Lovelace", "kind": "Person"}, {"name": "London", "kind": "Location"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(entities[1].kind, EntityKind::Location);
+        assert_eq!(entities[0].name, "Ada Lovelace");
+
+        let SentimentResponse { sentiment } =
+            serde_json::from_str(r#"{"sentiment": "Mixed"}"#).unwrap();
+        assert_eq!(sentiment, Sentiment::Mixed);
+    }
+}
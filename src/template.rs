@@ -0,0 +1,167 @@
+use crate::{LlmError, Message, Role};
+
+/// A prompt with `{name}` placeholders, rendered into a [`Message`] with the values of the
+/// variables, such as `Translate '{text}' to {language}.`. Write `{{` and `}}` for literal
+/// braces, for example in JSON examples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    role: Role,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Variable(String),
+}
+
+impl PromptTemplate {
+    /// A template for user messages. Fails with [`LlmError::InvalidTemplate`] if a brace is
+    /// not closed or escaped, or a placeholder name is not made of ASCII letters, digits and
+    /// underscores.
+    pub fn new(template: &str) -> Result<Self, LlmError> {
+        Ok(Self {
+            role: Role::User,
+            segments: parse(template)?,
+        })
+    }
+
+    /// Render messages of `role` instead, such as [`Role::Developer`] for instructions.
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// The names of the placeholders, in order of appearance and with repeats.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Variable(name) => Some(name.as_str()),
+            Segment::Text(_) => None,
+        })
+    }
+
+    /// Replace every placeholder with the value of its variable. Fails with
+    /// [`LlmError::MissingVariable`] if a placeholder has no value, while variables the
+    /// template does not use are ignored. Values are inserted as is, so braces in them are
+    /// kept.
+    pub fn render(&self, variables: &[(&str, &str)]) -> Result<Message, LlmError> {
+        let mut content = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => content.push_str(text),
+                Segment::Variable(name) => {
+                    let (_, value) = variables
+                        .iter()
+                        .find(|(key, _)| key == name)
+                        .ok_or_else(|| LlmError::MissingVariable(name.clone()))?;
+                    content.push_str(value);
+                }
+            }
+        }
+        Ok(Message::new(self.role, content))
+    }
+}
+
+/// Split a template into literal text, with escaped braces unescaped, and placeholders.
+fn parse(template: &str) -> Result<Vec<Segment>, LlmError> {
+    let invalid = |reason: String| Err(LlmError::InvalidTemplate(reason));
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '{' if chars.next_if(|&(_, c)| c == '{').is_some() => text.push('{'),
+            '}' if chars.next_if(|&(_, c)| c == '}').is_some() => text.push('}'),
+            '{' => {
+                let rest = &template[index + 1..];
+                let Some(end) = rest.find('}') else {
+                    return invalid(format!("Unclosed '{{' at byte {index}"));
+                };
+                let name = &rest[..end];
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    return invalid(format!(
+                        "Invalid placeholder '{{{name}}}' at byte {index}, write '{{{{' for a \
+                            literal brace"
+                    ));
+                }
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(Segment::Variable(name.to_string()));
+                // Skip the name and the closing brace.
+                for _ in 0..=name.chars().count() {
+                    chars.next();
+                }
+            }
+            '}' => return invalid(format!("Unmatched '}}' at byte {index}")),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template =
+            PromptTemplate::new("Hi {name}! Reply like {{\"greeting\": \"...\"}}, {name}.")
+                .unwrap();
+        assert_eq!(template.variables().collect::<Vec<_>>(), ["name", "name"]);
+
+        let message = template
+            .render(&[("name", "Ada"), ("unused", "x")])
+            .unwrap();
+        assert!(matches!(message.role, Role::User));
+        assert_eq!(
+            message.content,
+            r#"Hi Ada! Reply like {"greeting": "..."}, Ada."#
+        );
+
+        // Braces in values are not placeholders.
+        let message = template.render(&[("name", "{name}")]).unwrap();
+        assert_eq!(
+            message.content,
+            r#"Hi {name}! Reply like {"greeting": "..."}, {name}."#
+        );
+
+        assert!(matches!(
+            template.render(&[]),
+            Err(LlmError::MissingVariable(name)) if name == "name"
+        ));
+
+        let developer = PromptTemplate::new("Answer in {language}.")
+            .unwrap()
+            .role(Role::Developer)
+            .render(&[("language", "Norwegian")])
+            .unwrap();
+        assert!(matches!(developer.role, Role::Developer));
+        assert_eq!(developer.content, "Answer in Norwegian.");
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        for template in ["Hi {name", "Hi name}", "Hi {}", "{\"json\": true}", "{a b}"] {
+            assert!(
+                matches!(
+                    PromptTemplate::new(template),
+                    Err(LlmError::InvalidTemplate(_))
+                ),
+                "{template}"
+            );
+        }
+        assert_eq!(
+            PromptTemplate::new("Ægir {navn}}} {{")
+                .unwrap()
+                .render(&[("navn", "Ran")])
+                .unwrap()
+                .content,
+            "Ægir Ran} {"
+        );
+    }
+}
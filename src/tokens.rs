@@ -0,0 +1,164 @@
+use crate::{ContentPart, LlmError, Message, Schema};
+
+/// Context window sizes in tokens per model family, from most to least specific. Dated
+/// snapshots such as `o3-mini-2025-01-31` resolve to the longest matching prefix, so every
+/// family needs its own entry rather than falling back to a shorter one, such as `gpt-4.5`
+/// to the much smaller window of `gpt-4`.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-5", 400_000),
+    ("gpt-4.5", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1-preview", 128_000),
+    ("o1-mini", 128_000),
+    ("o1", 200_000),
+    ("o3-mini", 200_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+];
+
+/// Roughly how many characters make up a token for English text with OpenAI's tokenizers.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens OpenAI adds around every message for the role and separators.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Tokens used to prime the assistant's reply.
+const TOKENS_PER_REPLY: usize = 3;
+
+/// Tokens OpenAI charges for an image at `low` detail, whatever its size.
+const TOKENS_PER_LOW_DETAIL_IMAGE: usize = 85;
+
+/// Tokens for an image at `high` or `auto` detail. The real cost depends on the size of the
+/// image, which is not known here, so this is that of a 1024x1024 image.
+const TOKENS_PER_IMAGE: usize = 765;
+
+/// The context window of a model, or `None` if the model is unknown.
+pub fn context_window(model: &str) -> Option<usize> {
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, window)| *window)
+}
+
+fn known_context_window(model: &str) -> Result<usize, LlmError> {
+    context_window(model).ok_or_else(|| LlmError::UnknownContextWindow(model.to_string()))
+}
+
+/// Estimate the number of tokens in a piece of text. This is a heuristic rather than an
+/// exact tokenizer, so leave some headroom when comparing against hard limits.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Estimate the number of prompt tokens a request uses. The schema is counted when given,
+/// since OpenAI injects it into the prompt for structured outputs. Images count as a fixed
+/// number of tokens by their detail, as their size is not known.
+pub fn estimate_prompt_tokens(messages: &[Message], schema: Option<&Schema>) -> usize {
+    let messages: usize = messages
+        .iter()
+        .map(|message| {
+            let calls: usize = message
+                .tool_calls
+                .iter()
+                .map(|call| estimate_tokens(&call.function.arguments))
+                .sum();
+            let parts: usize = message.parts.iter().map(estimate_part_tokens).sum();
+            TOKENS_PER_MESSAGE + estimate_tokens(&message.content) + parts + calls
+        })
+        .sum();
+    let schema = schema.map_or(0, |schema| estimate_tokens(&schema.schema.to_string()));
+
+    messages + schema + TOKENS_PER_REPLY
+}
+
+fn estimate_part_tokens(part: &ContentPart) -> usize {
+    match part {
+        ContentPart::Text { text } => estimate_tokens(text),
+        ContentPart::ImageUrl { image_url } if image_url.detail.as_deref() == Some("low") => {
+            TOKENS_PER_LOW_DETAIL_IMAGE
+        }
+        ContentPart::ImageUrl { .. } => TOKENS_PER_IMAGE,
+    }
+}
+
+/// Estimate how many tokens remain in the model's context window after the prompt,
+/// saturating at zero. Errors for unknown models rather than guessing a limit.
+pub fn remaining_context(
+    messages: &[Message],
+    schema: Option<&Schema>,
+    model: &str,
+) -> Result<usize, LlmError> {
+    let window = known_context_window(model)?;
+    Ok(window.saturating_sub(estimate_prompt_tokens(messages, schema)))
+}
+
+/// Estimate whether a request fits in the model's context window, so callers can chunk or
+/// truncate before OpenAI rejects it with `context_length_exceeded`.
+pub fn fits_context(
+    messages: &[Message],
+    schema: Option<&Schema>,
+    model: &str,
+) -> Result<bool, LlmError> {
+    let window = known_context_window(model)?;
+    Ok(estimate_prompt_tokens(messages, schema) <= window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_window_prefix() {
+        assert_eq!(context_window("o3-mini-2025-01-31"), Some(200_000));
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_window("gpt-4-0613"), Some(8_192));
+        assert_eq!(context_window("gpt-4.5-preview"), Some(128_000));
+        assert_eq!(context_window("gpt-4.1-mini"), Some(1_047_576));
+        assert_eq!(context_window("gpt-5-mini"), Some(400_000));
+        assert_eq!(context_window("not-a-model"), None);
+    }
+
+    #[test]
+    fn test_fits_context() {
+        let short = vec![Message::user("Hello, world!")];
+        let long = vec![Message::user("word ".repeat(10_000))];
+
+        assert!(fits_context(&short, None, "gpt-4").unwrap());
+        assert!(!fits_context(&long, None, "gpt-4").unwrap());
+        assert!(matches!(
+            fits_context(&short, None, "unknown-model"),
+            Err(LlmError::UnknownContextWindow(model)) if model == "unknown-model"
+        ));
+
+        let schema = crate::get_schema::<Vec<String>>();
+        let without_schema = remaining_context(&short, None, "gpt-4").unwrap();
+        let with_schema = remaining_context(&short, Some(&schema), "gpt-4").unwrap();
+        assert!(with_schema < without_schema);
+        assert_eq!(remaining_context(&long, None, "gpt-4").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_image_tokens() {
+        let text = vec![Message::user("What is in this image?")];
+        let base = estimate_prompt_tokens(&text, None);
+        let image = ContentPart::image_url("https://example.com/cat.png");
+
+        let with_image = vec![Message::user("What is in this image?").with_part(image.clone())];
+        assert_eq!(
+            estimate_prompt_tokens(&with_image, None),
+            base + TOKENS_PER_IMAGE
+        );
+        let low_detail =
+            vec![Message::user("What is in this image?").with_part(image.detail("low"))];
+        assert_eq!(
+            estimate_prompt_tokens(&low_detail, None),
+            base + TOKENS_PER_LOW_DETAIL_IMAGE
+        );
+    }
+}
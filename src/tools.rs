@@ -0,0 +1,346 @@
+use crate::{
+    LlmClient, LlmError, Message, OpenAIChatCompletionQuery, OpenAIChatCompletionResponse, Schema,
+    parse_structured, run_query, try_get_schema, validate_schema_name,
+};
+
+/// A function the model may call, whose arguments follow the schema of a Rust type, see
+/// [`query_openai_tools`].
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", rename = "function")]
+pub struct ToolDef {
+    function: FunctionDef,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct FunctionDef {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    parameters: serde_json::Value,
+    strict: bool,
+}
+
+impl ToolDef {
+    /// A tool taking `T` as its arguments, named after the schema of `T`. The arguments of a
+    /// function are named, so `T` must be a struct or another type with an object schema,
+    /// otherwise this fails with [`LlmError::InvalidParameter`].
+    pub fn new<T: schemars::JsonSchema + 'static>() -> Result<Self, LlmError> {
+        let schema = try_get_schema::<T>()?;
+        Self::from_schema(schema.name.clone(), schema)
+    }
+
+    /// A tool named `name` taking `T` as its arguments, like [`ToolDef::new`]. Tool names
+    /// follow the same rules as schema names, see [`crate::SchemaOptions::name`].
+    pub fn named<T: schemars::JsonSchema + 'static>(
+        name: impl Into<String>,
+    ) -> Result<Self, LlmError> {
+        let name = name.into();
+        validate_schema_name(&name)?;
+        Self::from_schema(name, try_get_schema::<T>()?)
+    }
+
+    fn from_schema(name: String, schema: Schema) -> Result<Self, LlmError> {
+        // OpenAI rejects the whole request otherwise.
+        let kind = &schema.schema["type"];
+        if kind != "object" {
+            return Err(LlmError::InvalidParameter {
+                name: "tools",
+                reason: format!("The parameters of tool '{name}' must be an object, not {kind}"),
+            });
+        }
+        Ok(Self {
+            function: FunctionDef {
+                name,
+                description: None,
+                parameters: schema.schema,
+                strict: schema.strict,
+            },
+        })
+    }
+
+    /// Tell the model what the tool does and when to call it.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.function.description = Some(description.into());
+        self
+    }
+
+    /// The name the model calls the tool by.
+    pub fn name(&self) -> &str {
+        &self.function.name
+    }
+
+    /// Let the model call the tool with arguments that do not follow the schema exactly.
+    pub(crate) fn disable_strict(&mut self) {
+        self.function.strict = false;
+    }
+}
+
+/// Whether and which tools the model calls.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// The model decides whether to call tools or answer with text.
+    #[default]
+    Auto,
+    /// The model must call at least one tool.
+    Required,
+    /// The model must not call any tool.
+    None,
+    /// The model must call the tool with this name.
+    Function(String),
+}
+
+impl serde::Serialize for ToolChoice {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ToolChoice::Auto => serializer.serialize_str("auto"),
+            ToolChoice::Required => serializer.serialize_str("required"),
+            ToolChoice::None => serializer.serialize_str("none"),
+            ToolChoice::Function(name) => serde::Serialize::serialize(
+                &serde_json::json!({ "type": "function", "function": { "name": name } }),
+                serializer,
+            ),
+        }
+    }
+}
+
+/// A call of a tool by the model.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename = "function")]
+pub struct ToolCall {
+    /// Identifies the call, to relate its result to it.
+    pub id: String,
+    pub function: FunctionCall,
+}
+
+/// The function a [`ToolCall`] calls and its arguments.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments as JSON text, see [`ToolCall::arguments`].
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// Parse the arguments of the call into `T`, the type the tool was defined with.
+    pub fn arguments<T>(&self) -> Result<T, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a>,
+    {
+        parse_structured(&self.function.arguments)
+    }
+}
+
+/// The reply to a request with tools: text, calls of the tools, or both.
+///
+/// To continue after calling the tools in an agent loop, send the conversation again with
+/// the reply as [`ToolResponse::message`] followed by a [`Message::tool_result`] for each
+/// call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolResponse {
+    pub content: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl ToolResponse {
+    /// The reply as an assistant message for the history, with its tool calls.
+    pub fn message(&self) -> Message {
+        Message::assistant_tool_calls(
+            self.content.clone().unwrap_or_default(),
+            self.tool_calls.clone(),
+        )
+    }
+
+    /// Parse the arguments of every call of `tool` into `T`, in the order of the calls.
+    pub fn calls<T>(&self, tool: &ToolDef) -> Result<Vec<T>, LlmError>
+    where
+        T: for<'a> serde::Deserialize<'a>,
+    {
+        self.tool_calls
+            .iter()
+            .filter(|call| call.function.name == tool.name())
+            .map(ToolCall::arguments)
+            .collect()
+    }
+}
+
+/// Query OpenAI with `tools` the model may call, according to `tool_choice`. Set
+/// `parallel_tool_calls` to `Some(false)` to get at most one call per reply. Parse the
+/// arguments of the calls with [`ToolResponse::calls`].
+pub async fn query_openai_tools(
+    messages: Vec<Message>,
+    tools: Vec<ToolDef>,
+    tool_choice: ToolChoice,
+    parallel_tool_calls: Option<bool>,
+) -> Result<ToolResponse, LlmError> {
+    LlmClient::default()
+        .query_tools(messages, tools, tool_choice, parallel_tool_calls)
+        .await
+}
+
+impl LlmClient {
+    /// Query with tools the model may call, like [`query_openai_tools`].
+    pub async fn query_tools(
+        &self,
+        messages: Vec<Message>,
+        tools: Vec<ToolDef>,
+        tool_choice: ToolChoice,
+        parallel_tool_calls: Option<bool>,
+    ) -> Result<ToolResponse, LlmError> {
+        let query = tools_query(
+            self.config.model.clone(),
+            messages,
+            tools,
+            tool_choice,
+            parallel_tool_calls,
+        );
+        let (response, _) = run_query(self, query).await?;
+        tool_response(response)
+    }
+}
+
+/// The text and tool calls of the first choice of `response`.
+fn tool_response(response: OpenAIChatCompletionResponse) -> Result<ToolResponse, LlmError> {
+    let choice = response
+        .choices
+        .into_iter()
+        .next()
+        .ok_or(LlmError::EmptyChoices)?;
+    // The arguments of the last call are cut off.
+    if choice.finish_reason.as_deref() == Some("length") {
+        return Err(LlmError::Truncated);
+    }
+    let message = choice.message;
+    if let Some(refusal) = message.refusal {
+        return Err(LlmError::Refusal(refusal));
+    }
+    Ok(ToolResponse {
+        content: message.content,
+        tool_calls: message.tool_calls,
+    })
+}
+
+fn tools_query(
+    model: String,
+    messages: Vec<Message>,
+    tools: Vec<ToolDef>,
+    tool_choice: ToolChoice,
+    parallel_tool_calls: Option<bool>,
+) -> OpenAIChatCompletionQuery {
+    let mut query = OpenAIChatCompletionQuery::text(model, messages);
+    query.tools = tools;
+    query.tool_choice = Some(tool_choice);
+    query.parallel_tool_calls = parallel_tool_calls;
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct GetWeather {
+        city: String,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Search {
+        query: String,
+    }
+
+    #[test]
+    fn test_tools_query() {
+        let weather = ToolDef::new::<GetWeather>()
+            .unwrap()
+            .description("Get the current weather in a city");
+        let search = ToolDef::named::<Search>("search").unwrap();
+        let query = tools_query(
+            "gpt-4o".to_string(),
+            vec![Message::user("Weather in Oslo and Bergen?")],
+            vec![weather.clone(), search],
+            ToolChoice::Function("search".to_string()),
+            Some(false),
+        );
+        let body = serde_json::to_value(&query).unwrap();
+
+        let schema = crate::get_schema::<GetWeather>();
+        assert_eq!(
+            body["tools"][0],
+            serde_json::json!({
+                "type": "function",
+                "function": {
+                    "name": schema.name(),
+                    "description": "Get the current weather in a city",
+                    "parameters": schema.json_schema(),
+                    "strict": true,
+                },
+            })
+        );
+        assert_eq!(body["tools"][1]["function"]["name"], "search");
+        assert_eq!(
+            body["tool_choice"],
+            serde_json::json!({ "type": "function", "function": { "name": "search" } })
+        );
+        assert_eq!(body["parallel_tool_calls"], false);
+        assert_eq!(
+            serde_json::to_value(ToolChoice::Required).unwrap(),
+            "required"
+        );
+
+        let plain = serde_json::to_value(OpenAIChatCompletionQuery::text(
+            "gpt-4o".to_string(),
+            Vec::new(),
+        ))
+        .unwrap();
+        assert!(plain.get("tools").is_none());
+        assert!(plain.get("tool_choice").is_none());
+
+        assert!(matches!(
+            ToolDef::named::<GetWeather>("get weather"),
+            Err(LlmError::InvalidSchemaName(_))
+        ));
+        // Function arguments must be an object.
+        assert!(matches!(
+            ToolDef::named::<Vec<String>>("search"),
+            Err(LlmError::InvalidParameter { name: "tools", .. })
+        ));
+        assert!(ToolDef::new::<u32>().is_err());
+
+        let response: OpenAIChatCompletionResponse = serde_json::from_value(serde_json::json!({
+            "choices": [{
+                "message": {
+                    "content": null,
+                    "tool_calls": [
+                        { "id": "call_1", "type": "function", "function": {
+                            "name": weather.name(), "arguments": r#"{"city": "Oslo"}"#,
+                        } },
+                        { "id": "call_2", "type": "function", "function": {
+                            "name": "search", "arguments": r#"{"query": "rain"}"#,
+                        } },
+                        { "id": "call_3", "type": "function", "function": {
+                            "name": weather.name(), "arguments": r#"{"city": "Bergen"}"#,
+                        } },
+                    ],
+                },
+            }],
+        }))
+        .unwrap();
+        let message = &response.choices[0].message;
+        let response = ToolResponse {
+            content: message.content.clone(),
+            tool_calls: message.tool_calls.clone(),
+        };
+        let cities: Vec<GetWeather> = response.calls(&weather).unwrap();
+        assert_eq!(
+            cities,
+            [
+                GetWeather {
+                    city: "Oslo".to_string()
+                },
+                GetWeather {
+                    city: "Bergen".to_string()
+                },
+            ]
+        );
+        assert_eq!(response.tool_calls[1].id, "call_2");
+    }
+}
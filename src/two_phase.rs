@@ -0,0 +1,136 @@
+use std::borrow::Cow;
+
+use crate::{
+    LlmClient, LlmError, Message, OpenAIChatCompletionQuery, parse_structured, query_openai_inner,
+    run_query, try_get_schema,
+};
+
+/// The answer of a two-phase query: the model's free-form reasoning and the structured
+/// result extracted from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TwoPhase<T> {
+    pub reasoning: String,
+    pub value: T,
+}
+
+/// The instructions for each phase of [`query_openai_two_phase_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwoPhasePrompts {
+    reasoning: Cow<'static, str>,
+    extraction: Cow<'static, str>,
+}
+
+impl Default for TwoPhasePrompts {
+    fn default() -> Self {
+        Self {
+            reasoning: Cow::Borrowed(
+                "Think the request through step by step in plain text before giving your \
+                    final answer.",
+            ),
+            extraction: Cow::Borrowed(
+                "Give the final answer from your reasoning above in the required format.",
+            ),
+        }
+    }
+}
+
+impl TwoPhasePrompts {
+    /// Prompt the first phase with `prompt`, sent as a developer message before the
+    /// conversation.
+    pub fn reasoning(mut self, prompt: impl Into<String>) -> Self {
+        self.reasoning = Cow::Owned(prompt.into());
+        self
+    }
+
+    /// Prompt the second phase with `prompt`, sent as a user message after the reasoning.
+    pub fn extraction(mut self, prompt: impl Into<String>) -> Self {
+        self.extraction = Cow::Owned(prompt.into());
+        self
+    }
+}
+
+/// Let the model reason freely about `messages` first, then extract `T` from its own
+/// reasoning in a second, structured request. This often gives more accurate results than
+/// asking for structured output directly, at the cost of an extra request.
+pub async fn query_openai_two_phase<T>(messages: Vec<Message>) -> Result<TwoPhase<T>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    query_openai_two_phase_with(messages, &TwoPhasePrompts::default()).await
+}
+
+/// Query OpenAI like [`query_openai_two_phase`] with custom prompts for each phase.
+pub async fn query_openai_two_phase_with<T>(
+    messages: Vec<Message>,
+    prompts: &TwoPhasePrompts,
+) -> Result<TwoPhase<T>, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+{
+    let schema = try_get_schema::<T>()?;
+
+    let client = LlmClient::default();
+    let query = OpenAIChatCompletionQuery::text(
+        client.config.model.clone(),
+        reasoning_messages(&messages, prompts),
+    );
+    let (response, _) = run_query(&client, query).await?;
+    let reasoning = response.content()?.to_string();
+
+    let response =
+        query_openai_inner(extraction_messages(messages, &reasoning, prompts), schema).await?;
+    Ok(TwoPhase {
+        value: parse_structured(response.content()?)?,
+        reasoning,
+    })
+}
+
+fn reasoning_messages(messages: &[Message], prompts: &TwoPhasePrompts) -> Vec<Message> {
+    let prompt = Message::developer(prompts.reasoning.to_string());
+    std::iter::once(prompt)
+        .chain(messages.iter().cloned())
+        .collect()
+}
+
+fn extraction_messages(
+    mut messages: Vec<Message>,
+    reasoning: &str,
+    prompts: &TwoPhasePrompts,
+) -> Vec<Message> {
+    messages.push(Message::assistant(reasoning));
+    messages.push(Message::user(prompts.extraction.to_string()));
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+
+    #[test]
+    fn test_two_phase_messages() {
+        let messages = vec![Message::user(
+            "How many legs do three spiders have?".to_string(),
+        )];
+        let prompts = TwoPhasePrompts::default().reasoning("Reason carefully.");
+
+        let reasoning = reasoning_messages(&messages, &prompts);
+        assert_eq!(reasoning.len(), 2);
+        assert!(matches!(reasoning[0].role, Role::Developer));
+        assert_eq!(reasoning[0].content, "Reason carefully.");
+        assert_eq!(reasoning[1].content, messages[0].content);
+
+        let extraction = extraction_messages(messages, "Each spider has 8 legs, so 24.", &prompts);
+        assert_eq!(extraction.len(), 3);
+        assert!(matches!(extraction[1].role, Role::Assistant));
+        assert_eq!(extraction[1].content, "Each spider has 8 legs, so 24.");
+        assert!(matches!(extraction[2].role, Role::User));
+        assert_eq!(extraction[2].content, TwoPhasePrompts::default().extraction);
+
+        let query = OpenAIChatCompletionQuery::text("gpt-4o".to_string(), reasoning);
+        assert_eq!(
+            serde_json::to_value(&query).unwrap()["response_format"],
+            serde_json::json!({ "type": "text" })
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use crate::{
+    CompletionRequest, LlmClient, LlmError, Message, Provider, QueryParams, repair, try_get_schema,
+};
+
+/// Query OpenAI and check the parsed response against business rules the schema cannot
+/// express, such as a number being within a range or two fields agreeing with each other.
+///
+/// When `validate` rejects a response, the model is shown its previous answer together with
+/// the reason and asked to correct it, up to `retries` times. The last rejection is returned
+/// as an error once the retries are exhausted.
+pub async fn query_openai_validated<T, F>(
+    messages: Vec<Message>,
+    validate: F,
+    retries: usize,
+) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    F: Fn(&T) -> Result<(), String>,
+{
+    query_validated_with_provider(&LlmClient::default(), messages, validate, retries).await
+}
+
+/// Query `provider` and check the parsed response like [`query_openai_validated`].
+pub async fn query_validated_with_provider<T, P, F>(
+    provider: &P,
+    mut messages: Vec<Message>,
+    validate: F,
+    retries: usize,
+) -> Result<T, LlmError>
+where
+    T: for<'a> serde::Deserialize<'a> + schemars::JsonSchema + 'static,
+    P: Provider,
+    F: Fn(&T) -> Result<(), String>,
+{
+    let schema = try_get_schema::<T>()?;
+
+    let mut attempt = 0;
+    loop {
+        let request = CompletionRequest {
+            messages: messages.clone(),
+            schema: schema.clone(),
+            params: QueryParams::default(),
+        };
+        let (parsed, content): (T, String) = repair::complete_and_parse(provider, request).await?;
+
+        let reason = match validate(&parsed) {
+            Ok(()) => return Ok(parsed),
+            Err(reason) => reason,
+        };
+        if attempt == retries {
+            return Err(LlmError::ValidationFailed(reason));
+        }
+        attempt += 1;
+
+        messages.push(Message::assistant(content));
+        messages.push(Message::user(format!(
+            "The previous response was rejected: {reason}\nReply again with the problem fixed."
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MockProvider, Role};
+
+    #[derive(Debug, PartialEq, serde::Deserialize, schemars::JsonSchema)]
+    struct Rating {
+        flair: f64,
+    }
+
+    fn in_range(rating: &Rating) -> Result<(), String> {
+        if (0.0..=1.0).contains(&rating.flair) {
+            Ok(())
+        } else {
+            Err("Flair must be between 0 and 1".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validated_retries() {
+        // Out of range first, then corrected.
+        let provider = MockProvider::new(|request| {
+            Ok(match request.messages.len() {
+                1 => r#"{"flair": 7.5}"#,
+                _ => r#"{"flair": 0.75}"#,
+            }
+            .to_string())
+        });
+        let rating: Rating = query_validated_with_provider(
+            &provider,
+            vec![Message::user("Rate the flair of this text.")],
+            in_range,
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(rating, Rating { flair: 0.75 });
+
+        let requests = provider.requests();
+        assert_eq!(requests.len(), 2);
+        let correction = &requests[1].messages;
+        assert_eq!(correction.len(), 3);
+        assert!(matches!(correction[1].role, Role::Assistant));
+        assert_eq!(correction[1].content, r#"{"flair": 7.5}"#);
+        assert!(matches!(correction[2].role, Role::User));
+        assert!(
+            correction[2]
+                .content
+                .contains("rejected: Flair must be between 0 and 1")
+        );
+
+        // The last rejection is returned once the retries are used up.
+        let provider = MockProvider::fixed(r#"{"flair": 7.5}"#);
+        let error =
+            query_validated_with_provider::<Rating, _, _>(&provider, Vec::new(), in_range, 1)
+                .await
+                .unwrap_err();
+        assert!(
+            matches!(error, LlmError::ValidationFailed(reason) if reason == "Flair must be between 0 and 1")
+        );
+        assert_eq!(provider.requests().len(), 2);
+    }
+}